@@ -0,0 +1,498 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use yaml_rust::parser::{Event, MarkedEventReceiver, Parser};
+use yaml_rust::scanner::Marker;
+
+use super::validator::{Issue, Segment};
+
+/// 1-based line and column of a position inside a .runer file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+/// A problem found in a .runer file, located in its source whenever it is
+/// possible to do so.
+///
+/// It renders in a compiler like fashion: the file path, line and column,
+/// the offending yaml line with a caret under the problematic token, and an
+/// optional "did you mean" hint.
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    pub file: String,
+    pub message: String,
+    pub position: Option<Position>,
+    pub help: Option<String>,
+    snippet: Option<(String, usize)>,
+}
+
+impl Diagnostic {
+    /// Creates a Diagnostic that is not bound to any position in the file.
+    pub fn new(file: &str, message: impl Into<String>) -> Self {
+        Self {
+            file: file.to_owned(),
+            message: message.into(),
+            position: None,
+            help: None,
+            snippet: None,
+        }
+    }
+
+    /// Converts a deserialization error into a Diagnostic. Unknown field and
+    /// unknown variant errors get a suggestion if the misspelled key is close
+    /// enough to one of the expected ones.
+    pub fn from_yaml_error(file: &str, source: &str, error: &serde_yaml::Error) -> Self {
+        let mut message = error.to_string();
+        let mut diagnostic = Self::new(file, "");
+        if let Some(location) = error.location() {
+            let suffix = format!(" at line {} column {}", location.line(), location.column());
+            if let Some(stripped) = message.strip_suffix(&suffix) {
+                message = stripped.to_owned();
+            }
+            let position = Position {
+                line: location.line(),
+                column: location.column(),
+            };
+            let unknown = unknown_and_expected(&message).map(|(unknown, _)| unknown);
+            let width = unknown.map(|u| u.len()).unwrap_or(1);
+            diagnostic = diagnostic.at(source, position, width);
+        }
+        if let Some((unknown, expected)) = unknown_and_expected(&message) {
+            diagnostic.help = did_you_mean(unknown, expected).map(suggestion);
+        }
+        diagnostic.message = message;
+        diagnostic
+    }
+
+    /// Converts a validation Issue into a Diagnostic, locating its path in
+    /// the given SourceMap.
+    pub fn from_issue(file: &str, source: &str, map: &SourceMap, issue: &Issue) -> Self {
+        let mut diagnostic = Self::new(file, issue.to_string());
+        diagnostic.help = issue.help.clone();
+        if let Some((position, width)) = map.locate(&issue.path) {
+            diagnostic = diagnostic.at(source, position, width);
+        }
+        diagnostic
+    }
+
+    fn at(mut self, source: &str, position: Position, width: usize) -> Self {
+        self.position = Some(position);
+        self.snippet = source
+            .lines()
+            .nth(position.line.saturating_sub(1))
+            .map(|line| (line.to_owned(), width.max(1)));
+        self
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "error: {}", self.message)?;
+        let position = match self.position {
+            Some(position) => position,
+            None => return write!(f, " --> {}", self.file),
+        };
+        let gutter = " ".repeat(position.line.to_string().len());
+        write!(
+            f,
+            "{gutter}--> {}:{}:{}",
+            self.file, position.line, position.column
+        )?;
+        if let Some((line, width)) = &self.snippet {
+            write!(f, "\n{gutter} |")?;
+            write!(f, "\n{} | {line}", position.line)?;
+            write!(
+                f,
+                "\n{gutter} | {}{}",
+                " ".repeat(position.column.saturating_sub(1)),
+                "^".repeat(*width)
+            )?;
+        }
+        if let Some(help) = &self.help {
+            write!(f, "\n{gutter} = help: {help}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Every problem that was found in a .runer file. This is the error type
+/// that is returned when a Rune fails to be extracted or validated.
+#[derive(Debug)]
+pub struct Diagnostics(pub Vec<Diagnostic>);
+
+impl fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (idx, diagnostic) in self.0.iter().enumerate() {
+            if idx > 0 {
+                writeln!(f)?;
+                writeln!(f)?;
+            }
+            write!(f, "{diagnostic}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Diagnostics {}
+
+impl From<Diagnostic> for Diagnostics {
+    fn from(diagnostic: Diagnostic) -> Self {
+        Self(vec![diagnostic])
+    }
+}
+
+/// Position of a yaml node, and of its key if the node is a mapping value.
+#[derive(Clone, Copy, Debug)]
+struct Span {
+    key: Option<(Position, usize)>,
+    value: Position,
+}
+
+/// Maps the path of every node of a yaml document to its position in the
+/// source, so that validation issues can point back to the file.
+#[derive(Default)]
+pub struct SourceMap {
+    spans: HashMap<Vec<Segment>, Span>,
+}
+
+impl SourceMap {
+    /// Builds the SourceMap of the given yaml source. A source that can't be
+    /// parsed results in an empty map.
+    pub fn build(source: &str) -> Self {
+        let mut builder = SourceMapBuilder::default();
+        if Parser::new(source.chars())
+            .load(&mut builder, false)
+            .is_err()
+        {
+            return Self::default();
+        }
+        Self {
+            spans: builder.spans,
+        }
+    }
+
+    /// Finds the position of the given path. If the path itself doesn't
+    /// exist in the source (e.g. an omitted field) the closest existing
+    /// ancestor is used.
+    fn locate(&self, path: &[Segment]) -> Option<(Position, usize)> {
+        (1..=path.len()).rev().find_map(|len| {
+            self.spans
+                .get(&path[..len])
+                .map(|span| span.key.unwrap_or((span.value, 1)))
+        })
+    }
+}
+
+enum Frame {
+    Mapping { key: Option<(String, Position)> },
+    Sequence { next: usize },
+}
+
+#[derive(Default)]
+struct SourceMapBuilder {
+    path: Vec<Segment>,
+    frames: Vec<Frame>,
+    spans: HashMap<Vec<Segment>, Span>,
+}
+
+impl SourceMapBuilder {
+    /// Registers a node that starts at the given marker. Returns false if the
+    /// node is a mapping key rather than a value.
+    fn enter_node(&mut self, event: &Event, position: Position) -> bool {
+        let (segment, key) = match self.frames.last_mut() {
+            None => {
+                self.spans.insert(
+                    Vec::new(),
+                    Span {
+                        key: None,
+                        value: position,
+                    },
+                );
+                return true;
+            }
+            Some(Frame::Mapping { key }) => match key.take() {
+                Some((name, key_position)) => (
+                    Segment::Key(name.clone()),
+                    Some((key_position, name.chars().count())),
+                ),
+                None => {
+                    // Only scalar keys can be referenced by a path. Complex
+                    // keys are still consumed so that the parity holds.
+                    let name = match event {
+                        Event::Scalar(name, ..) => name.clone(),
+                        _ => String::new(),
+                    };
+                    *key = Some((name, position));
+                    return false;
+                }
+            },
+            Some(Frame::Sequence { next }) => {
+                *next += 1;
+                (Segment::Index(*next - 1), None)
+            }
+        };
+        self.path.push(segment);
+        self.spans.insert(
+            self.path.clone(),
+            Span {
+                key,
+                value: position,
+            },
+        );
+        true
+    }
+}
+
+impl MarkedEventReceiver for SourceMapBuilder {
+    fn on_event(&mut self, event: Event, mark: Marker) {
+        let position = Position {
+            line: mark.line(),
+            column: mark.col() + 1,
+        };
+        match event {
+            Event::Scalar(..) | Event::Alias(_) => {
+                let is_value = self.enter_node(&event, position);
+                if is_value && !self.frames.is_empty() {
+                    self.path.pop();
+                }
+            }
+            Event::MappingStart(_) => {
+                let is_value = self.enter_node(&event, position);
+                if !is_value {
+                    // Complex keys are not tracked, their content is skipped
+                    // by treating them as an anonymous sequence.
+                    self.path.push(Segment::Key(String::new()));
+                }
+                self.frames.push(Frame::Mapping { key: None });
+            }
+            Event::SequenceStart(_) => {
+                let is_value = self.enter_node(&event, position);
+                if !is_value {
+                    self.path.push(Segment::Key(String::new()));
+                }
+                self.frames.push(Frame::Sequence { next: 0 });
+            }
+            Event::MappingEnd | Event::SequenceEnd => {
+                self.frames.pop();
+                if !self.frames.is_empty() {
+                    self.path.pop();
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Extracts the misspelled token and the expected tokens out of serde's
+/// "unknown field/variant `x`, expected one of `a`, `b`" messages.
+fn unknown_and_expected(message: &str) -> Option<(&str, Vec<&str>)> {
+    if !message.contains("unknown field") && !message.contains("unknown variant") {
+        return None;
+    }
+    let mut quoted = message.split('`').skip(1).step_by(2);
+    let unknown = quoted.next()?;
+    Some((unknown, quoted.collect()))
+}
+
+/// Returns the candidate that is the closest to the given word, if it is
+/// close enough to be considered a misspelling of it.
+pub fn did_you_mean<'a>(
+    word: &str,
+    candidates: impl IntoIterator<Item = &'a str>,
+) -> Option<&'a str> {
+    let threshold = (word.chars().count() / 3).max(1);
+    candidates
+        .into_iter()
+        .map(|candidate| (edit_distance(word, candidate), candidate))
+        .filter(|(distance, _)| *distance <= threshold)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+/// The help text that suggests the given candidate, quoted like serde quotes
+/// the tokens of its messages.
+pub fn suggestion(candidate: &str) -> String {
+    format!("did you mean `{candidate}`?")
+}
+
+/// Levenshtein distance between the two given strings.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::model::runer::Rune;
+
+    use super::*;
+
+    const SOURCE: &str = "\
+blueprints:
+    api:
+        container:
+            name: api
+            image: me/api
+flows:
+    - name: stack
+      tasks:
+          - { id: 1, type: Blueprint, name: api, job: container }
+";
+
+    fn key(name: &str) -> Segment {
+        Segment::Key(name.to_owned())
+    }
+
+    #[test]
+    fn paths_are_located_at_their_key() {
+        let map = SourceMap::build(SOURCE);
+        let image = [
+            key("blueprints"),
+            key("api"),
+            key("container"),
+            key("image"),
+        ];
+        assert_eq!(
+            map.locate(&image),
+            Some((
+                Position {
+                    line: 5,
+                    column: 13
+                },
+                5
+            ))
+        );
+        let task = [
+            key("flows"),
+            Segment::Index(0),
+            key("tasks"),
+            Segment::Index(0),
+        ];
+        assert_eq!(
+            map.locate(&task),
+            Some((
+                Position {
+                    line: 9,
+                    column: 13
+                },
+                1
+            ))
+        );
+        let job = [
+            key("flows"),
+            Segment::Index(0),
+            key("tasks"),
+            Segment::Index(0),
+            key("job"),
+        ];
+        assert_eq!(
+            map.locate(&job),
+            Some((
+                Position {
+                    line: 9,
+                    column: 50
+                },
+                3
+            ))
+        );
+    }
+
+    #[test]
+    fn omitted_paths_are_located_at_their_closest_ancestor() {
+        let map = SourceMap::build(SOURCE);
+        let hc = [
+            key("blueprints"),
+            key("api"),
+            key("container"),
+            key("hc"),
+            key("tcp"),
+        ];
+        assert_eq!(map.locate(&hc), Some((Position { line: 3, column: 9 }, 9)));
+        assert_eq!(SourceMap::build("a: [").locate(&[key("a")]), None);
+    }
+
+    #[test]
+    fn diagnostics_point_at_the_problem() {
+        let issue = Issue {
+            path: vec![
+                key("flows"),
+                Segment::Index(0),
+                key("tasks"),
+                Segment::Index(0),
+                key("job"),
+            ],
+            message: "task 1 runs the 'container' job".to_owned(),
+            help: Some(suggestion("shell")),
+        };
+        let map = SourceMap::build(SOURCE);
+        let diagnostic = Diagnostic::from_issue("app.runer", SOURCE, &map, &issue);
+        assert_eq!(
+            diagnostic.to_string(),
+            "\
+error: flows[0].tasks[0].job: task 1 runs the 'container' job
+ --> app.runer:9:50
+  |
+9 |           - { id: 1, type: Blueprint, name: api, job: container }
+  |                                                  ^^^
+  = help: did you mean `shell`?"
+        );
+    }
+
+    #[test]
+    fn the_gutter_fits_the_line_number() {
+        let source = format!("{}flows: 1\n", "\n".repeat(11));
+        let diagnostic = Diagnostic::new("app.runer", "flows is not a list").at(
+            &source,
+            Position {
+                line: 12,
+                column: 8,
+            },
+            1,
+        );
+        assert_eq!(
+            diagnostic.to_string(),
+            "\
+error: flows is not a list
+  --> app.runer:12:8
+   |
+12 | flows: 1
+   |        ^"
+        );
+        assert_eq!(
+            Diagnostic::new("app.runer", "Not a .runer file").to_string(),
+            "error: Not a .runer file\n --> app.runer"
+        );
+    }
+
+    #[test]
+    fn yaml_errors_suggest_the_closest_expected_key() {
+        let source = SOURCE.replace("image:", "imag:");
+        let error = serde_yaml::from_str::<Rune>(&source).unwrap_err();
+        let diagnostic = Diagnostic::from_yaml_error("app.runer", &source, &error);
+        assert!(diagnostic
+            .message
+            .starts_with("blueprints.api.container: unknown field `imag`, expected one of"));
+        assert_eq!(
+            diagnostic.position,
+            Some(Position {
+                line: 5,
+                column: 13
+            })
+        );
+        assert_eq!(diagnostic.help.as_deref(), Some("did you mean `image`?"));
+        assert!(diagnostic.to_string().ends_with(
+            "5 |             imag: me/api\n  |             ^^^^\n  = help: did you mean `image`?"
+        ));
+    }
+}
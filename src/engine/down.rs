@@ -0,0 +1,325 @@
+use std::cmp::Reverse;
+
+use anyhow::Result;
+use chrono::DateTime;
+use log::info;
+
+use super::run_state::{ContainerRecord, FlowRun};
+use super::runtime::network_in_use;
+use super::state::State;
+
+/// What [down_flow] removes besides the containers and networks of the Flow.
+///
+/// Networks have no opt-in: only the ones that the run created are removed,
+/// never the ones that existed before it, and they are kept while other
+/// containers use them. The run is forgotten once it is torn down, so a
+/// network left behind would have no owner anymore, and the next run would
+/// find it existing and not record it as its own either.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DownOptions {
+    /// Also remove the named volumes that the containers mount.
+    pub volumes: bool,
+    /// Also remove the images that the Flow builds.
+    pub images: bool,
+}
+
+/// Tears down what the latest run of the Flow with the given name started,
+/// as recorded in the state file: its containers get stopped and removed,
+/// the last started first, then the networks that the run created get
+/// removed, and optionally the volumes of the Flow and the images the run
+/// built. Everything is removed with the runtime that created it. The run is
+/// forgotten afterwards.
+///
+/// Keeps going if something can't be removed. Returns a line per resource
+/// that describes what happened to it, networks that containers of other
+/// Flows still use are kept.
+pub async fn down_flow(
+    flow_name: &str,
+    state: &State,
+    options: DownOptions,
+) -> Result<Vec<String>> {
+    let flow = state.find_flow(flow_name)?;
+    let Some(state_file) = &state.state_file else {
+        return Ok(Vec::new());
+    };
+    let run_state = state_file.load().await?;
+    let Some(run) = run_state.flows.get(&flow.name) else {
+        return Ok(vec![format!("flow '{}' has not been run", flow.name)]);
+    };
+    let runtimes = state.runtimes.as_ref().unwrap();
+    let mut lines = Vec::new();
+
+    for container in launched_containers(run) {
+        let name = &container.name;
+        let runtime = match runtimes.named(&container.runtime) {
+            Ok(runtime) => runtime,
+            Err(e) => {
+                lines.push(format!("skipped container {name}: {e}"));
+                continue;
+            }
+        };
+        let info = match runtime.inspect(name).await {
+            Ok(info) => info,
+            Err(e) => {
+                lines.push(format!("skipped container {name}: {e}"));
+                continue;
+            }
+        };
+        if info.running {
+            info!("Stopping {name} with {}", runtime.name());
+            if let Err(e) = runtime.stop(name).await {
+                lines.push(format!("kept container {name}: {e}"));
+                continue;
+            }
+        }
+        match runtime.remove(name).await {
+            Ok(()) => lines.push(format!("removed container {name}")),
+            Err(e) => lines.push(format!("kept container {name}: {e}")),
+        }
+    }
+
+    for network in &run.networks {
+        let name = &network.name;
+        let result = match runtimes.named(&network.runtime) {
+            Ok(runtime) => runtime.remove_network(name).await,
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(()) => lines.push(format!("removed network {name}")),
+            Err(e) if network_in_use(&e) => lines.push(format!(
+                "kept network {name}, other containers still use it"
+            )),
+            Err(e) => lines.push(format!("kept network {name}: {e}")),
+        }
+    }
+
+    if options.volumes {
+        for (runtime, name, _) in state.flow_volumes(flow) {
+            match runtime.remove_volume(&name).await {
+                Ok(()) => lines.push(format!("removed volume {name}")),
+                Err(e) => lines.push(format!("kept volume {name}: {e}")),
+            }
+        }
+    }
+
+    if options.images {
+        for image in run.tasks.values().filter_map(|task| task.image.as_ref()) {
+            let result = match runtimes.named(&image.runtime) {
+                Ok(runtime) => runtime.remove_image(&image.tag).await,
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(()) => lines.push(format!("removed image {}", image.tag)),
+                Err(e) => lines.push(format!("kept image {}: {e}", image.tag)),
+            }
+        }
+    }
+
+    state_file.remove_flow(&flow.name).await;
+    Ok(lines)
+}
+
+/// Containers that the given run launched, the last started first. A Task
+/// only starts once the Tasks it depends on are finished, so this is the
+/// reverse of their dependency order.
+fn launched_containers(run: &FlowRun) -> Vec<&ContainerRecord> {
+    let mut launched: Vec<_> = run
+        .tasks
+        .values()
+        .filter_map(|task| {
+            let started_at = task
+                .started_at
+                .as_deref()
+                .and_then(|timestamp| DateTime::parse_from_rfc3339(timestamp).ok());
+            Some((started_at, task.container.as_ref()?))
+        })
+        .collect();
+    launched.sort_by_key(|(started_at, _)| Reverse(*started_at));
+    launched
+        .into_iter()
+        .map(|(_, container)| container)
+        .collect()
+}
+
+/// Stops the containers that the latest run of the Flow with the given name
+/// launched, as recorded in the state file, the last started first and with
+/// the runtime that launched them. Unlike [down_flow] it leaves them in
+/// place, so that they can be inspected or torn down later.
+///
+/// Returns a line per container that describes what happened to it.
+pub async fn stop_launched(flow_name: &str, state: &State) -> Result<Vec<String>> {
+    let flow = state.find_flow(flow_name)?;
+    let Some(state_file) = &state.state_file else {
+        return Ok(Vec::new());
+    };
+    let run_state = state_file.load().await?;
+    let Some(run) = run_state.flows.get(&flow.name) else {
+        return Ok(Vec::new());
+    };
+    let runtimes = state.runtimes.as_ref().unwrap();
+    let mut lines = Vec::new();
+
+    for container in launched_containers(run) {
+        let name = &container.name;
+        let runtime = match runtimes.named(&container.runtime) {
+            Ok(runtime) => runtime,
+            Err(e) => {
+                lines.push(format!("kept container {name}: {e}"));
+                continue;
+            }
+        };
+        info!("Stopping {name} with {}", runtime.name());
+        match runtime.stop(name).await {
+            Ok(()) => lines.push(format!("stopped container {name}")),
+            Err(e) => lines.push(format!("kept container {name}: {e}")),
+        }
+    }
+    Ok(lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use crate::model::runer::{Container, Network, Rune};
+
+    use super::super::executor::execute_flow;
+    use super::super::run_state::StateFile;
+    use super::super::runtime::fake::{FakeRuntime, Operation};
+    use super::super::runtime::{ContainerRuntime, Runtimes};
+    use super::*;
+
+    /// `db` and `cache` start right away, `api` needs both and `web` needs
+    /// `api`, whose image is built first.
+    const RUNE: &str = r#"
+blueprints:
+    db:
+        container:
+            name: db
+            image: postgres
+            networks: [backend]
+            volumes: [[pgdata, /var/lib/postgresql/data]]
+    cache:
+        container: { name: cache, image: redis }
+    api:
+        image: { context: ., tag: me/api }
+        container: { name: api, image: me/api }
+    web:
+        container: { name: web, image: me/web, networks: [frontend] }
+flows:
+    - name: stack
+      tasks:
+          - { id: 1, type: Blueprint, name: db, job: container }
+          - { id: 2, type: Blueprint, name: cache, job: container }
+          - { id: 3, type: Blueprint, name: api, job: image }
+          - { id: 4, type: Blueprint, name: api, job: container, depends: [1, 2, 3] }
+          - { id: 5, type: Blueprint, name: web, job: container, depends: 4 }
+"#;
+
+    /// State of the Rune run with the given runtime, with the state file in
+    /// a directory of its own.
+    fn run_stack(runtime: &Arc<FakeRuntime>) -> State {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let dir = std::env::temp_dir().join(format!(
+            "runer-down-{}-{}",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("stack.runer");
+        std::fs::write(&file, RUNE).unwrap();
+        let rune: Rune = serde_yaml::from_str(RUNE).unwrap();
+        let state = State {
+            runtimes: Some(Arc::new(Runtimes::single(runtime.clone()))),
+            ..State::from_rune(rune).with_state_file(StateFile::for_rune(&file).unwrap())
+        };
+        smol::block_on(execute_flow("stack".to_owned(), state.clone())).unwrap();
+        state
+    }
+
+    fn down(state: &State, options: DownOptions) -> Vec<String> {
+        smol::block_on(down_flow("stack", state, options)).unwrap()
+    }
+
+    /// Position of the given operation in the recorded ones.
+    fn position(operations: &[Operation], operation: Operation) -> usize {
+        operations
+            .iter()
+            .position(|o| *o == operation)
+            .unwrap_or_else(|| panic!("{operation} wasn't recorded in {operations:?}"))
+    }
+
+    #[test]
+    fn containers_are_torn_down_in_reverse_dependency_order() {
+        let runtime = Arc::new(FakeRuntime::default());
+        let state = run_stack(&runtime);
+        let started = runtime.operations().len();
+        down(&state, DownOptions::default());
+
+        let operations = &runtime.operations()[started..];
+        let stop = |name: &str| position(operations, Operation::Stop(name.to_owned()));
+        let remove = |name: &str| position(operations, Operation::Remove(name.to_owned()));
+        assert!(stop("web") < stop("api"));
+        assert!(remove("web") < stop("api"));
+        assert!(stop("api") < stop("db"));
+        assert!(stop("api") < stop("cache"));
+        assert!(remove("api") < stop("db"));
+        assert!(remove("api") < stop("cache"));
+        // Networks go once no container of the Flow is left on them
+        let remove_network =
+            |name: &str| position(operations, Operation::RemoveNetwork(name.to_owned()));
+        assert!(remove("db") < remove_network("backend"));
+        assert!(remove("web") < remove_network("frontend"));
+    }
+
+    #[test]
+    fn only_networks_the_run_created_are_removed() {
+        let runtime = Arc::new(FakeRuntime::default());
+        smol::block_on(runtime.create_network("frontend", &Network::default())).unwrap();
+        let state = run_stack(&runtime);
+
+        let lines = down(&state, DownOptions::default());
+        assert!(lines.contains(&"removed network backend".to_owned()));
+        let operations = runtime.operations();
+        assert!(!operations.contains(&Operation::RemoveNetwork("frontend".to_owned())));
+        // The run is forgotten once it is torn down
+        assert_eq!(
+            down(&state, DownOptions::default()),
+            ["flow 'stack' has not been run"]
+        );
+    }
+
+    #[test]
+    fn networks_in_use_are_kept() {
+        let runtime = Arc::new(FakeRuntime::default());
+        let state = run_stack(&runtime);
+        let other: Container =
+            serde_yaml::from_str("{ name: other, image: busybox, networks: [backend] }").unwrap();
+        smol::block_on(runtime.run(&other)).unwrap();
+
+        let lines = down(&state, DownOptions::default());
+        assert!(lines.contains(&"kept network backend, other containers still use it".to_owned()));
+        assert!(lines.contains(&"removed network frontend".to_owned()));
+    }
+
+    #[test]
+    fn volumes_and_images_are_only_removed_on_request() {
+        let runtime = Arc::new(FakeRuntime::default());
+        let state = run_stack(&runtime);
+        down(&state, DownOptions::default());
+        let operations = runtime.operations();
+        assert!(!operations.contains(&Operation::RemoveVolume("pgdata".to_owned())));
+        assert!(!operations.contains(&Operation::RemoveImage("me/api".to_owned())));
+
+        let runtime = Arc::new(FakeRuntime::default());
+        let state = run_stack(&runtime);
+        let options = DownOptions {
+            volumes: true,
+            images: true,
+        };
+        let lines = down(&state, options);
+        assert!(lines.contains(&"removed volume pgdata".to_owned()));
+        assert!(lines.contains(&"removed image me/api".to_owned()));
+    }
+}
@@ -0,0 +1,131 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+
+use crate::model::runer::{JobType, Task, TaskType};
+
+use super::graph::TaskGraph;
+use super::invocation::{quote, Invocation};
+use super::job::{image_post_invocations, image_pre_invocations, shell_invocation};
+use super::readiness::{describe_host_check, ready_condition, ready_timeout, shell_timeout};
+use super::state::State;
+
+/// Describes what executing the given Flow would do, without running
+/// anything.
+///
+/// Tasks are listed in dependency order, each with the exact command lines
+/// that its job would spawn.
+pub fn describe_flow(flow_name: &str, state: &State) -> Result<String> {
+    let flow = state.find_flow(flow_name)?;
+    let graph = TaskGraph::from_flow(flow)?;
+    let tasks: HashMap<u32, &Task> = flow.tasks.iter().map(|t| (t.id, t)).collect();
+
+    let mut out = format!("Flow '{}'\n", flow.name);
+
+    if let Some(pkg_dependencies) = &flow.pkg_dependencies {
+        out.push_str("\n  Package dependency checks\n");
+        for d in pkg_dependencies {
+            let check = Invocation::shell(&format!("command -v {d}"));
+            out.push_str(&format!("    $ {check}\n"));
+        }
+    }
+
+    let networks = state.flow_networks(flow);
+    if !networks.is_empty() {
+        out.push_str("\n  Networks (created unless they exist)\n");
+        for (runtime, name, network) in &networks {
+            out.push_str(&format!(
+                "    {}\n",
+                runtime.describe_network(name, network)
+            ));
+        }
+    }
+
+    let volumes = state.flow_volumes(flow);
+    if !volumes.is_empty() {
+        out.push_str("\n  Volumes (created unless they exist)\n");
+        for (runtime, name, volume) in &volumes {
+            out.push_str(&format!("    {}\n", runtime.describe_volume(name, volume)));
+        }
+    }
+
+    for id in graph.topological_order() {
+        let task = tasks[&id];
+        out.push_str(&format!(
+            "\n  Task {} {} [{}/{}]",
+            task.id, task.name, task.typ, task.job
+        ));
+        let parents = graph.parents(id);
+        if !parents.is_empty() {
+            let parents: Vec<String> = parents.iter().map(u32::to_string).collect();
+            out.push_str(&format!(" after {}", parents.join(", ")));
+        }
+        out.push('\n');
+        for line in describe_task(task, state) {
+            out.push_str(&format!("    {line}\n"));
+        }
+    }
+
+    Ok(out)
+}
+
+/// Command lines that the job of the given Task would spawn. _set_ jobs
+/// don't spawn anything, they are shown as the variables they would export.
+fn describe_task(task: &Task, state: &State) -> Vec<String> {
+    let commands = |invocations: Vec<Invocation>| -> Vec<String> {
+        invocations
+            .iter()
+            .map(|invocation| format!("$ {invocation}"))
+            .collect()
+    };
+    match task.typ {
+        TaskType::Blueprint => {
+            let blueprint = &state.blueprints.as_ref().unwrap()[&task.name];
+            let runtime = state.runtimes.as_ref().unwrap().for_blueprint(&task.name);
+            match task.job {
+                JobType::Image => {
+                    let image = blueprint.image.as_ref().unwrap();
+                    let mut lines = commands(image_pre_invocations(image));
+                    lines.extend(runtime.describe_build(image).lines().map(str::to_owned));
+                    lines.extend(commands(image_post_invocations(image)));
+                    lines
+                }
+                JobType::Container => {
+                    let container = blueprint.container.as_ref().unwrap();
+                    let mut lines: Vec<_> = runtime
+                        .describe_run(container)
+                        .lines()
+                        .map(str::to_owned)
+                        .collect();
+                    lines.push(format!(
+                        "(waits until {} is {}, timeout {:?})",
+                        container.name,
+                        ready_condition(container),
+                        ready_timeout(container)
+                    ));
+                    if let Some(check) = container.hc.as_ref().and_then(describe_host_check) {
+                        lines.push(format!("(health check on the host) {check}"));
+                    }
+                    lines
+                }
+                JobType::Shell => {
+                    let shell = blueprint.shell.as_ref().unwrap();
+                    let mut lines = commands(vec![shell_invocation(shell)]);
+                    if let Some(check) = shell.hc.as_ref().and_then(describe_host_check) {
+                        lines.push(format!(
+                            "(keeps running, waits until healthy, timeout {:?})",
+                            shell_timeout(shell)
+                        ));
+                        lines.push(format!("(health check on the host) {check}"));
+                    }
+                    lines
+                }
+                JobType::Set => vec![],
+            }
+        }
+        TaskType::Env => state.env.as_ref().unwrap()[&task.name]
+            .iter()
+            .map(|(key, value)| format!("export {}", quote(&format!("{key}={value}"))))
+            .collect(),
+    }
+}
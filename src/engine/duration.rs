@@ -0,0 +1,19 @@
+use std::time::Duration;
+
+/// Parses a Docker style duration, a whole number followed by its unit
+/// (`ns`, `us`, `ms`, `s`, `m` or `h`), e.g. `500ms`, `10s` or `1m`.
+pub fn parse_duration(duration: &str) -> Option<Duration> {
+    let duration = duration.trim();
+    let split = duration.find(|c: char| !c.is_ascii_digit())?;
+    let (amount, unit) = duration.split_at(split);
+    let amount: u64 = amount.parse().ok()?;
+    Some(match unit {
+        "ns" => Duration::from_nanos(amount),
+        "us" => Duration::from_micros(amount),
+        "ms" => Duration::from_millis(amount),
+        "s" => Duration::from_secs(amount),
+        "m" => Duration::from_secs(amount * 60),
+        "h" => Duration::from_secs(amount * 3600),
+        _ => return None,
+    })
+}
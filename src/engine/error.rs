@@ -0,0 +1,265 @@
+use std::fmt;
+
+use async_signal::Signal;
+
+use super::diagnostic::Diagnostics;
+use super::scheduler::TaskOutcome;
+use super::shutdown::signal_name;
+
+/// Category of an error, which decides the exit code of runer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// Anything that isn't covered by the other kinds
+    Other,
+    Parse,
+    Validation,
+    /// A Task failed on its own, e.g. its commands exited with a non-success
+    /// code or its container became unhealthy
+    Task,
+    Timeout,
+    Spawn,
+    Runtime,
+    /// runer was interrupted by the signal with the given number
+    Interrupted(i32),
+}
+
+impl ErrorKind {
+    /// Exit code of runer for errors of this kind. 2 is left out, it is
+    /// what clap exits with on invalid command line arguments.
+    pub fn exit_code(self) -> i32 {
+        match self {
+            ErrorKind::Other => 1,
+            ErrorKind::Parse => 3,
+            ErrorKind::Validation => 4,
+            ErrorKind::Task => 5,
+            ErrorKind::Timeout => 6,
+            ErrorKind::Spawn => 7,
+            ErrorKind::Runtime => 8,
+            // Like a shell reports a command killed by a signal
+            ErrorKind::Interrupted(signal) => 128 + signal,
+        }
+    }
+
+    /// The kind shared by all the given kinds, [ErrorKind::Task] if they
+    /// differ.
+    fn common(kinds: impl IntoIterator<Item = ErrorKind>) -> ErrorKind {
+        let mut kinds = kinds.into_iter();
+        let first = kinds.next().unwrap_or(ErrorKind::Task);
+        if kinds.all(|kind| kind == first) {
+            first
+        } else {
+            ErrorKind::Task
+        }
+    }
+}
+
+/// Errors that runer tells apart when it exits.
+///
+/// They travel inside anyhow errors like any other error, either as the
+/// error itself or as the context of another one, and get looked up by
+/// [error_kind].
+#[derive(Debug)]
+pub enum RunerError {
+    /// The .runer file can't be read or isn't a valid Rune
+    Parse(Diagnostics),
+    /// The Rune doesn't pass the semantic validation
+    Validation(Diagnostics),
+    /// The program with the given name can't be spawned on the host
+    Spawn(String),
+    Timeout(String),
+    /// The container runtime with the given name failed to do something
+    Runtime(String),
+    /// The given number of .runer files failed the validation, the worst of
+    /// them with the given kind of error
+    InvalidFiles(ErrorKind, usize),
+    /// Tasks of the given Flows failed
+    TasksFailed(Vec<FlowSummary>),
+    Interrupted(Signal),
+}
+
+impl RunerError {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            RunerError::Parse(_) => ErrorKind::Parse,
+            RunerError::Validation(_) => ErrorKind::Validation,
+            RunerError::Spawn(_) => ErrorKind::Spawn,
+            RunerError::Timeout(_) => ErrorKind::Timeout,
+            RunerError::Runtime(_) => ErrorKind::Runtime,
+            RunerError::InvalidFiles(kind, _) => *kind,
+            RunerError::TasksFailed(flows) => {
+                ErrorKind::common(flows.iter().flat_map(FlowSummary::failure_kinds))
+            }
+            RunerError::Interrupted(signal) => ErrorKind::Interrupted(*signal as i32),
+        }
+    }
+}
+
+impl fmt::Display for RunerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RunerError::Parse(diagnostics) | RunerError::Validation(diagnostics) => {
+                write!(f, "{diagnostics}")
+            }
+            RunerError::Spawn(program) => write!(f, "Failed to spawn {program}"),
+            RunerError::Timeout(message) => write!(f, "{message}"),
+            RunerError::Runtime(runtime) => write!(f, "{runtime} failed"),
+            RunerError::InvalidFiles(_, count) => write!(f, "{count} .runer file(s) are invalid"),
+            RunerError::TasksFailed(flows) => {
+                for (idx, flow) in flows.iter().enumerate() {
+                    if idx > 0 {
+                        writeln!(f)?;
+                    }
+                    write!(f, "{flow}")?;
+                }
+                Ok(())
+            }
+            RunerError::Interrupted(signal) => write!(f, "Interrupted by {}", signal_name(*signal)),
+        }
+    }
+}
+
+impl std::error::Error for RunerError {}
+
+/// Kind of the given error, according to the outermost [RunerError] it
+/// carries.
+pub fn error_kind(error: &anyhow::Error) -> ErrorKind {
+    error
+        .downcast_ref::<RunerError>()
+        .map_or(ErrorKind::Other, RunerError::kind)
+}
+
+/// Outcome of every Task of a Flow.
+#[derive(Debug)]
+pub struct FlowSummary {
+    pub flow: String,
+    /// ID, name and outcome of the Tasks, in their declaration order
+    pub tasks: Vec<(u32, String, TaskOutcome)>,
+}
+
+impl FlowSummary {
+    fn failure_kinds(&self) -> impl Iterator<Item = ErrorKind> + '_ {
+        self.tasks
+            .iter()
+            .filter_map(|(_, _, outcome)| match outcome {
+                TaskOutcome::Failed { kind, .. } => Some(*kind),
+                _ => None,
+            })
+    }
+}
+
+/// Renders a line per Task that didn't succeed, and a single line for the
+/// ones that did.
+impl fmt::Display for FlowSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let count =
+            |pred: fn(&TaskOutcome) -> bool| self.tasks.iter().filter(|(_, _, o)| pred(o)).count();
+        write!(
+            f,
+            "Flow '{}': {} succeeded, {} failed, {} skipped",
+            self.flow,
+            count(|o| matches!(o, TaskOutcome::Succeeded)),
+            count(|o| matches!(o, TaskOutcome::Failed { .. })),
+            count(|o| matches!(o, TaskOutcome::Skipped(_))),
+        )?;
+        let succeeded: Vec<String> = self
+            .tasks
+            .iter()
+            .filter(|(_, _, outcome)| matches!(outcome, TaskOutcome::Succeeded))
+            .map(|(id, name, _)| format!("{id} ({name})"))
+            .collect();
+        if !succeeded.is_empty() {
+            write!(f, "\n  succeeded: {}", succeeded.join(", "))?;
+        }
+        for (id, name, outcome) in &self.tasks {
+            if !matches!(outcome, TaskOutcome::Succeeded) {
+                write!(f, "\n  task {id} ({name}) {outcome}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::{anyhow, Context};
+
+    use super::*;
+
+    fn failed(kind: ErrorKind) -> TaskOutcome {
+        TaskOutcome::Failed {
+            reason: "failed".to_owned(),
+            kind,
+        }
+    }
+
+    fn tasks_failed(kinds: &[ErrorKind]) -> RunerError {
+        let tasks = kinds
+            .iter()
+            .enumerate()
+            .map(|(idx, kind)| (idx as u32 + 1, format!("task{idx}"), failed(*kind)))
+            .chain([(9, "ok".to_owned(), TaskOutcome::Succeeded)])
+            .collect();
+        RunerError::TasksFailed(vec![FlowSummary {
+            flow: "stack".to_owned(),
+            tasks,
+        }])
+    }
+
+    #[test]
+    fn exit_codes_follow_the_outermost_runer_error() {
+        let io = || std::io::Error::other("connection refused");
+        let cases: Vec<(anyhow::Error, i32)> = vec![
+            (anyhow!("boom"), 1),
+            (anyhow!("boom").context("while running"), 1),
+            (
+                anyhow!(RunerError::Parse(Diagnostics(Vec::new()))).context("Can't load .runer"),
+                3,
+            ),
+            (anyhow!(RunerError::Validation(Diagnostics(Vec::new()))), 4),
+            (
+                anyhow!(RunerError::InvalidFiles(ErrorKind::Validation, 2)),
+                4,
+            ),
+            (anyhow!(RunerError::InvalidFiles(ErrorKind::Parse, 1)), 3),
+            (anyhow!(tasks_failed(&[ErrorKind::Task])), 5),
+            (
+                anyhow!(tasks_failed(&[ErrorKind::Timeout, ErrorKind::Timeout])),
+                6,
+            ),
+            // Tasks that failed differently are the Tasks' own failure
+            (
+                anyhow!(tasks_failed(&[ErrorKind::Timeout, ErrorKind::Spawn])),
+                5,
+            ),
+            (
+                anyhow!(io()).context(RunerError::Timeout("db didn't become ready".to_owned())),
+                6,
+            ),
+            (
+                Err::<(), _>(io())
+                    .context(RunerError::Spawn("npm".to_owned()))
+                    .context("task 3 failed")
+                    .unwrap_err(),
+                7,
+            ),
+            (
+                anyhow!(io()).context(RunerError::Runtime("docker".to_owned())),
+                8,
+            ),
+            // The outermost one wins
+            (
+                anyhow!(RunerError::Spawn("docker".to_owned()))
+                    .context(RunerError::Runtime("docker".to_owned())),
+                8,
+            ),
+            (anyhow!(RunerError::Interrupted(Signal::Int)), 130),
+            (
+                anyhow!(RunerError::Interrupted(Signal::Term)).context("while tearing down"),
+                143,
+            ),
+        ];
+        for (error, code) in cases {
+            assert_eq!(error_kind(&error).exit_code(), code, "{error:#}");
+        }
+    }
+}
@@ -1,96 +1,239 @@
-use anyhow::{anyhow, Result};
-use log::info;
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Context, Result};
+use async_signal::Signal;
+use log::{error, info, warn};
 use smol::channel;
 use smol::process::{Child, Command, Stdio};
 
+use crate::model::runer::Task;
+
+use super::down::stop_launched;
+use super::error::{FlowSummary, RunerError};
+use super::graph::TaskGraph;
+use super::output::stop_following;
+use super::run_state::NetworkRecord;
+use super::scheduler::{Scheduler, TaskOutcome};
+use super::shutdown::{
+    force_exit, interrupted, listen, signal_name, stop_processes, ShutdownOptions,
+};
 use super::state::State;
 use super::task::run_task;
 
-/// Executes a single Flow residing in the Application State.
-/// Right now it takes an index and with it, it checks the Vec<Flow>.
-pub async fn execute_flow(flow_idx: usize, state: State) -> Result<()> {
-    if let Some(flows) = state.flows {
-        if let Some(flow) = flows.get(flow_idx) {
-            // Package dependencies are prioritized first in a Flow.
-            // If the application fails to find a dependency throws an error.
-            // Flow shouldn't continue if there is a missing dependency.
-            if let Some(pkg_dependencies) = &flow.pkg_dependencies {
-                check_package_dependencies(&pkg_dependencies).await?;
+/// Executes the Flows with the given names one after another, or all at
+/// once if _parallel_ is set.
+///
+/// The logs of the started containers are followed until every Flow is
+/// finished.
+///
+/// If runer gets interrupted by SIGINT or SIGTERM, no further Task is
+/// started, and the running ones are shut down according to the given
+/// options. Another signal during the shutdown makes runer exit right away.
+pub async fn execute_flows(
+    flow_names: Vec<String>,
+    state: State,
+    parallel: bool,
+    shutdown: ShutdownOptions,
+) -> Result<()> {
+    let mut signals = listen()?;
+    let outcome = smol::future::or(
+        async { Ok(execute_all(flow_names.clone(), state.clone(), parallel).await) },
+        async { Err(interrupted(&mut signals).await) },
+    )
+    .await;
+    let result = match outcome {
+        Ok(result) => result,
+        Err(signal) => {
+            warn!(
+                "Interrupted by {}, stopping the running tasks. Interrupt again to exit immediately",
+                signal_name(signal)
+            );
+            smol::future::or(
+                shut_down(signal, &flow_names, &state, shutdown),
+                force_exit(&mut signals),
+            )
+            .await;
+            Err(RunerError::Interrupted(signal).into())
+        }
+    };
+    stop_following();
+    result
+}
+
+/// Forwards the given signal to the processes that the Tasks spawned, and
+/// stops the containers the Flows launched if requested.
+async fn shut_down(signal: Signal, flow_names: &[String], state: &State, options: ShutdownOptions) {
+    stop_processes(signal, options.grace_period).await;
+    if options.stop_containers {
+        // Flows are stopped in the reverse order they are run
+        for name in flow_names.iter().rev() {
+            match stop_launched(name, state).await {
+                Ok(lines) => lines.iter().for_each(|line| info!("{line}")),
+                Err(e) => error!("Can't stop the containers of flow '{name}': {e:#}"),
             }
+        }
+    }
+}
 
-            // This mpsc channel is used to collect the child process handles
-            // that are generated by asynchronously spawned tasks in the
-            // upcomming <task> loop.
-            let (tx, rx) = channel::bounded::<Option<(u32, Child)>>(flow.tasks.len());
-
-            // TODO: Handle this situation beforehand with some validation.
-            // There is no point to allow the application to get executed this
-            // far.
-            if !flow.tasks.is_empty() {
-                for task in &flow.tasks {
-                    smol::spawn(run_task(
-                        // Care **clone** calls.
-                        tx.clone(),
-                        task.clone(),
-                        state.handles.as_ref().unwrap().clone(),
-                        state.blueprints.as_ref().unwrap().clone(),
-                        state.env.as_ref().unwrap().clone(),
-                    ))
-                    .detach();
-                }
+/// Every Flow is run even if Tasks of another one fail, and the failures
+/// of all of them are reported together. Any other error stops the run.
+async fn execute_all(flow_names: Vec<String>, state: State, parallel: bool) -> Result<()> {
+    let mut failed = Vec::new();
+    let mut collect = |result: Result<()>| match result {
+        Err(e) if matches!(e.downcast_ref(), Some(RunerError::TasksFailed(_))) => {
+            if let Ok(RunerError::TasksFailed(summaries)) = e.downcast() {
+                failed.extend(summaries);
+            }
+            Ok(())
+        }
+        result => result,
+    };
+    if parallel {
+        let handles: Vec<_> = flow_names
+            .into_iter()
+            .map(|name| smol::spawn(execute_flow(name, state.clone())))
+            .collect();
+        for handle in handles {
+            collect(handle.await)?;
+        }
+    } else {
+        for name in flow_names {
+            collect(execute_flow(name, state.clone()).await)?;
+        }
+    }
+    if !failed.is_empty() {
+        return Err(RunerError::TasksFailed(failed).into());
+    }
+    Ok(())
+}
 
-                // This for loop responsible for collecting the child process
-                // handles that are sent by the mpsc channel declared before.
-                // The collected handles are stored in the Application State,
-                // so that they can be accessible by the other spawned tasks
-                // and manage their ordering. (in terms of execution order)
-                //
-                // MENTAL NOTE: This code assumes that each Task sends exactly
-                // one message through the channel. If a Task fails to send
-                // its message, OR there happens to be a Task implemented to
-                // not sent a message (wrong implementation) this loop would
-                // hang which causes the entire application to hang.
-                for _ in 0..flow.tasks.len() {
-                    match rx.recv().await {
-                        Ok(p) => {
-                            if let Some((task_id, handle)) = p {
-                                let mut handles = state.handles.as_ref().unwrap().lock().await;
-                                handles.insert(task_id, handle);
-                            }
-                        }
-                        Err(_) => return Err(anyhow!("CHANNEL ERROR")),
-                    }
-                }
+/// Executes a single Flow residing in the Application State, looked up by
+/// its name.
+///
+/// Returns [RunerError::TasksFailed] with the outcome of every Task if any
+/// of them failed.
+pub async fn execute_flow(flow_name: String, state: State) -> Result<()> {
+    // The Rune is validated before the Application State gets built, so a
+    // Flow reaching this point is guaranteed to have at least one Task and
+    // every Task references an existing Fragment.
+    let flow = state.find_flow(&flow_name)?;
 
-                // After each Task gets spawned and their handles are stored,
-                // the application makes sure that each of them get exited.
-                //
-                // TODO: If for some reason a spawned Task not gets exited with
-                // success status, the application panics which is not
-                // convenient. The application should shutdown gracefully.
-                // Maybe even a Rollback can be implemented.
-                let mut handles = state.handles.as_ref().unwrap().lock().await;
-                for p in handles.iter_mut() {
-                    match p.1.try_status() {
-                        Ok(Some(status)) => info!("Task {} exited with: {status}", p.0),
-                        Ok(None) => match p.1.status().await {
-                            Ok(status) => info!("Task {} exited with: {status}", p.0),
-                            Err(e) => panic!("Task {} exited with error: {e}", p.0),
-                        },
-                        Err(e) => panic!("error attempting to wait: {e}"),
-                    }
-                }
-            } else {
-                return Err(anyhow!("Flow should have at least one task."));
+    // Package dependencies are prioritized first in a Flow.
+    // If the application fails to find a dependency throws an error.
+    // Flow shouldn't continue if there is a missing dependency.
+    if let Some(pkg_dependencies) = &flow.pkg_dependencies {
+        check_package_dependencies(pkg_dependencies).await?;
+    }
+
+    let graph = TaskGraph::from_flow(flow)?;
+    let state_file = state.state_file.clone();
+    if let Some(state_file) = &state_file {
+        state_file.begin_flow(flow).await;
+    }
+
+    // Networks and volumes are created up front, so that containers started
+    // in parallel don't race each other to create the ones they share.
+    for (runtime, name, network) in state.flow_networks(flow) {
+        let created = runtime
+            .create_network(&name, &network)
+            .await
+            .with_context(|| RunerError::Runtime(runtime.name().to_owned()))?;
+        if created {
+            info!("Created network {name} with {}", runtime.name());
+            if let Some(state_file) = &state_file {
+                let record = NetworkRecord {
+                    name,
+                    runtime: runtime.name().to_owned(),
+                };
+                state_file.network_created(&flow.name, record).await;
             }
-        } else {
-            return Err(anyhow!("No flow found for given idx."));
         }
-    } else {
-        // TODO: Handle this situation beforehand with some validation.
-        // There is no point to allow the application to get executed this
-        // far.
-        return Err(anyhow!("Application state has no flow to run."));
+    }
+    for (runtime, name, volume) in state.flow_volumes(flow) {
+        let created = runtime
+            .create_volume(&name, &volume)
+            .await
+            .with_context(|| RunerError::Runtime(runtime.name().to_owned()))?;
+        if created {
+            info!("Created volume {name} with {}", runtime.name());
+        }
+    }
+
+    let mut scheduler = Scheduler::new(&graph);
+    let tasks: HashMap<u32, &Task> = flow.tasks.iter().map(|t| (t.id, t)).collect();
+
+    // Every spawned Task notifies the executor exactly once through this
+    // mpsc channel, when it is finished. The executor sleeps on the channel
+    // in between, no Task ever waits for its parents on its own.
+    let (tx, rx) = channel::unbounded::<(u32, TaskOutcome)>();
+
+    let spawn = |id: u32| {
+        smol::spawn(run_task(
+            // Care **clone** calls.
+            tx.clone(),
+            flow.name.clone(),
+            tasks[&id].clone(),
+            state.clone(),
+        ))
+        .detach();
+    };
+
+    let mut running = 0;
+    for id in scheduler.initial() {
+        spawn(id);
+        running += 1;
+    }
+
+    // The loop ends when there is no running Task left. Tasks that are
+    // neither started nor skipped at that point can't exist, since every
+    // Task either gets unlocked by its parents or skipped by their failure.
+    while running > 0 {
+        let (task_id, outcome) = rx.recv().await.map_err(|_| anyhow!("CHANNEL ERROR"))?;
+        running -= 1;
+        match &outcome {
+            TaskOutcome::Succeeded => info!("Task {task_id} {outcome}"),
+            _ => error!("Task {task_id} {outcome}"),
+        }
+        if let Some(state_file) = &state_file {
+            state_file
+                .task_finished(&flow.name, task_id, &outcome)
+                .await;
+        }
+        let (ready, skipped) = scheduler.complete(task_id, outcome);
+        for id in skipped {
+            let outcome = scheduler.outcome(id).unwrap();
+            warn!("Task {id} {outcome}");
+            if let Some(state_file) = &state_file {
+                state_file.task_finished(&flow.name, id, outcome).await;
+            }
+        }
+        for id in ready {
+            spawn(id);
+            running += 1;
+        }
+    }
+
+    if let Some(state_file) = &state_file {
+        state_file.finish_flow(&flow.name).await;
+    }
+    let (succeeded, failed, skipped) = scheduler.summary();
+    info!(
+        "Flow '{}' finished: {succeeded} succeeded, {failed} failed, {skipped} skipped",
+        flow.name
+    );
+    if failed > 0 {
+        let summary = FlowSummary {
+            flow: flow.name.clone(),
+            tasks: flow
+                .tasks
+                .iter()
+                .filter_map(|task| {
+                    let outcome = scheduler.outcome(task.id)?.clone();
+                    Some((task.id, task.name.clone(), outcome))
+                })
+                .collect(),
+        };
+        return Err(RunerError::TasksFailed(vec![summary]).into());
     }
     Ok(())
 }
@@ -112,7 +255,8 @@ pub async fn check_package_dependencies(deps: &Vec<String>) -> Result<()> {
                 .arg("-c")
                 .arg(format!("command -v {}", d.clone()))
                 .stdout(Stdio::null())
-                .spawn()?,
+                .spawn()
+                .context(RunerError::Spawn("sh".to_owned()))?,
         ))
         .await?;
     }
@@ -128,3 +272,105 @@ pub async fn check_package_dependencies(deps: &Vec<String>) -> Result<()> {
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use crate::model::runer::Rune;
+
+    use super::super::error::RunerError;
+    use super::super::run_state::StateFile;
+    use super::super::runtime::fake::{FakeRuntime, Operation};
+    use super::super::runtime::Runtimes;
+    use super::super::scheduler::TaskOutcome;
+    use super::super::state::State;
+    use super::execute_flow;
+
+    /// `db` and `cache` start right away, `api` needs both and `web` needs
+    /// `api`.
+    const RUNE: &str = r#"
+blueprints:
+    db:
+        container: { name: db, image: postgres, networks: [backend] }
+    cache:
+        container: { name: cache, image: redis }
+    api:
+        container: { name: api, image: me/api }
+    web:
+        container: { name: web, image: me/web, networks: [frontend] }
+flows:
+    - name: stack
+      tasks:
+          - { id: 1, type: Blueprint, name: db, job: container }
+          - { id: 2, type: Blueprint, name: cache, job: container }
+          - { id: 3, type: Blueprint, name: api, job: container, depends: [1, 2] }
+          - { id: 4, type: Blueprint, name: web, job: container, depends: 3 }
+"#;
+
+    /// State of the Rune, with the state file in a directory of its own.
+    fn state(runtime: &Arc<FakeRuntime>) -> State {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let dir = std::env::temp_dir().join(format!(
+            "runer-executor-{}-{}",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("stack.runer");
+        std::fs::write(&file, RUNE).unwrap();
+        let rune: Rune = serde_yaml::from_str(RUNE).unwrap();
+        State {
+            runtimes: Some(Arc::new(Runtimes::single(runtime.clone()))),
+            ..State::from_rune(rune).with_state_file(StateFile::for_rune(&file).unwrap())
+        }
+    }
+
+    /// Position of the given operation in the recorded ones.
+    fn position(operations: &[Operation], operation: Operation) -> usize {
+        operations
+            .iter()
+            .position(|o| *o == operation)
+            .unwrap_or_else(|| panic!("{operation} wasn't recorded in {operations:?}"))
+    }
+
+    fn run(name: &str) -> Operation {
+        Operation::Run(name.to_owned())
+    }
+
+    #[test]
+    fn tasks_start_after_their_parents() {
+        let runtime = Arc::new(FakeRuntime::default());
+        smol::block_on(execute_flow("stack".to_owned(), state(&runtime))).unwrap();
+
+        let operations = runtime.operations();
+        let api = position(&operations, run("api"));
+        assert!(position(&operations, run("db")) < api);
+        assert!(position(&operations, run("cache")) < api);
+        assert!(api < position(&operations, run("web")));
+    }
+
+    #[test]
+    fn dependents_of_a_failed_task_are_skipped() {
+        let runtime = Arc::new(FakeRuntime::default().fail("run", "cache"));
+        let error = smol::block_on(execute_flow("stack".to_owned(), state(&runtime))).unwrap_err();
+
+        let Some(RunerError::TasksFailed(flows)) = error.downcast_ref() else {
+            panic!("unexpected error: {error:#}");
+        };
+        let outcomes: Vec<(u32, &TaskOutcome)> = flows[0]
+            .tasks
+            .iter()
+            .map(|(id, _, outcome)| (*id, outcome))
+            .collect();
+        assert!(matches!(outcomes[0], (1, TaskOutcome::Succeeded)));
+        assert!(matches!(outcomes[1], (2, TaskOutcome::Failed { .. })));
+        assert!(matches!(outcomes[2], (3, TaskOutcome::Skipped(2))));
+        assert!(matches!(outcomes[3], (4, TaskOutcome::Skipped(2))));
+
+        let operations = runtime.operations();
+        assert!(!operations.contains(&run("api")));
+        assert!(!operations.contains(&run("web")));
+    }
+}
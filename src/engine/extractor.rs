@@ -1,30 +1,73 @@
+use std::path::Path;
+
 use crate::model::runer::Rune;
-use anyhow::{anyhow, Result};
-use config::{Config, File, FileFormat};
+use anyhow::Result;
 use log::info;
 
+use super::diagnostic::{Diagnostic, Diagnostics, SourceMap};
+use super::error::RunerError;
+use super::validator::validate_rune;
+
 /// It expects a string literal that should correspond to a filename with
 /// .runer extension.
 ///
 /// * Returns error if the given file is not a .runer file.
 /// * Returns error if it can't deserialize the given file into a valid
-/// Rune struct.
+///   Rune struct.
+///
+/// Errors are returned as [RunerError::Parse] with [Diagnostics] which carry
+/// the file path, the line and the column of the problem.
+///
+/// Relative paths in the Rune are resolved against the directory of the
+/// file.
 ///
 /// MENTAL NOTE: .runer files are basically files written in valid yaml
-/// format. That's why funtion currently uses yaml formatter of config
-/// crate. It would be a good idea to implement a .runer specific validation
-/// on top of that.
+/// format. That's why funtion uses the yaml deserializer directly, which
+/// keeps track of the location of the errors. The .runer specific semantic
+/// validation is done by [load_rune].
 pub fn extract_rune(file: &str) -> Result<Rune> {
+    let source = read_rune_source(file).map_err(RunerError::Parse)?;
+    let mut rune = parse_rune(file, &source).map_err(RunerError::Parse)?;
+    let dir = Path::new(file)
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    rune.resolve_paths(dir);
+    Ok(rune)
+}
+
+/// Extracts the Rune from the given file and runs the semantic validation on
+/// it. All the validation issues are reported together as a
+/// [RunerError::Validation] with [Diagnostics] pointing to their location in
+/// the file.
+pub fn load_rune(file: &str) -> Result<Rune> {
+    let rune = extract_rune(file)?;
+    let issues = validate_rune(&rune);
+    if !issues.is_empty() {
+        let source = read_rune_source(file).map_err(RunerError::Parse)?;
+        let map = SourceMap::build(&source);
+        return Err(RunerError::Validation(Diagnostics(
+            issues
+                .iter()
+                .map(|issue| Diagnostic::from_issue(file, &source, &map, issue))
+                .collect(),
+        ))
+        .into());
+    }
+    Ok(rune)
+}
+
+fn read_rune_source(file: &str) -> Result<String, Diagnostics> {
     if !file.ends_with(".runer") {
-        return Err(anyhow!("Not a .runer file"));
+        return Err(Diagnostic::new(file, "Not a .runer file").into());
     }
-    let rune = Config::builder()
-        .add_source(File::new(file, FileFormat::Yaml))
-        .build()
-        .unwrap()
-        .try_deserialize::<Rune>()?;
+    std::fs::read_to_string(file)
+        .map_err(|e| Diagnostic::new(file, format!("Can't read file: {e}")).into())
+}
 
-    Ok(rune)
+fn parse_rune(file: &str, source: &str) -> Result<Rune, Diagnostics> {
+    serde_yaml::from_str::<Rune>(source)
+        .map_err(|e| Diagnostic::from_yaml_error(file, source, &e).into())
 }
 
 /// Utility function to see the details and metrics about the given Rune.
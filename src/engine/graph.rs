@@ -0,0 +1,320 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+
+use crate::model::runer::{Flow, Task};
+
+/// Dependency graph of the Tasks of a single Flow.
+///
+/// Nodes are Task IDs and an edge goes from a parent Task to each Task that
+/// _depends_ on it. Task IDs are kept in their declaration order, so that
+/// the Tasks which become ready at the same time are started in the order
+/// they are written in the .runer file.
+pub struct TaskGraph {
+    ids: Vec<u32>,
+    parents: HashMap<u32, Vec<u32>>,
+    children: HashMap<u32, Vec<u32>>,
+}
+
+impl TaskGraph {
+    /// Builds the graph of the given Flow.
+    ///
+    /// Returns error if the Tasks form a dependency cycle. Dependencies to
+    /// nonexistent Tasks and of a Task on itself are ignored, they are
+    /// reported by the validation.
+    pub fn from_flow(flow: &Flow) -> Result<Self> {
+        let ids: Vec<u32> = flow.tasks.iter().map(|t| t.id).collect();
+        let mut parents: HashMap<u32, Vec<u32>> = ids.iter().map(|id| (*id, vec![])).collect();
+        let mut children: HashMap<u32, Vec<u32>> = ids.iter().map(|id| (*id, vec![])).collect();
+        for task in &flow.tasks {
+            for parent in &task.depends {
+                if *parent != task.id
+                    && children.contains_key(parent)
+                    && !parents[&task.id].contains(parent)
+                {
+                    parents.get_mut(&task.id).unwrap().push(*parent);
+                    children.get_mut(parent).unwrap().push(task.id);
+                }
+            }
+        }
+        let graph = Self {
+            ids,
+            parents,
+            children,
+        };
+        if let Some(cycle) = graph.find_cycle() {
+            return Err(anyhow!("Dependency cycle: {}", format_cycle(&cycle)));
+        }
+        Ok(graph)
+    }
+
+    /// Task IDs in their declaration order.
+    pub fn ids(&self) -> &[u32] {
+        &self.ids
+    }
+
+    /// Tasks that the given Task depends on.
+    pub fn parents(&self, id: u32) -> &[u32] {
+        self.parents.get(&id).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    /// Tasks that depend on the given Task.
+    pub fn children(&self, id: u32) -> &[u32] {
+        self.children
+            .get(&id)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// Tasks that don't depend on any other Task.
+    pub fn roots(&self) -> Vec<u32> {
+        self.ids
+            .iter()
+            .filter(|id| self.parents(**id).is_empty())
+            .copied()
+            .collect()
+    }
+
+    /// Task IDs in an order in which every Task comes after all of its
+    /// parents. Among the Tasks that are ready at the same time, the
+    /// declaration order is kept.
+    pub fn topological_order(&self) -> Vec<u32> {
+        let mut pending: HashMap<u32, usize> = self
+            .ids
+            .iter()
+            .map(|id| (*id, self.parents(*id).len()))
+            .collect();
+        let mut order = Vec::with_capacity(self.ids.len());
+        while order.len() < self.ids.len() {
+            let next = self
+                .ids
+                .iter()
+                .find(|id| pending[*id] == 0 && !order.contains(*id))
+                .copied()
+                .expect("a TaskGraph has no cycles");
+            order.push(next);
+            for child in self.children(next) {
+                *pending.get_mut(child).unwrap() -= 1;
+            }
+        }
+        order
+    }
+
+    /// Finds a dependency cycle, if there is any. The returned path starts and
+    /// ends with the same Task ID, e.g. `[1, 2, 3, 1]`.
+    fn find_cycle(&self) -> Option<Vec<u32>> {
+        #[derive(Clone, Copy, PartialEq)]
+        enum Mark {
+            Unvisited,
+            InProgress,
+            Done,
+        }
+
+        fn visit(
+            graph: &TaskGraph,
+            id: u32,
+            marks: &mut HashMap<u32, Mark>,
+            stack: &mut Vec<u32>,
+        ) -> Option<Vec<u32>> {
+            marks.insert(id, Mark::InProgress);
+            stack.push(id);
+            for child in graph.children(id) {
+                match marks[child] {
+                    Mark::InProgress => {
+                        let start = stack.iter().position(|s| s == child).unwrap();
+                        let mut cycle = stack[start..].to_vec();
+                        cycle.push(*child);
+                        return Some(cycle);
+                    }
+                    Mark::Unvisited => {
+                        if let Some(cycle) = visit(graph, *child, marks, stack) {
+                            return Some(cycle);
+                        }
+                    }
+                    Mark::Done => {}
+                }
+            }
+            stack.pop();
+            marks.insert(id, Mark::Done);
+            None
+        }
+
+        let mut marks: HashMap<u32, Mark> =
+            self.ids.iter().map(|id| (*id, Mark::Unvisited)).collect();
+        for id in &self.ids {
+            if marks[id] == Mark::Unvisited {
+                if let Some(cycle) = visit(self, *id, &mut marks, &mut Vec::new()) {
+                    return Some(cycle);
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Renders the given Flow as a Graphviz DOT digraph. Each node is labeled
+/// with its Task ID, blueprint (or env) name, TaskType and JobType, and
+/// each edge goes from a parent Task to its dependent.
+pub fn to_dot(flow: &Flow) -> Result<String> {
+    let graph = TaskGraph::from_flow(flow)?;
+    let mut out = format!("digraph \"{}\" {{\n", escape(&flow.name));
+    out.push_str("    rankdir=LR;\n    node [shape=box];\n");
+    for task in &flow.tasks {
+        out.push_str(&format!(
+            "    t{} [label=\"{}\"];\n",
+            task.id,
+            node_label(task, "\\n", escape)
+        ));
+    }
+    for id in graph.ids() {
+        for child in graph.children(*id) {
+            out.push_str(&format!("    t{id} -> t{child};\n"));
+        }
+    }
+    out.push_str("}\n");
+    Ok(out)
+}
+
+/// Renders the given Flow as a Mermaid flowchart, with the same nodes and
+/// edges as [to_dot]. The name of the Flow is its title.
+pub fn to_mermaid(flow: &Flow) -> Result<String> {
+    let graph = TaskGraph::from_flow(flow)?;
+    let mut out = format!(
+        "---\ntitle: {}\n---\nflowchart LR\n",
+        yaml_string(&flow.name)
+    );
+    for task in &flow.tasks {
+        out.push_str(&format!(
+            "    t{}[\"{}\"]\n",
+            task.id,
+            node_label(task, "<br/>", |text| text.replace('"', "#quot;"))
+        ));
+    }
+    for id in graph.ids() {
+        for child in graph.children(*id) {
+            out.push_str(&format!("    t{id} --> t{child}\n"));
+        }
+    }
+    Ok(out)
+}
+
+fn node_label(task: &Task, line_break: &str, escape: impl Fn(&str) -> String) -> String {
+    format!(
+        "{}: {}{line_break}{} / {}",
+        task.id,
+        escape(&task.name),
+        task.typ,
+        task.job
+    )
+}
+
+/// Escapes the given text for a double quoted DOT string.
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Quotes the given text as a double quoted yaml string, for the front
+/// matter of a Mermaid chart.
+fn yaml_string(text: &str) -> String {
+    format!(
+        "\"{}\"",
+        escape(text).replace('\n', "\\n").replace('\r', "\\r")
+    )
+}
+
+/// Formats a cycle path as `1 -> 2 -> 3 -> 1`.
+fn format_cycle(cycle: &[u32]) -> String {
+    cycle
+        .iter()
+        .map(u32::to_string)
+        .collect::<Vec<String>>()
+        .join(" -> ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flow(tasks: &str) -> Flow {
+        serde_yaml::from_str(&format!("{{ name: stack, tasks: {tasks} }}")).unwrap()
+    }
+
+    const STACK: &str = r#"[{ id: 1, type: Blueprint, name: db, job: container },
+        { id: 2, type: Blueprint, name: "my \"api\"", job: image },
+        { id: 3, type: Blueprint, name: my "api", job: container, depends: [1, 2] }]"#;
+
+    #[test]
+    fn flows_render_as_dot() {
+        let mut flow = flow(STACK);
+        flow.name = "dev \"stack\"".to_owned();
+        assert_eq!(
+            to_dot(&flow).unwrap(),
+            r#"digraph "dev \"stack\"" {
+    rankdir=LR;
+    node [shape=box];
+    t1 [label="1: db\nBlueprint / container"];
+    t2 [label="2: my \"api\"\nBlueprint / image"];
+    t3 [label="3: my \"api\"\nBlueprint / container"];
+    t1 -> t3;
+    t2 -> t3;
+}
+"#
+        );
+    }
+
+    #[test]
+    fn flows_render_as_mermaid() {
+        let mut flow = flow(STACK);
+        flow.name = "dev: \"stack\"\nv2".to_owned();
+        assert_eq!(
+            to_mermaid(&flow).unwrap(),
+            r#"---
+title: "dev: \"stack\"\nv2"
+---
+flowchart LR
+    t1["1: db<br/>Blueprint / container"]
+    t2["2: my #quot;api#quot;<br/>Blueprint / image"]
+    t3["3: my #quot;api#quot;<br/>Blueprint / container"]
+    t1 --> t3
+    t2 --> t3
+"#
+        );
+    }
+
+    #[test]
+    fn cycles_are_reported_with_their_path() {
+        let error = TaskGraph::from_flow(&flow(
+            "[{ id: 1, type: Env, name: a, job: set, depends: 2 },
+              { id: 2, type: Env, name: b, job: set, depends: 1 }]",
+        ))
+        .err()
+        .unwrap();
+        assert_eq!(error.to_string(), "Dependency cycle: 1 -> 2 -> 1");
+    }
+
+    #[test]
+    fn self_and_unknown_dependencies_are_ignored() {
+        let graph = TaskGraph::from_flow(&flow(
+            "[{ id: 1, type: Env, name: a, job: set, depends: [1, 9] },
+              { id: 2, type: Env, name: b, job: set, depends: [1, 1] }]",
+        ))
+        .unwrap();
+        assert!(graph.parents(1).is_empty());
+        assert_eq!(graph.parents(2), [1]);
+        assert_eq!(graph.children(1), [2]);
+        assert_eq!(graph.roots(), [1]);
+    }
+
+    #[test]
+    fn topological_order_keeps_the_declaration_order() {
+        let graph = TaskGraph::from_flow(&flow(
+            "[{ id: 4, type: Env, name: d, job: set, depends: [3, 1] },
+              { id: 3, type: Env, name: c, job: set },
+              { id: 2, type: Env, name: b, job: set, depends: 3 },
+              { id: 1, type: Env, name: a, job: set }]",
+        ))
+        .unwrap();
+        assert_eq!(graph.roots(), [3, 1]);
+        assert_eq!(graph.topological_order(), [3, 2, 1, 4]);
+    }
+}
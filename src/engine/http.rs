@@ -0,0 +1,223 @@
+use anyhow::{anyhow, Context, Result};
+use smol::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+
+/// A minimal HTTP/1.1 request. Each request is sent over its own connection
+/// (`Connection: close`), which keeps the response framing simple.
+pub struct Request {
+    pub method: &'static str,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub fn new(method: &'static str, path: impl Into<String>) -> Self {
+        Self {
+            method,
+            path: path.into(),
+            headers: vec![],
+            body: vec![],
+        }
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_owned(), value.to_owned()));
+        self
+    }
+
+    pub fn json(self, body: &serde_json::Value) -> Self {
+        let mut request = self.header("Content-Type", "application/json");
+        request.body = body.to_string().into_bytes();
+        request
+    }
+
+    pub fn body(mut self, content_type: &str, body: Vec<u8>) -> Self {
+        self = self.header("Content-Type", content_type);
+        self.body = body;
+        self
+    }
+}
+
+/// Splits a plain `http://host[:port][/path]` URL into the authority to
+/// connect to, with the default port added if it is missing, and the path.
+pub fn split_url(url: &str) -> Result<(String, String)> {
+    let rest = url
+        .strip_prefix("http://")
+        .ok_or_else(|| anyhow!("unsupported URL '{url}', expected 'http://host[:port]/path'"))?;
+    let (authority, path) = match rest.find('/') {
+        Some(idx) => rest.split_at(idx),
+        None => (rest, "/"),
+    };
+    if authority.is_empty() {
+        return Err(anyhow!("URL '{url}' has no host"));
+    }
+    let has_port = authority
+        .rsplit_once(':')
+        .is_some_and(|(_, port)| !port.ends_with(']'));
+    let authority = if has_port {
+        authority.to_owned()
+    } else {
+        format!("{authority}:80")
+    };
+    Ok((authority, path.to_owned()))
+}
+
+enum Framing {
+    Length(usize),
+    Chunked { remaining: usize, done: bool },
+    Eof,
+}
+
+/// Response of a [Request], whose body can be either read at once or
+/// consumed chunk by chunk as it arrives (e.g. for streaming endpoints).
+pub struct Response<S> {
+    pub status: u16,
+    reader: BufReader<S>,
+    framing: Framing,
+}
+
+/// Sends the given Request over the given connection and reads the head of
+/// the response.
+pub async fn send<S>(mut stream: S, host: &str, request: &Request) -> Result<Response<S>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut head = format!(
+        "{} {} HTTP/1.1\r\nHost: {host}\r\nConnection: close\r\nContent-Length: {}\r\n",
+        request.method,
+        request.path,
+        request.body.len()
+    );
+    for (name, value) in &request.headers {
+        head.push_str(&format!("{name}: {value}\r\n"));
+    }
+    head.push_str("\r\n");
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(&request.body).await?;
+    stream.flush().await?;
+
+    let mut reader = BufReader::new(stream);
+    let mut status_line = String::new();
+    reader.read_line(&mut status_line).await?;
+    let status = status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse::<u16>().ok())
+        .ok_or_else(|| anyhow!("Malformed HTTP status line: {}", status_line.trim()))?;
+
+    let mut headers = Vec::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await? == 0 {
+            break;
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_ascii_lowercase(), value.trim().to_owned()));
+        }
+    }
+
+    let header = |name: &str| {
+        headers
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    };
+    let framing = if request.method == "HEAD" || status == 204 || status == 304 {
+        Framing::Length(0)
+    } else if header("transfer-encoding").is_some_and(|v| v.eq_ignore_ascii_case("chunked")) {
+        Framing::Chunked {
+            remaining: 0,
+            done: false,
+        }
+    } else if let Some(length) = header("content-length") {
+        Framing::Length(length.parse().context("Malformed Content-Length")?)
+    } else {
+        Framing::Eof
+    };
+
+    Ok(Response {
+        status,
+        reader,
+        framing,
+    })
+}
+
+impl<S: AsyncRead + Unpin> Response<S> {
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    /// Reads the next piece of the body as it arrives. Returns None once the
+    /// body is fully consumed.
+    pub async fn next_chunk(&mut self) -> Result<Option<Vec<u8>>> {
+        let mut buffer = vec![0; 8192];
+        match &mut self.framing {
+            Framing::Length(0) => Ok(None),
+            Framing::Length(remaining) => {
+                let limit = (*remaining).min(buffer.len());
+                let read = self.reader.read(&mut buffer[..limit]).await?;
+                if read == 0 {
+                    return Err(anyhow!("Connection closed before the end of the body"));
+                }
+                *remaining -= read;
+                buffer.truncate(read);
+                Ok(Some(buffer))
+            }
+            Framing::Chunked { done: true, .. } => Ok(None),
+            Framing::Chunked { remaining, done } => {
+                if *remaining == 0 {
+                    let mut size_line = String::new();
+                    self.reader.read_line(&mut size_line).await?;
+                    let size = size_line.trim().split(';').next().unwrap_or_default();
+                    *remaining = usize::from_str_radix(size, 16)
+                        .with_context(|| format!("Malformed chunk size: {size}"))?;
+                    if *remaining == 0 {
+                        *done = true;
+                        // Trailing CRLF of the last chunk
+                        let mut line = String::new();
+                        self.reader.read_line(&mut line).await?;
+                        return Ok(None);
+                    }
+                }
+                let limit = (*remaining).min(buffer.len());
+                let read = self.reader.read(&mut buffer[..limit]).await?;
+                if read == 0 {
+                    return Err(anyhow!("Connection closed in the middle of a chunk"));
+                }
+                *remaining -= read;
+                if *remaining == 0 {
+                    let mut crlf = [0; 2];
+                    self.reader.read_exact(&mut crlf).await?;
+                }
+                buffer.truncate(read);
+                Ok(Some(buffer))
+            }
+            Framing::Eof => {
+                let read = self.reader.read(&mut buffer).await?;
+                if read == 0 {
+                    return Ok(None);
+                }
+                buffer.truncate(read);
+                Ok(Some(buffer))
+            }
+        }
+    }
+
+    /// Reads the entire body as (lossy) UTF-8 text.
+    pub async fn text(self) -> Result<String> {
+        Ok(String::from_utf8_lossy(&self.bytes().await?).into_owned())
+    }
+
+    /// Reads the entire body.
+    pub async fn bytes(mut self) -> Result<Vec<u8>> {
+        let mut body = Vec::new();
+        while let Some(chunk) = self.next_chunk().await? {
+            body.extend(chunk);
+        }
+        Ok(body)
+    }
+}
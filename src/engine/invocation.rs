@@ -0,0 +1,100 @@
+use std::fmt;
+use std::os::unix::process::CommandExt;
+
+use smol::process::{Command, Stdio};
+
+/// A fully assembled command line, before it gets spawned.
+///
+/// Jobs describe the processes they run as Invocations, so that the exact
+/// same command lines can either be spawned or printed (e.g. for a dry-run).
+#[derive(Clone, Debug, Default)]
+pub struct Invocation {
+    pub program: String,
+    pub args: Vec<String>,
+    pub envs: Vec<(String, String)>,
+}
+
+impl Invocation {
+    pub fn new(program: &str) -> Self {
+        Self {
+            program: program.to_owned(),
+            ..Self::default()
+        }
+    }
+
+    /// Invocation of the given script through `sh -c`.
+    pub fn shell(script: &str) -> Self {
+        Self::new("sh").arg("-c").arg(script)
+    }
+
+    pub fn arg(mut self, arg: impl Into<String>) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.args.extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn env(mut self, key: &str, value: &str) -> Self {
+        self.envs.push((key.to_owned(), value.to_owned()));
+        self
+    }
+
+    /// Builds the Command that runs this Invocation.
+    pub fn command(&self) -> Command {
+        let mut command = Command::new(&self.program);
+        command.args(&self.args);
+        command.envs(self.envs.iter().map(|(k, v)| (k, v)));
+        command
+    }
+
+    /// Builds the Command that runs this Invocation as the leader of a new
+    /// process group, which keeps it and its children from receiving the
+    /// signals that the terminal sends to runer.
+    ///
+    /// Its stdin is closed, since a background process group that reads the
+    /// terminal gets stopped.
+    pub fn group_command(&self) -> Command {
+        let mut command = std::process::Command::new(&self.program);
+        command.args(&self.args);
+        command.envs(self.envs.iter().map(|(k, v)| (k, v)));
+        command.process_group(0);
+        let mut command = Command::from(command);
+        command.stdin(Stdio::null());
+        command
+    }
+}
+
+/// Renders the Invocation as a command line that can be pasted into a
+/// POSIX shell.
+impl fmt::Display for Invocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.envs.is_empty() {
+            write!(f, "env ")?;
+            for (key, value) in &self.envs {
+                write!(f, "{} ", quote(&format!("{key}={value}")))?;
+            }
+        }
+        write!(f, "{}", quote(&self.program))?;
+        for arg in &self.args {
+            write!(f, " {}", quote(arg))?;
+        }
+        Ok(())
+    }
+}
+
+/// Quotes the given word for a POSIX shell, if it needs to be quoted.
+pub fn quote(word: &str) -> String {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "_-@%+=:,./".contains(c);
+    if !word.is_empty() && word.chars().all(safe) {
+        word.to_owned()
+    } else {
+        format!("'{}'", word.replace('\'', "'\\''"))
+    }
+}
@@ -1,183 +1,235 @@
-use anyhow::Result;
-use log::info;
-use smol::process::{Child, Command, Stdio};
-
-use crate::model::runer::{Container, ExecutionEnvironment, Image, Shell};
-
-/// Creates a new docker image(if it doesn't exist) according to given Image.
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, Context, Result};
+use log::{info, warn};
+use smol::future::Boxed;
+
+use crate::model::runer::{Container, Image, Shell};
+
+use super::error::RunerError;
+use super::invocation::Invocation;
+use super::output::{forward_lines, LineSink};
+use super::ports::check_host_ports;
+use super::readiness::{shell_timeout, wait_until_healthy, wait_until_ready};
+use super::run_state::{ContainerRecord, ImageRecord, Launched};
+use super::runtime::ContainerRuntime;
+use super::shutdown::ProcessGroup;
+
+/// Creates a new image(if it doesn't exist) according to given Image, with
+/// the given container runtime.
 ///
-/// ---
-/// Panics if <docker build> command returns non-success code.
-pub async fn create_docker_image(docker_image: &Image) -> Result<Child, std::io::Error> {
-    info!("Starting to create docker image for {}", docker_image.tag);
+/// Its <pre> and <post> commands are run on the host, before and after the
+/// image gets built. Their output and the build output are passed to `sink`,
+/// and the built image is passed to `record`.
+pub async fn create_docker_image(
+    docker_image: &Image,
+    runtime: &dyn ContainerRuntime,
+    sink: LineSink,
+    record: &(dyn Fn(Launched) -> Boxed<()> + Sync),
+) -> Result<()> {
+    info!(
+        "Starting to create {} image for {}",
+        runtime.name(),
+        docker_image.tag
+    );
 
     // Running <pre> commands synchronously
-    if let Some(pre) = &docker_image.pre {
-        for p in pre {
-            Command::new("sh")
-                .arg("-c")
-                .arg(p.1.clone())
-                .output()
-                .await?;
-        }
-    }
-
-    let mut docker_build_command = Command::new("docker");
-    docker_build_command.arg("build");
-
-    if let Some(cmd_options) = &docker_image.options {
-        cmd_options.iter().for_each(|cmd_option| {
-            docker_build_command.arg(cmd_option);
-        })
+    for p in image_pre_invocations(docker_image) {
+        run_hook(&p, sink.clone()).await?;
     }
 
-    docker_build_command.args(["-t", &docker_image.tag]);
-
-    if let Some(build_args) = &docker_image.build_args {
-        build_args.iter().for_each(|build_arg| {
-            docker_build_command.arg(format!("--build-arg=\"{}\"", build_arg));
-        });
+    runtime.build(docker_image, sink.clone()).await?;
+    match runtime.image_id(&docker_image.tag).await {
+        Ok(id) => {
+            record(Launched::Image(ImageRecord {
+                tag: docker_image.tag.clone(),
+                id,
+                runtime: runtime.name().to_owned(),
+            }))
+            .await
+        }
+        Err(e) => warn!("Can't tell the ID of {}: {e:#}", docker_image.tag),
     }
 
-    // Running <docker build> command synchronously
-    docker_build_command
-        .arg(&docker_image.context)
-        .output()
-        .await?;
-
     // Running <post> commands synchronously
-    if let Some(post) = &docker_image.post {
-        for p in post {
-            Command::new("sh")
-                .arg("-c")
-                .arg(p.1.clone())
-                .output()
-                .await?;
-        }
+    for p in image_post_invocations(docker_image) {
+        run_hook(&p, sink.clone()).await?;
     }
 
-    Command::new("sh")
-        .current_dir(&docker_image.context)
-        .arg("-c")
-        .arg(format!(
-            "echo \"Image creation is done for {}\"",
-            &docker_image.tag
-        ))
-        .stdout(Stdio::null())
-        .spawn()
+    info!("Image creation is done for {}", docker_image.tag);
+    Ok(())
 }
 
-/// Runs a new docker container according to the given Container.
+/// Runs a <pre> or <post> command of an Image, passing its output to `sink`.
 ///
-/// ---
-/// Panics if an empty <entrypoint> command token array is provided.
-/// Panics if an empty <healthcheck> command token array is provided.
-/// Panics if <docker run> command returns non-success code.
-pub fn run_docker_container(docker_container: &Container) -> Result<Child, std::io::Error> {
-    info!("Starting {}", docker_container.name);
-    let mut docker_run_command = Command::new("docker");
-    docker_run_command.arg("run");
-    docker_run_command.arg("-d");
-    docker_run_command.args(["--name", &docker_container.name]);
-
-    if let Some(env) = &docker_container.env {
-        env.iter().for_each(|p| {
-            // docker_run_command.arg(format!("--env {}={}", p.0, p.1));
-            docker_run_command.args(["--env", &format!("{}={}", p.0, p.1)]);
-        })
-    }
-
-    if let Some(ports) = &docker_container.ports {
-        docker_run_command.args(["-p", &format!("{}:{}", ports.0, ports.1)]);
+/// Returns error if the command exits with a non-success code, which fails
+/// the Task before the build or before it counts as done.
+async fn run_hook(invocation: &Invocation, sink: LineSink) -> Result<()> {
+    let mut child = invocation
+        .group_command()
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| RunerError::Spawn(invocation.program.clone()))?;
+    let _group = ProcessGroup::register(child.id());
+    let stdout = forward_lines(child.stdout.take().unwrap(), sink.clone());
+    let stderr = forward_lines(child.stderr.take().unwrap(), sink);
+    stdout.await;
+    stderr.await;
+    let status = child.status().await?;
+    if !status.success() {
+        return Err(anyhow!("{invocation} exited with {status}"));
     }
+    Ok(())
+}
 
-    if let Some(volumes) = &docker_container.volumes {
-        volumes.iter().for_each(|v| {
-            docker_run_command.args(["-v", &format!("{}:{}", v.0, v.1)]);
-        })
-    }
+/// <pre> commands of the given Image, in their execution order.
+pub fn image_pre_invocations(docker_image: &Image) -> Vec<Invocation> {
+    docker_image
+        .pre
+        .iter()
+        .flatten()
+        .map(|p| Invocation::shell(&p.1))
+        .collect()
+}
 
-    if let Some(options) = &docker_container.options {
-        options.iter().for_each(|o| {
-            docker_run_command.arg(o);
-        })
-    }
+/// <post> commands of the given Image, in their execution order.
+pub fn image_post_invocations(docker_image: &Image) -> Vec<Invocation> {
+    docker_image
+        .post
+        .iter()
+        .flatten()
+        .map(|p| Invocation::shell(&p.1))
+        .collect()
+}
 
-    if let Some(entrypoint) = &docker_container.entrypoint {
-        if !entrypoint.is_empty() {
-            docker_run_command.args(["--entrypoint", &entrypoint[0]]);
-            entrypoint[1..].iter().for_each(|t| {
-                docker_run_command.arg(&t);
-            })
-        } else {
-            panic!("Missing entrypoint command/arguments.");
-        }
+/// Runs a new container according to the given Container, with the given
+/// container runtime, once the host ports it publishes are known to be free.
+///
+/// It is finished once the container is ready, so that its dependent Tasks
+/// find it healthy (or at least running). The started container is passed to
+/// `record` as soon as it exists, and its output to `sink` for as long as the
+/// Flow runs.
+pub async fn run_docker_container(
+    docker_container: &Container,
+    runtime: &dyn ContainerRuntime,
+    sink: LineSink,
+    record: &(dyn Fn(Launched) -> Boxed<()> + Sync),
+) -> Result<()> {
+    info!("Starting {} with {}", docker_container.name, runtime.name());
+    check_host_ports(docker_container).await?;
+    let id = runtime
+        .run(docker_container)
+        .await
+        .with_context(|| RunerError::Runtime(runtime.name().to_owned()))?;
+    record(Launched::Container(ContainerRecord {
+        name: docker_container.name.clone(),
+        id,
+        runtime: runtime.name().to_owned(),
+    }))
+    .await;
+    if let Err(e) = runtime.follow_logs(&docker_container.name, sink).await {
+        warn!("Can't follow the logs of {}: {e:#}", docker_container.name);
     }
+    wait_until_ready(docker_container, runtime).await
+}
 
-    if let Some(hc) = &docker_container.hc {
-        if !hc.command.1.is_empty() {
-            match &hc.command.0 {
-                ExecutionEnvironment::Local => {
-                    todo!("Implement command execution in Container")
-                }
-                ExecutionEnvironment::Container => {
-                    docker_run_command.args(["--health-cmd", &hc.command.1]);
-                }
-            }
-        } else {
-            panic!("Missing healthcheck command/arguments.")
+/// Runs the commands of the given Shell and waits until they are finished.
+/// If the Shell has a health check, it waits only until the check passes
+/// and leaves the commands running.
+///
+/// The output of the commands is passed to `sink`, and the ID of the shell
+/// process to `record`.
+///
+/// Returns error if the shell exits with a non-success code.
+pub async fn run_shell_script(
+    shell: &Shell,
+    sink: LineSink,
+    record: &(dyn Fn(Launched) -> Boxed<()> + Sync),
+) -> Result<()> {
+    info!("Starting to run shell script");
+    let invocation = shell_invocation(shell);
+    let mut child = invocation
+        .group_command()
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| RunerError::Spawn(invocation.program.clone()))?;
+    let group = ProcessGroup::register(child.id());
+    record(Launched::Process(child.id())).await;
+
+    let Some(hc) = &shell.hc else {
+        let stdout = forward_lines(child.stdout.take().unwrap(), sink.clone());
+        let stderr = forward_lines(child.stderr.take().unwrap(), sink);
+        stdout.await;
+        stderr.await;
+        let status = child.status().await?;
+        if !status.success() {
+            return Err(anyhow!("exited with {status}"));
         }
+        return Ok(());
+    };
+
+    // The output is also captured for log probes, for as long as the
+    // commands run
+    let output = Arc::new(Mutex::new(String::new()));
+    let tee: LineSink = {
+        let output = output.clone();
+        Arc::new(move |line: &str| {
+            sink(line);
+            capture(&mut output.lock().unwrap(), line);
+        })
+    };
+    forward_lines(child.stdout.take().unwrap(), tee.clone()).detach();
+    forward_lines(child.stderr.take().unwrap(), tee).detach();
+    wait_until_healthy(hc, shell_timeout(shell), &mut child, &output).await?;
+    // The commands keep running, and stay reachable by signals until they
+    // exit
+    smol::spawn(async move {
+        let _ = child.status().await;
+        drop(group);
+    })
+    .detach();
+    Ok(())
+}
 
-        if let Some(interval) = &hc.interval {
-            docker_run_command.args(["--health-interval", interval]);
-        }
-        if let Some(retries) = hc.retries {
-            docker_run_command.args(["--health-retries", &retries.to_string()]);
-        }
+/// How much of the output of a shell script is kept for log probes.
+const CAPTURED_OUTPUT_LIMIT: usize = 1 << 20;
+
+/// Appends the given line to the captured output, dropping the oldest
+/// output beyond [CAPTURED_OUTPUT_LIMIT].
+fn capture(captured: &mut String, line: &str) {
+    captured.push_str(line);
+    captured.push('\n');
+    if captured.len() > CAPTURED_OUTPUT_LIMIT {
+        let excess = captured.len() - CAPTURED_OUTPUT_LIMIT;
+        let cut = (excess..captured.len())
+            .find(|idx| captured.is_char_boundary(*idx))
+            .unwrap_or(captured.len());
+        captured.drain(..cut);
     }
-
-    // TODO: Change this
-    docker_run_command.arg("--net=last_default");
-
-    docker_run_command.arg(&docker_container.image);
-
-    docker_run_command.stdout(Stdio::null()).spawn()
 }
 
-// TODO: Instead of setting environment_variables with private function
-// use .env method of Command struct.
-pub async fn run_shell_script(shell: &Shell) -> Result<Child, std::io::Error> {
-    info!("Starting to run shell script");
+/// Assembles the <sh -c> command of the given Shell. Its environment
+/// variables are only visible to the spawned shell.
+pub fn shell_invocation(shell: &Shell) -> Invocation {
+    let mut invocation = Invocation::shell(&shell.commands.join(" && "));
     if let Some(environment_variables) = &shell.env {
-        match set_environment_variables(environment_variables) {
-            Ok(mut child) => {
-                child.status().await?;
-            }
-            Err(e) => panic!(
-                "FATAL ERROR: Something went wrong setting up environment variables {}",
-                e
-            ),
+        for (key, value) in environment_variables {
+            invocation = invocation.env(key, value);
         }
     }
-    Command::new("sh")
-        .arg("-c")
-        .arg(shell.commands.join(" && "))
-        .spawn()
+    invocation
 }
 
 /// Sets the given (String, String) tuples as environment variables inside the
 /// **execution** environment.
 ///
 /// First element gets used as KEY. Second element gets used as VALUE.
-pub fn set_environment_variables(
-    key_values: &Vec<(String, String)>,
-) -> Result<Child, std::io::Error> {
+pub fn set_environment_variables(key_values: &[(String, String)]) {
     info!("Setting environment variables");
     key_values.iter().for_each(|p| {
         std::env::set_var(&p.0, &p.1);
     });
-    Command::new("echo")
-        .arg("Environment variables are set")
-        .stdout(Stdio::null())
-        .spawn()
 }
@@ -0,0 +1,243 @@
+use std::collections::HashSet;
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::model::runer::{Flow, Rune};
+
+use super::graph::TaskGraph;
+
+#[derive(Serialize)]
+struct RuneListing {
+    flows: Vec<FlowListing>,
+    blueprints: Vec<BlueprintListing>,
+    env: Vec<EnvListing>,
+}
+
+#[derive(Serialize)]
+struct FlowListing {
+    name: String,
+    task_count: usize,
+    pkg_dependencies: Vec<String>,
+    tasks: Vec<TaskListing>,
+}
+
+#[derive(Serialize)]
+struct TaskListing {
+    id: u32,
+    name: String,
+    #[serde(rename = "type")]
+    typ: String,
+    job: String,
+    depends: Vec<u32>,
+}
+
+#[derive(Serialize)]
+struct BlueprintListing {
+    name: String,
+    image: bool,
+    container: bool,
+    shell: bool,
+}
+
+#[derive(Serialize)]
+struct EnvListing {
+    name: String,
+    variables: Vec<String>,
+}
+
+impl RuneListing {
+    fn from_rune(rune: &Rune) -> Self {
+        let flows = rune
+            .flows
+            .iter()
+            .flatten()
+            .map(|flow| FlowListing {
+                name: flow.name.clone(),
+                task_count: flow.tasks.len(),
+                pkg_dependencies: flow.pkg_dependencies.clone().unwrap_or_default(),
+                tasks: flow
+                    .tasks
+                    .iter()
+                    .map(|task| TaskListing {
+                        id: task.id,
+                        name: task.name.clone(),
+                        typ: task.typ.to_string(),
+                        job: task.job.to_string(),
+                        depends: task.depends.clone(),
+                    })
+                    .collect(),
+            })
+            .collect();
+
+        let mut blueprints: Vec<BlueprintListing> = rune
+            .blueprints
+            .iter()
+            .flatten()
+            .map(|(name, blueprint)| BlueprintListing {
+                name: name.clone(),
+                image: blueprint.image.is_some(),
+                container: blueprint.container.is_some(),
+                shell: blueprint.shell.is_some(),
+            })
+            .collect();
+        blueprints.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let mut env: Vec<EnvListing> = rune
+            .env
+            .iter()
+            .flatten()
+            .map(|(name, variables)| EnvListing {
+                name: name.clone(),
+                variables: variables.iter().map(|(key, _)| key.clone()).collect(),
+            })
+            .collect();
+        env.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Self {
+            flows,
+            blueprints,
+            env,
+        }
+    }
+}
+
+/// Renders the Flows, Blueprints and environment variable lists of the given
+/// Rune, either as human readable tables or as JSON.
+pub fn list_rune(rune: &Rune, json: bool) -> Result<String> {
+    let listing = RuneListing::from_rune(rune);
+    if json {
+        return Ok(format!("{}\n", serde_json::to_string_pretty(&listing)?));
+    }
+
+    let mut out = String::new();
+
+    out.push_str("FLOWS\n");
+    if listing.flows.is_empty() {
+        out.push_str("  (none)\n");
+    }
+    for flow in rune.flows.iter().flatten() {
+        out.push_str(&format!("  {} ({} tasks)\n", flow.name, flow.tasks.len()));
+        out.push_str(&render_tree(flow));
+    }
+
+    out.push_str("\nBLUEPRINTS\n");
+    let mark = |defined: bool| if defined { "yes" } else { "-" };
+    let rows: Vec<Vec<String>> = listing
+        .blueprints
+        .iter()
+        .map(|b| {
+            vec![
+                b.name.clone(),
+                mark(b.image).to_owned(),
+                mark(b.container).to_owned(),
+                mark(b.shell).to_owned(),
+            ]
+        })
+        .collect();
+    out.push_str(&render_table(
+        &["NAME", "IMAGE", "CONTAINER", "SHELL"],
+        &rows,
+    ));
+
+    out.push_str("\nENV SETS\n");
+    let rows: Vec<Vec<String>> = listing
+        .env
+        .iter()
+        .map(|e| vec![e.name.clone(), e.variables.join(", ")])
+        .collect();
+    out.push_str(&render_table(&["NAME", "VARIABLES"], &rows));
+
+    Ok(out)
+}
+
+/// Renders the dependency tree of the given Flow, starting from the Tasks
+/// that don't depend on any other Task. A Task with several parents is
+/// expanded only under the first one, later occurrences are marked with (*).
+fn render_tree(flow: &Flow) -> String {
+    let graph = match TaskGraph::from_flow(flow) {
+        Ok(graph) => graph,
+        Err(e) => return format!("    {e}\n"),
+    };
+
+    fn render(
+        flow: &Flow,
+        graph: &TaskGraph,
+        id: u32,
+        prefix: &str,
+        last: bool,
+        seen: &mut HashSet<u32>,
+        out: &mut String,
+    ) {
+        let task = flow.tasks.iter().find(|t| t.id == id).unwrap();
+        let branch = if last { "└── " } else { "├── " };
+        let repeated = !seen.insert(id);
+        out.push_str(&format!(
+            "    {prefix}{branch}{} {} [{}/{}]{}\n",
+            task.id,
+            task.name,
+            task.typ,
+            task.job,
+            if repeated { " (*)" } else { "" }
+        ));
+        if repeated {
+            return;
+        }
+        let children = graph.children(id);
+        let prefix = format!("{prefix}{}", if last { "    " } else { "│   " });
+        for (idx, child) in children.iter().enumerate() {
+            render(
+                flow,
+                graph,
+                *child,
+                &prefix,
+                idx == children.len() - 1,
+                seen,
+                out,
+            );
+        }
+    }
+
+    let mut out = String::new();
+    let mut seen = HashSet::new();
+    let roots = graph.roots();
+    for (idx, root) in roots.iter().enumerate() {
+        render(
+            flow,
+            &graph,
+            *root,
+            "",
+            idx == roots.len() - 1,
+            &mut seen,
+            &mut out,
+        );
+    }
+    out
+}
+
+/// Renders the given rows as left aligned columns under the given headers.
+pub fn render_table(headers: &[&str], rows: &[Vec<String>]) -> String {
+    if rows.is_empty() {
+        return "  (none)\n".to_owned();
+    }
+    let mut widths: Vec<usize> = headers.iter().map(|h| h.chars().count()).collect();
+    for row in rows {
+        for (idx, cell) in row.iter().enumerate() {
+            widths[idx] = widths[idx].max(cell.chars().count());
+        }
+    }
+    let render_row = |cells: Vec<&str>| {
+        let line = cells
+            .iter()
+            .enumerate()
+            .map(|(idx, cell)| format!("{cell:<width$}", width = widths[idx]))
+            .collect::<Vec<String>>()
+            .join("  ");
+        format!("  {}\n", line.trim_end())
+    };
+    let mut out = render_row(headers.to_vec());
+    for row in rows {
+        out.push_str(&render_row(row.iter().map(String::as_str).collect()));
+    }
+    out
+}
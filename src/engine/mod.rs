@@ -1,5 +1,25 @@
+pub mod diagnostic;
+pub mod down;
+pub mod dry_run;
+pub mod duration;
+pub mod error;
 pub mod executor;
 pub mod extractor;
+pub mod graph;
+pub mod http;
+pub mod invocation;
 pub mod job;
+pub mod listing;
+pub mod output;
+pub mod ports;
+pub mod readiness;
+pub mod run_log;
+pub mod run_state;
+pub mod runtime;
+pub mod scheduler;
+pub mod shutdown;
 pub mod state;
+pub mod status;
 pub mod task;
+pub mod validator;
+pub mod volumes;
@@ -0,0 +1,200 @@
+use std::fs::File;
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use chrono::Local;
+use log::warn;
+use smol::io::{AsyncBufReadExt, AsyncRead, BufReader};
+
+use crate::model::runer::Task;
+
+use super::run_log::{append_line, RunLog};
+
+/// Receives the output of a Task line by line, without the line break.
+pub type LineSink = Arc<dyn Fn(&str) + Send + Sync>;
+
+static COLOR: AtomicBool = AtomicBool::new(true);
+
+/// Background tasks that follow the logs of started containers, see
+/// [follow].
+static FOLLOWERS: Mutex<Vec<smol::Task<()>>> = Mutex::new(Vec::new());
+
+/// Colors of the prefixes, picked by Task ID so that neighbouring Tasks
+/// differ.
+const PALETTE: [u8; 6] = [36, 33, 32, 35, 34, 31];
+
+/// Decides whether the prefixes get colored: not if it is disabled on the
+/// command line or through `NO_COLOR`, or if stdout isn't a terminal.
+pub fn init_color(no_color: bool) {
+    let enabled = color_enabled(
+        no_color,
+        std::env::var_os("NO_COLOR").is_some(),
+        std::io::stdout().is_terminal(),
+    );
+    COLOR.store(enabled, Ordering::Relaxed);
+}
+
+fn color_enabled(no_color: bool, no_color_env: bool, terminal: bool) -> bool {
+    !no_color && !no_color_env && terminal
+}
+
+/// Prints the output of a single Task, every line prefixed with the time and
+/// the name of the Task, and keeps it in the log of the Task in the run
+/// directory.
+pub struct TaskLog {
+    prefix: String,
+    quiet: bool,
+    file: Option<Mutex<File>>,
+}
+
+impl TaskLog {
+    pub fn for_task(task: &Task, run_log: Option<&RunLog>) -> Self {
+        Self::new(task, COLOR.load(Ordering::Relaxed), run_log)
+    }
+
+    fn new(task: &Task, color: bool, run_log: Option<&RunLog>) -> Self {
+        let prefix = if color {
+            let color = PALETTE[task.id as usize % PALETTE.len()];
+            format!("\x1b[{color}m[{}]\x1b[0m", task.name)
+        } else {
+            format!("[{}]", task.name)
+        };
+        let file = run_log.and_then(|run_log| match run_log.open(&task.name) {
+            Ok(file) => Some(Mutex::new(file)),
+            Err(e) => {
+                warn!("Output of Task {} won't be kept: {e:#}", task.id);
+                None
+            }
+        });
+        Self {
+            prefix,
+            quiet: task.quiet,
+            file,
+        }
+    }
+
+    /// Prints the given line, unless the Task is quiet, and keeps it in the
+    /// log of the Task either way.
+    pub fn line(&self, line: &str) {
+        let line = line.trim_end_matches('\r');
+        if let Some(file) = &self.file {
+            // A log that can't be written doesn't stop the Task
+            let _ = append_line(&mut file.lock().unwrap(), line);
+        }
+        let time = Local::now().format("%H:%M:%S").to_string();
+        if let Some(printed) = self.render(&time, line) {
+            println!("{printed}");
+        }
+    }
+
+    /// The given line as it is printed at the given time, None if the Task
+    /// is quiet.
+    fn render(&self, time: &str, line: &str) -> Option<String> {
+        (!self.quiet).then(|| format!("{time} {} {line}", self.prefix))
+    }
+
+    pub fn sink(self: &Arc<Self>) -> LineSink {
+        let log = self.clone();
+        Arc::new(move |line| log.line(line))
+    }
+}
+
+/// Passes the lines of the given stream to the given sink as they arrive,
+/// until the stream ends. Invalid UTF-8 is replaced rather than stopping the
+/// stream, which would block the writer once the pipe is full.
+pub fn forward_lines(
+    stream: impl AsyncRead + Unpin + Send + 'static,
+    sink: LineSink,
+) -> smol::Task<()> {
+    smol::spawn(async move {
+        let mut reader = BufReader::new(stream);
+        let mut line = Vec::new();
+        while let Ok(read) = reader.read_until(b'\n', &mut line).await {
+            if read == 0 {
+                break;
+            }
+            if line.ends_with(b"\n") {
+                line.pop();
+            }
+            sink(&String::from_utf8_lossy(&line));
+            line.clear();
+        }
+    })
+}
+
+/// Keeps the given background task, which follows the logs of a container,
+/// running until [stop_following] is called.
+pub fn follow(task: smol::Task<()>) {
+    FOLLOWERS.lock().unwrap().push(task);
+}
+
+/// Cancels every background task registered with [follow].
+pub fn stop_following() {
+    FOLLOWERS.lock().unwrap().clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn task(yaml: &str) -> Task {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn lines_are_prefixed_with_the_time_and_the_task() {
+        let db = task("{ id: 1, type: Blueprint, name: db, job: container }");
+        let log = TaskLog::new(&db, false, None);
+        assert_eq!(
+            log.render("10:00:00", "ready").as_deref(),
+            Some("10:00:00 [db] ready")
+        );
+    }
+
+    #[test]
+    fn prefixes_are_colored_by_task_id() {
+        let db = task("{ id: 1, type: Blueprint, name: db, job: container }");
+        let api = task("{ id: 8, type: Blueprint, name: api, job: container }");
+        assert_eq!(
+            TaskLog::new(&db, true, None)
+                .render("10:00:00", "ready")
+                .as_deref(),
+            Some("10:00:00 \x1b[33m[db]\x1b[0m ready")
+        );
+        // The palette wraps around
+        assert_eq!(
+            TaskLog::new(&api, true, None)
+                .render("10:00:00", "ready")
+                .as_deref(),
+            Some("10:00:00 \x1b[32m[api]\x1b[0m ready")
+        );
+    }
+
+    #[test]
+    fn color_is_suppressed_on_request_or_without_a_terminal() {
+        assert!(color_enabled(false, false, true));
+        // --no-color
+        assert!(!color_enabled(true, false, true));
+        // NO_COLOR
+        assert!(!color_enabled(false, true, true));
+        // stdout isn't a terminal, e.g. piped or redirected
+        assert!(!color_enabled(false, false, false));
+    }
+
+    #[test]
+    fn quiet_tasks_are_only_kept_in_their_log() {
+        let runs_dir = std::env::temp_dir().join(format!("runer-output-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&runs_dir);
+        let run_log = RunLog::create(&runs_dir).unwrap();
+        let build = task("{ id: 2, type: Blueprint, name: api, job: image, quiet: true }");
+        let log = TaskLog::new(&build, true, Some(&run_log));
+        assert_eq!(log.render("10:00:00", "step 1/4"), None);
+
+        log.line("\x1b[1mstep 1/4\x1b[0m\r");
+        log.line("step 2/4");
+        let content = std::fs::read_to_string(run_log.dir().join("api.log")).unwrap();
+        let _ = std::fs::remove_dir_all(&runs_dir);
+        assert_eq!(content, "step 1/4\nstep 2/4\n");
+    }
+}
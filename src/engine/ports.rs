@@ -0,0 +1,126 @@
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, TcpListener, UdpSocket};
+
+use anyhow::{anyhow, Result};
+use log::warn;
+use smol::process::Command;
+
+use crate::model::runer::{Container, PortMapping, Protocol};
+
+/// Checks that every host port that the given Container publishes is free,
+/// by binding it for a moment. Ports that runer itself isn't allowed to bind,
+/// e.g. the ones below 1024 for non-root users, are only warned about, since
+/// the container engine binds them on its own.
+///
+/// Returns error naming the process that holds the first port in use, if it
+/// can be found out.
+pub async fn check_host_ports(container: &Container) -> Result<()> {
+    for mapping in container.ports.iter().flatten() {
+        for container_port in mapping.container.ports() {
+            let Some(port) = mapping.host_port(container_port) else {
+                continue;
+            };
+            match bind(mapping, port) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::AddrInUse => {
+                    let holder = match port_holder(port, mapping.protocol).await {
+                        Some(holder) => format!("by {holder}"),
+                        None => "by another process".to_owned(),
+                    };
+                    return Err(anyhow!(
+                        "host port {port}/{} of {} is already in use {holder}",
+                        mapping.protocol,
+                        container.name
+                    ));
+                }
+                Err(e) => warn!(
+                    "Can't tell whether host port {port}/{} of {} is free: {e}",
+                    mapping.protocol, container.name
+                ),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Binds the given host port of the given mapping, and releases it right
+/// away.
+fn bind(mapping: &PortMapping, port: u16) -> io::Result<()> {
+    let ip = mapping.host_ip.unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+    match mapping.protocol {
+        Protocol::Tcp => TcpListener::bind((ip, port)).map(drop),
+        Protocol::Udp => UdpSocket::bind((ip, port)).map(drop),
+    }
+}
+
+/// Finds out the process that holds the given host port, with _lsof_ or _ss_,
+/// whichever is installed. Returns it as `name (pid N)`.
+async fn port_holder(port: u16, protocol: Protocol) -> Option<String> {
+    let lsof = match protocol {
+        Protocol::Tcp => {
+            Command::new("lsof")
+                .args(["-nP", &format!("-iTCP:{port}"), "-sTCP:LISTEN", "-Fpc"])
+                .output()
+                .await
+        }
+        Protocol::Udp => {
+            Command::new("lsof")
+                .args(["-nP", &format!("-iUDP:{port}"), "-Fpc"])
+                .output()
+                .await
+        }
+    };
+    if let Ok(output) = lsof {
+        // One field per line, e.g. `p1234` followed by `cnginx`
+        let output = String::from_utf8_lossy(&output.stdout);
+        let pid = output.lines().find_map(|l| l.strip_prefix('p'));
+        let name = output.lines().find_map(|l| l.strip_prefix('c'));
+        if let (Some(pid), Some(name)) = (pid, name) {
+            return Some(format!("{name} (pid {pid})"));
+        }
+    }
+
+    let flags = match protocol {
+        Protocol::Tcp => "-Hlptn",
+        Protocol::Udp => "-Hlpun",
+    };
+    let output = Command::new("ss")
+        .args([flags, &format!("sport = :{port}")])
+        .output()
+        .await
+        .ok()?;
+    // e.g. `users:(("nginx",pid=1234,fd=6))`
+    let output = String::from_utf8_lossy(&output.stdout);
+    let users = output.split("users:((").nth(1)?;
+    let mut fields = users.split(',');
+    let name = fields.next()?.trim_matches('"');
+    let pid = fields.find_map(|f| f.strip_prefix("pid="))?;
+    Some(format!("{name} (pid {pid})"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use super::*;
+
+    fn container(port: u16) -> Container {
+        serde_yaml::from_str(&format!(
+            "{{ name: app, image: app, ports: [\"127.0.0.1:{port}:80\"] }}"
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn ports_in_use_are_reported() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let error = smol::block_on(check_host_ports(&container(port))).unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with(&format!("host port {port}/tcp of app is already in use by")));
+
+        drop(listener);
+        smol::block_on(check_host_ports(&container(port))).unwrap();
+    }
+}
@@ -0,0 +1,608 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+use anyhow::{anyhow, Context, Result};
+use log::info;
+use regex::Regex;
+use smol::future;
+use smol::net::TcpStream;
+use smol::process::Child;
+use smol::Timer;
+
+use crate::model::runer::{
+    Container, ExecutionEnvironment, HealthCheck, HttpProbe, ReadyCondition, Shell,
+};
+
+use super::duration::parse_duration;
+use super::error::RunerError;
+use super::http::{self, Request};
+use super::invocation::Invocation;
+use super::runtime::ContainerRuntime;
+
+/// How long a Task may take to become ready if its Rune doesn't say.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// How often the state of a starting Task is checked.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often a health check command runs on the host if its Rune doesn't
+/// say, the same as the default of container-native health checks.
+const DEFAULT_HC_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How many consecutive failures of a health check command make the Task
+/// unhealthy if its Rune doesn't say.
+const DEFAULT_HC_RETRIES: u32 = 3;
+
+/// How often a built-in probe runs if its Rune doesn't say. Probes are cheap,
+/// so they run a lot more often than commands.
+const DEFAULT_PROBE_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How long a TCP or HTTP probe may take before it counts as failed.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How many lines of the output of a crashed Task are reported.
+const LOG_TAIL: usize = 20;
+
+/// Condition that the given Container has to meet before its dependent
+/// Tasks can start: the declared one, otherwise `healthy` if the container
+/// has a health check and `running` if it doesn't.
+pub fn ready_condition(container: &Container) -> ReadyCondition {
+    container
+        .ready
+        .as_ref()
+        .and_then(|ready| ready.condition)
+        .unwrap_or(if container.hc.is_some() {
+            ReadyCondition::Healthy
+        } else {
+            ReadyCondition::Running
+        })
+}
+
+/// How long to wait for the given Container to become ready.
+pub fn ready_timeout(container: &Container) -> Duration {
+    container
+        .ready
+        .as_ref()
+        .and_then(|ready| ready.timeout.as_deref())
+        .and_then(parse_duration)
+        .unwrap_or(DEFAULT_TIMEOUT)
+}
+
+/// How long to wait for the health check of the given shell script to pass.
+pub fn shell_timeout(shell: &Shell) -> Duration {
+    shell
+        .timeout
+        .as_deref()
+        .and_then(parse_duration)
+        .unwrap_or(DEFAULT_TIMEOUT)
+}
+
+/// What runer does on the host to check the health of a Task, or None if the
+/// container runtime checks it.
+pub fn describe_host_check(hc: &HealthCheck) -> Option<String> {
+    if let Some((ExecutionEnvironment::Local, command)) = &hc.command {
+        return Some(format!("$ {}", Invocation::shell(command)));
+    }
+    if let Some(address) = &hc.tcp {
+        return Some(format!("connect to {address}"));
+    }
+    if let Some(probe) = &hc.http {
+        let mut description = format!("GET {}", probe.url);
+        if let Some(status) = probe.status {
+            description.push_str(&format!(" expecting {status}"));
+        }
+        if let Some(body) = &probe.body {
+            description.push_str(&format!(" containing '{body}'"));
+        }
+        return Some(description);
+    }
+    hc.log
+        .as_ref()
+        .map(|pattern| format!("look for a line matching /{pattern}/"))
+}
+
+/// Where the output of a Task is read from by log probes.
+enum TaskOutput<'a> {
+    Container(&'a dyn ContainerRuntime, &'a str),
+    Captured(&'a Mutex<String>),
+}
+
+impl TaskOutput<'_> {
+    async fn read(&self) -> String {
+        match self {
+            TaskOutput::Container(runtime, container) => {
+                runtime.logs(container).await.unwrap_or_default()
+            }
+            TaskOutput::Captured(output) => output.lock().unwrap().clone(),
+        }
+    }
+}
+
+/// Waits until the given, already started, Container meets its readiness
+/// condition.
+///
+/// Returns error as soon as the container exits or its health check reports
+/// it as unhealthy, or once the timeout passes, with the last output of the
+/// health check or the tail of the container logs to tell why.
+pub async fn wait_until_ready(container: &Container, runtime: &dyn ContainerRuntime) -> Result<()> {
+    let condition = ready_condition(container);
+    let timeout = ready_timeout(container);
+    let deadline = Instant::now() + timeout;
+    let mut host_check = match &container.hc {
+        Some(hc) => HostCheck::new(hc)?,
+        None => None,
+    };
+    let output = TaskOutput::Container(runtime, &container.name);
+    info!("Waiting until {} is {condition}", container.name);
+    loop {
+        let inspected_at = SystemTime::now();
+        let info = runtime
+            .inspect(&container.name)
+            .await
+            .with_context(|| RunerError::Runtime(runtime.name().to_owned()))?;
+        if !info.running {
+            let code = info
+                .exit_code
+                .map_or_else(|| "unknown".to_owned(), |code| code.to_string());
+            return Err(anyhow!(
+                "{} exited with code {code} before it became {condition}{}",
+                container.name,
+                describe_tail(&output.read().await)
+            ));
+        }
+        let (health, health_output) = match &mut host_check {
+            Some(check) if condition == ReadyCondition::Healthy => check.poll(&output).await,
+            _ => (info.health, info.health_output),
+        };
+        match (condition, health.as_deref()) {
+            (ReadyCondition::Running, _) | (ReadyCondition::Healthy, Some("healthy")) => {
+                info!("{} is {condition}", container.name);
+                return Ok(());
+            }
+            (ReadyCondition::Healthy, Some("unhealthy")) => {
+                return Err(anyhow!(
+                    "{} is unhealthy{}",
+                    container.name,
+                    describe_output(health_output.as_deref())
+                ));
+            }
+            _ => {}
+        }
+        if Instant::now() >= deadline {
+            return Err(RunerError::Timeout(format!(
+                "{} didn't become {condition} within {timeout:?}{}",
+                container.name,
+                describe_output(health_output.as_deref())
+            ))
+            .into());
+        }
+        // Runtimes that watch events wake up as soon as the container dies
+        // or reports its health, the others are polled. So are containers
+        // checked on the host, their health only changes when checked.
+        if host_check.is_none() {
+            let event = future::or(
+                runtime.wait_for_event(&container.name, inspected_at),
+                async {
+                    Timer::at(deadline).await;
+                    Ok(Some("timeout".to_owned()))
+                },
+            )
+            .await;
+            if let Ok(Some(_)) = event {
+                continue;
+            }
+        }
+        Timer::after(POLL_INTERVAL).await;
+    }
+}
+
+/// Waits until the health check of a running shell script passes, with the
+/// output of the script captured so far in `output`.
+///
+/// Returns error if the script fails before, or its health check reports it
+/// as unhealthy, or once `timeout` passes. A script that exits successfully
+/// is still checked until it becomes healthy.
+pub async fn wait_until_healthy(
+    hc: &HealthCheck,
+    timeout: Duration,
+    child: &mut Child,
+    output: &Mutex<String>,
+) -> Result<()> {
+    let deadline = Instant::now() + timeout;
+    let mut check =
+        HostCheck::new(hc)?.ok_or_else(|| anyhow!("'Container' health checks need a container"))?;
+    let output = TaskOutput::Captured(output);
+    let mut exited = false;
+    info!("Waiting until the shell script is healthy");
+    loop {
+        if !exited {
+            if let Some(status) = child.try_status()? {
+                if !status.success() {
+                    return Err(anyhow!(
+                        "shell script exited with {status} before it became healthy{}",
+                        describe_tail(&output.read().await)
+                    ));
+                }
+                // e.g. it started a daemon, that has to become healthy
+                exited = true;
+            }
+        }
+        let (health, health_output) = check.poll(&output).await;
+        match health.as_deref() {
+            Some("healthy") => {
+                info!("Shell script is healthy, it keeps running in the background");
+                return Ok(());
+            }
+            Some("unhealthy") => {
+                return Err(anyhow!(
+                    "shell script is unhealthy{}",
+                    describe_output(health_output.as_deref())
+                ));
+            }
+            _ => {}
+        }
+        if Instant::now() >= deadline {
+            return Err(RunerError::Timeout(format!(
+                "shell script didn't become healthy within {timeout:?}{}",
+                describe_output(health_output.as_deref())
+            ))
+            .into());
+        }
+        Timer::after(POLL_INTERVAL).await;
+    }
+}
+
+enum Probe {
+    Command(Invocation),
+    Tcp(String),
+    Http(HttpProbe),
+    Log(Regex),
+}
+
+/// A health check that runer runs on the host. It reports the Task unhealthy
+/// once the check fails `retries` times in a row.
+struct HostCheck {
+    probe: Probe,
+    interval: Duration,
+    retries: Option<u32>,
+    next_run: Instant,
+    failures: u32,
+    output: Option<String>,
+}
+
+impl HostCheck {
+    /// Returns None for health checks that the container runtime runs.
+    fn new(hc: &HealthCheck) -> Result<Option<Self>> {
+        let probe = if let Some((environment, command)) = &hc.command {
+            match environment {
+                ExecutionEnvironment::Local => Probe::Command(Invocation::shell(command)),
+                ExecutionEnvironment::Container => return Ok(None),
+            }
+        } else if let Some(address) = &hc.tcp {
+            Probe::Tcp(address.clone())
+        } else if let Some(probe) = &hc.http {
+            Probe::Http(probe.clone())
+        } else if let Some(pattern) = &hc.log {
+            Probe::Log(Regex::new(pattern)?)
+        } else {
+            return Ok(None);
+        };
+        let (default_interval, retries) = match probe {
+            Probe::Command(_) => (
+                DEFAULT_HC_INTERVAL,
+                Some(hc.retries.unwrap_or(DEFAULT_HC_RETRIES)),
+            ),
+            _ => (DEFAULT_PROBE_INTERVAL, hc.retries),
+        };
+        Ok(Some(Self {
+            probe,
+            interval: hc
+                .interval
+                .as_deref()
+                .and_then(parse_duration)
+                .unwrap_or(default_interval),
+            retries: retries.map(|retries| retries.max(1)),
+            next_run: Instant::now(),
+            failures: 0,
+            output: None,
+        }))
+    }
+
+    /// Runs the check if it is due, and returns the health state and the
+    /// latest output of the check, like the runtime reports them for
+    /// container-native health checks.
+    async fn poll(&mut self, output: &TaskOutput<'_>) -> (Option<String>, Option<String>) {
+        if Instant::now() >= self.next_run {
+            self.next_run = Instant::now() + self.interval;
+            let (passed, text) = self.run(output).await;
+            self.output = Some(text);
+            if passed {
+                return (Some("healthy".to_owned()), self.output.clone());
+            }
+            self.failures += 1;
+        }
+        let health = match self.retries {
+            Some(retries) if self.failures >= retries => "unhealthy",
+            _ => "starting",
+        };
+        (Some(health.to_owned()), self.output.clone())
+    }
+
+    /// Runs the check once, returning whether it passed and what it saw.
+    async fn run(&self, output: &TaskOutput<'_>) -> (bool, String) {
+        match &self.probe {
+            Probe::Command(invocation) => match invocation.command().output().await {
+                Ok(result) => {
+                    let mut text = String::from_utf8_lossy(&result.stdout).into_owned();
+                    text.push_str(&String::from_utf8_lossy(&result.stderr));
+                    (result.status.success(), text.trim().to_owned())
+                }
+                Err(e) => (false, format!("failed to run '{invocation}': {e}")),
+            },
+            Probe::Tcp(address) => match with_timeout(TcpStream::connect(address.as_str())).await {
+                Ok(_) => (true, format!("connected to {address}")),
+                Err(e) => (false, format!("connection to {address} failed: {e}")),
+            },
+            Probe::Http(probe) => match with_timeout(get(&probe.url)).await {
+                Ok((status, body)) => {
+                    let status_ok = match probe.status {
+                        Some(expected) => status == expected,
+                        None => (200..300).contains(&status),
+                    };
+                    if !status_ok {
+                        (false, format!("GET {} returned {status}", probe.url))
+                    } else if let Some(needle) = probe.body.as_ref().filter(|n| !body.contains(*n))
+                    {
+                        (
+                            false,
+                            format!("body of GET {} doesn't contain '{needle}'", probe.url),
+                        )
+                    } else {
+                        (true, format!("GET {} returned {status}", probe.url))
+                    }
+                }
+                Err(e) => (false, format!("GET {} failed: {e}", probe.url)),
+            },
+            Probe::Log(pattern) => {
+                let logs = output.read().await;
+                match logs.lines().find(|line| pattern.is_match(line)) {
+                    Some(line) => (true, line.to_owned()),
+                    None => (false, format!("no line matches /{pattern}/ yet")),
+                }
+            }
+        }
+    }
+}
+
+async fn get(url: &str) -> Result<(u16, String)> {
+    let (authority, path) = http::split_url(url)?;
+    let stream = TcpStream::connect(authority.as_str()).await?;
+    let response = http::send(stream, &authority, &Request::new("GET", path)).await?;
+    let status = response.status;
+    Ok((status, response.text().await?))
+}
+
+async fn with_timeout<T, E>(probe: impl std::future::Future<Output = Result<T, E>>) -> Result<T>
+where
+    E: Into<anyhow::Error>,
+{
+    future::or(async { probe.await.map_err(Into::into) }, async {
+        Timer::after(PROBE_TIMEOUT).await;
+        Err(anyhow!("timed out after {PROBE_TIMEOUT:?}"))
+    })
+    .await
+}
+
+fn describe_output(output: Option<&str>) -> String {
+    match output {
+        Some(output) if !output.is_empty() => format!(", last health check output: {output}"),
+        _ => String::new(),
+    }
+}
+
+fn describe_tail(output: &str) -> String {
+    let tail: Vec<_> = output.lines().rev().take(LOG_TAIL).collect();
+    if tail.is_empty() {
+        return String::new();
+    }
+    let mut description = ", last output:".to_owned();
+    for line in tail.into_iter().rev() {
+        description.push_str("\n  ");
+        description.push_str(line);
+    }
+    description
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use std::thread;
+
+    use smol::process::Command;
+
+    use super::*;
+
+    fn health_check(yaml: &str) -> HealthCheck {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    /// Waits until the given health check passes for a running script.
+    fn wait(hc: &HealthCheck, timeout: Duration) -> Result<()> {
+        wait_with_output(hc, timeout, &Mutex::new(String::new()))
+    }
+
+    /// Waits like [wait], for a script whose output is captured in `output`.
+    fn wait_with_output(hc: &HealthCheck, timeout: Duration, output: &Mutex<String>) -> Result<()> {
+        smol::block_on(async {
+            let mut child = Command::new("sleep").arg("30").spawn().unwrap();
+            let result = wait_until_healthy(hc, timeout, &mut child, output).await;
+            child.kill().unwrap();
+            result
+        })
+    }
+
+    /// A port that nothing listens on.
+    fn closed_port() -> u16 {
+        TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port()
+    }
+
+    /// Answers every HTTP request with the given status and body.
+    fn serve(status: &'static str, body: &'static str) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            for mut stream in listener.incoming().map_while(Result::ok) {
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap_or(0) > 0 && line != "\r\n" {
+                    line.clear();
+                }
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 {status}\r\nContent-Length: {}\r\n\r\n{body}",
+                    body.len()
+                );
+            }
+        });
+        port
+    }
+
+    #[test]
+    fn tcp_probe_passes_once_the_port_accepts_connections() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let hc = health_check(&format!("tcp: 127.0.0.1:{port}"));
+        wait(&hc, Duration::from_secs(5)).unwrap();
+    }
+
+    #[test]
+    fn http_probe_checks_the_status_and_the_body() {
+        let port = serve("200 OK", "ready");
+        let hc = health_check(&format!(
+            "http: {{ url: 'http://127.0.0.1:{port}/health', body: ready }}"
+        ));
+        wait(&hc, Duration::from_secs(5)).unwrap();
+
+        let port = serve("503 Service Unavailable", "starting");
+        let hc = health_check(&format!(
+            "{{ http: {{ url: 'http://127.0.0.1:{port}/health' }}, retries: 1 }}"
+        ));
+        let error = wait(&hc, Duration::from_secs(5)).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "shell script is unhealthy, last health check output: \
+                 GET http://127.0.0.1:{port}/health returned 503"
+            )
+        );
+    }
+
+    #[test]
+    fn probe_fails_once_its_retries_are_exhausted() {
+        let port = closed_port();
+        let hc = health_check(&format!(
+            "{{ tcp: 127.0.0.1:{port}, interval: 10ms, retries: 2 }}"
+        ));
+        let started = Instant::now();
+        let error = wait(&hc, Duration::from_secs(30)).unwrap_err();
+        assert!(error.to_string().starts_with(&format!(
+            "shell script is unhealthy, last health check output: connection to 127.0.0.1:{port} failed"
+        )));
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn probe_without_retries_runs_until_the_timeout() {
+        let port = closed_port();
+        let hc = health_check(&format!("{{ tcp: 127.0.0.1:{port}, interval: 10ms }}"));
+        let error = wait(&hc, Duration::from_secs(2)).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<RunerError>(),
+            Some(RunerError::Timeout(_))
+        ));
+        assert!(error
+            .to_string()
+            .starts_with("shell script didn't become healthy within 2s"));
+    }
+
+    #[test]
+    fn log_probe_passes_once_a_line_of_the_output_matches() {
+        let output = Arc::new(Mutex::new("starting up\n".to_owned()));
+        let writer = Arc::clone(&output);
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(200));
+            writer
+                .lock()
+                .unwrap()
+                .push_str("listening on port 8080\nready to accept connections\n");
+        });
+        let hc = health_check("{ log: 'ready to accept \\w+', interval: 10ms }");
+        let started = Instant::now();
+        wait_with_output(&hc, Duration::from_secs(5), &output).unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(200));
+    }
+
+    #[test]
+    fn log_probe_times_out_when_no_line_matches() {
+        let output = Mutex::new("starting up\nstill starting\n".to_owned());
+        let hc = health_check("{ log: '^ready$', interval: 10ms }");
+        let error = wait_with_output(&hc, Duration::from_secs(1), &output).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<RunerError>(),
+            Some(RunerError::Timeout(_))
+        ));
+        assert_eq!(
+            error.to_string(),
+            "shell script didn't become healthy within 1s, \
+             last health check output: no line matches /^ready$/ yet"
+        );
+    }
+
+    /// A path in the temporary directory that nothing exists at yet.
+    fn temp_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("runer-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn command_check_passes_once_the_command_succeeds() {
+        let marker = temp_path("ready");
+        let creator = marker.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(200));
+            std::fs::write(creator, "").unwrap();
+        });
+        let hc = health_check(&format!(
+            "{{ command: [Local, 'test -f {}'], interval: 10ms, retries: 1000 }}",
+            marker.display()
+        ));
+        let result = wait(&hc, Duration::from_secs(5));
+        let _ = std::fs::remove_file(&marker);
+        result.unwrap();
+    }
+
+    #[test]
+    fn command_check_fails_once_its_retries_are_exhausted() {
+        let runs = temp_path("runs");
+        let hc = health_check(&format!(
+            "{{ command: [Local, 'echo run >> {}; echo not yet; exit 1'], interval: 10ms, retries: 3 }}",
+            runs.display()
+        ));
+        let error = wait(&hc, Duration::from_secs(30)).unwrap_err();
+        let count = std::fs::read_to_string(&runs).unwrap().lines().count();
+        let _ = std::fs::remove_file(&runs);
+        assert_eq!(
+            error.to_string(),
+            "shell script is unhealthy, last health check output: not yet"
+        );
+        assert_eq!(count, 3);
+    }
+}
@@ -0,0 +1,313 @@
+use std::borrow::Cow;
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use chrono::Utc;
+use regex::Regex;
+use smol::Timer;
+
+/// How often a followed log is checked for new output.
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Escape sequences that terminals interpret, e.g. colors and cursor moves.
+static ANSI_ESCAPE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\x1b(\[[0-?]*[ -/]*[@-~]|\][^\x07\x1b]*(\x07|\x1b\\)|[@-Z\\-_])").unwrap()
+});
+
+/// Directory of a single run, `.runer.d/runs/<timestamp>`, that keeps the
+/// output of every Task in `<task>.log`.
+///
+/// Tasks with the same name, e.g. the image and the container job of a
+/// Blueprint, share the same log.
+#[derive(Debug)]
+pub struct RunLog {
+    dir: PathBuf,
+}
+
+impl RunLog {
+    /// Creates the directory of a new run in the given runs directory, named
+    /// after the current time so that runs sort in the order they started.
+    pub fn create(runs_dir: &Path) -> Result<Self> {
+        fs::create_dir_all(runs_dir)
+            .with_context(|| format!("Can't create {}", runs_dir.display()))?;
+        let name = Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+        // Runs started within the same second get a counter
+        let mut dir = runs_dir.join(&name);
+        for n in 2.. {
+            match fs::create_dir(&dir) {
+                Ok(()) => break,
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                    dir = runs_dir.join(format!("{name}-{n}"));
+                }
+                Err(e) => return Err(e).with_context(|| format!("Can't create {}", dir.display())),
+            }
+        }
+        Ok(Self { dir })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Opens the log of the Task with the given name for appending.
+    pub fn open(&self, task: &str) -> Result<File> {
+        let path = self.dir.join(log_file_name(task));
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("Can't open {}", path.display()))
+    }
+}
+
+/// Removes the escape sequences from the given line of output.
+pub fn strip_ansi(line: &str) -> Cow<'_, str> {
+    ANSI_ESCAPE.replace_all(line, "")
+}
+
+/// Appends the given line to the given log. The line is written at once, so
+/// that lines of Tasks sharing the log don't get mixed up.
+pub fn append_line(log: &mut File, line: &str) -> std::io::Result<()> {
+    let mut buf = strip_ansi(line).into_owned();
+    buf.push('\n');
+    log.write_all(buf.as_bytes())
+}
+
+fn log_file_name(task: &str) -> String {
+    let name: String = task
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '.' | '-' | '_' => c,
+            _ => '_',
+        })
+        .collect();
+    format!("{name}.log")
+}
+
+/// Names of the runs in the given runs directory, oldest first.
+pub fn list_runs(runs_dir: &Path) -> Result<Vec<String>> {
+    let entries = match fs::read_dir(runs_dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("Can't read {}", runs_dir.display())),
+    };
+    let mut runs: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().is_dir())
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .collect();
+    runs.sort();
+    Ok(runs)
+}
+
+/// Names of the Tasks that have a log in the given run directory.
+pub fn list_task_logs(run_dir: &Path) -> Result<Vec<String>> {
+    let mut tasks: Vec<String> = fs::read_dir(run_dir)
+        .with_context(|| format!("Can't read {}", run_dir.display()))?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter_map(|name| name.strip_suffix(".log").map(str::to_owned))
+        .collect();
+    tasks.sort();
+    Ok(tasks)
+}
+
+/// Directory of the run with the given name, or of the latest run if no
+/// name is given.
+pub fn find_run(runs_dir: &Path, run: Option<&str>) -> Result<PathBuf> {
+    let runs = list_runs(runs_dir)?;
+    if runs.is_empty() {
+        return Err(anyhow!("Nothing has been run yet"));
+    }
+    let found = match run {
+        Some(run) => runs
+            .iter()
+            .find(|r| *r == run)
+            .ok_or_else(|| anyhow!("No run named '{run}'. Available runs: {}", runs.join(", ")))?,
+        None => runs.last().unwrap(),
+    };
+    Ok(runs_dir.join(found))
+}
+
+/// Log of the Task with the given name in the given run, or in the latest
+/// run that has one if no run is given.
+pub fn find_task_log(runs_dir: &Path, run: Option<&str>, task: &str) -> Result<PathBuf> {
+    let file_name = log_file_name(task);
+    if run.is_some() {
+        let run_dir = find_run(runs_dir, run)?;
+        let path = run_dir.join(&file_name);
+        if !path.is_file() {
+            return Err(anyhow!(
+                "No log of task '{task}' in run {}. Available logs: {}",
+                run_dir.file_name().unwrap_or_default().to_string_lossy(),
+                list_task_logs(&run_dir)?.join(", ")
+            ));
+        }
+        return Ok(path);
+    }
+    list_runs(runs_dir)?
+        .iter()
+        .rev()
+        .map(|run| runs_dir.join(run).join(&file_name))
+        .find(|path| path.is_file())
+        .ok_or_else(|| anyhow!("No run has a log of task '{task}'"))
+}
+
+/// The given number of lines at the end of the given log, all of it if no
+/// number is given.
+fn last_lines(content: &str, lines: Option<usize>) -> &str {
+    let start = match lines {
+        Some(0) => content.len(),
+        Some(lines) => content
+            .trim_end_matches('\n')
+            .rmatch_indices('\n')
+            .nth(lines - 1)
+            .map_or(0, |(index, _)| index + 1),
+        None => 0,
+    };
+    &content[start..]
+}
+
+/// Prints the given log, only its last lines if a number of lines is given.
+/// If it is followed, keeps printing what gets appended to it until runer is
+/// interrupted.
+pub async fn show_log(path: &Path, tail: Option<usize>, follow: bool) -> Result<()> {
+    let mut log = File::open(path).with_context(|| format!("Can't open {}", path.display()))?;
+    let mut content = Vec::new();
+    log.read_to_end(&mut content)?;
+    let content = String::from_utf8_lossy(&content);
+    let mut stdout = std::io::stdout();
+    stdout.write_all(last_lines(&content, tail).as_bytes())?;
+    stdout.flush()?;
+
+    if !follow {
+        return Ok(());
+    }
+    let mut position = log.stream_position()?;
+    let mut buf = Vec::new();
+    loop {
+        Timer::after(FOLLOW_INTERVAL).await;
+        let length = fs::metadata(path)?.len();
+        if length < position {
+            // Truncated, start over
+            position = log.seek(SeekFrom::Start(0))?;
+        }
+        buf.clear();
+        position += log.read_to_end(&mut buf)? as u64;
+        if !buf.is_empty() {
+            stdout.write_all(&buf)?;
+            stdout.flush()?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    /// A runs directory with the given runs, each with logs of the given
+    /// Tasks.
+    fn runs_dir(runs: &[(&str, &[&str])]) -> PathBuf {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let dir = std::env::temp_dir().join(format!(
+            "runer-runs-{}-{}",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        for (run, tasks) in runs {
+            fs::create_dir_all(dir.join(run)).unwrap();
+            for task in *tasks {
+                fs::write(dir.join(run).join(log_file_name(task)), format!("{run}\n")).unwrap();
+            }
+        }
+        dir
+    }
+
+    #[test]
+    fn runs_are_found_by_name_or_the_latest_one() {
+        let dir = runs_dir(&[
+            ("20261017T100000Z", &["db"]),
+            ("20261017T090000Z", &["db"]),
+            ("20261017T100000Z-2", &["db"]),
+        ]);
+        let latest = find_run(&dir, None).unwrap();
+        let named = find_run(&dir, Some("20261017T090000Z")).unwrap();
+        let unknown = find_run(&dir, Some("yesterday")).unwrap_err();
+        let none = find_run(&dir.join("missing"), None).unwrap_err();
+        let _ = fs::remove_dir_all(&dir);
+
+        assert_eq!(latest, dir.join("20261017T100000Z-2"));
+        assert_eq!(named, dir.join("20261017T090000Z"));
+        assert_eq!(
+            unknown.to_string(),
+            "No run named 'yesterday'. Available runs: \
+             20261017T090000Z, 20261017T100000Z, 20261017T100000Z-2"
+        );
+        assert_eq!(none.to_string(), "Nothing has been run yet");
+    }
+
+    #[test]
+    fn task_logs_are_found_in_the_latest_run_that_has_one() {
+        let dir = runs_dir(&[
+            ("20261017T090000Z", &["db", "api/v2"]),
+            ("20261017T100000Z", &["db"]),
+        ]);
+        let db = find_task_log(&dir, None, "db").unwrap();
+        let api = find_task_log(&dir, None, "api/v2").unwrap();
+        let missing_in_run = find_task_log(&dir, Some("20261017T100000Z"), "api/v2").unwrap_err();
+        let missing = find_task_log(&dir, None, "web").unwrap_err();
+        let _ = fs::remove_dir_all(&dir);
+
+        assert_eq!(db, dir.join("20261017T100000Z/db.log"));
+        assert_eq!(api, dir.join("20261017T090000Z/api_v2.log"));
+        assert_eq!(
+            missing_in_run.to_string(),
+            "No log of task 'api/v2' in run 20261017T100000Z. Available logs: db"
+        );
+        assert_eq!(missing.to_string(), "No run has a log of task 'web'");
+    }
+
+    #[test]
+    fn the_tail_is_the_given_number_of_last_lines() {
+        let log = "one\ntwo\nthree\n";
+        assert_eq!(last_lines(log, None), log);
+        assert_eq!(last_lines(log, Some(0)), "");
+        assert_eq!(last_lines(log, Some(2)), "two\nthree\n");
+        assert_eq!(last_lines(log, Some(10)), log);
+        // A line that is still being written counts too
+        assert_eq!(last_lines("one\ntwo\nthr", Some(1)), "thr");
+    }
+
+    #[test]
+    fn escape_sequences_are_stripped() {
+        assert_eq!(
+            strip_ansi("\x1b[1;31merror\x1b[0m: failed"),
+            "error: failed"
+        );
+        assert_eq!(strip_ansi("\x1b]0;building\x07step 1/4"), "step 1/4");
+        assert_eq!(strip_ansi("\x1b[2K\x1b[1Gdone"), "done");
+        assert_eq!(strip_ansi("plain [text]"), "plain [text]");
+    }
+
+    #[test]
+    fn tasks_with_the_same_name_share_their_log() {
+        let dir = runs_dir(&[]);
+        let run_log = RunLog::create(&dir).unwrap();
+        // e.g. the image and the container job of the same Blueprint
+        let mut image = run_log.open("api").unwrap();
+        let mut container = run_log.open("api").unwrap();
+        append_line(&mut image, "built me/api").unwrap();
+        append_line(&mut container, "listening on :8080").unwrap();
+        let content = fs::read_to_string(run_log.dir().join("api.log")).unwrap();
+        let _ = fs::remove_dir_all(&dir);
+
+        assert_eq!(content, "built me/api\nlistening on :8080\n");
+    }
+}
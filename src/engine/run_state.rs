@@ -0,0 +1,415 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use chrono::Utc;
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::model::runer::Flow;
+
+use super::scheduler::TaskOutcome;
+
+/// Directory next to the .runer file where runer keeps what it knows about
+/// its runs.
+pub const STATE_DIR: &str = ".runer.d";
+
+/// What runer launched for a .runer file, as it is persisted between
+/// invocations. Only the latest run of every Flow is kept.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct RunState {
+    pub flows: BTreeMap<String, FlowRun>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FlowRun {
+    /// Hash of the content of the .runer file the Flow was run from, to
+    /// tell whether the file changed since.
+    pub rune_hash: String,
+    pub started_at: String,
+    pub finished_at: Option<String>,
+    pub tasks: BTreeMap<u32, TaskRun>,
+    /// Networks that the run created, the ones that existed before it are
+    /// left alone when it is torn down
+    #[serde(default)]
+    pub networks: Vec<NetworkRecord>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TaskRun {
+    pub name: String,
+    pub job: String,
+    pub status: TaskStatus,
+    /// Why the Task failed or got skipped
+    pub reason: Option<String>,
+    pub started_at: Option<String>,
+    pub finished_at: Option<String>,
+    pub container: Option<ContainerRecord>,
+    pub image: Option<ImageRecord>,
+    /// ID of the process that runs the commands of a shell job
+    pub pid: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TaskStatus {
+    Pending,
+    Running,
+    Succeeded,
+    Failed,
+    Skipped,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ContainerRecord {
+    pub name: String,
+    pub id: String,
+    pub runtime: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ImageRecord {
+    pub tag: String,
+    pub id: String,
+    pub runtime: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NetworkRecord {
+    pub name: String,
+    pub runtime: String,
+}
+
+/// Something that a job launched, to be recorded in the state file.
+pub enum Launched {
+    Container(ContainerRecord),
+    Image(ImageRecord),
+    Process(u32),
+}
+
+/// The state file of a .runer file, `.runer.d/state.json` next to it.
+///
+/// Every access holds a lock on `.runer.d/state.lock`, shared for reads and
+/// exclusive for updates, so that concurrent runer invocations don't
+/// overwrite each other's changes. Updates are written to a temporary file
+/// first and renamed over the state file, so readers never see a partially
+/// written one.
+#[derive(Debug)]
+pub struct StateFile {
+    dir: PathBuf,
+    rune_hash: String,
+}
+
+impl StateFile {
+    /// State file of the given .runer file.
+    ///
+    /// Returns error if the .runer file can't be read.
+    pub fn for_rune(rune: &Path) -> Result<Self> {
+        let source = fs::read(rune).with_context(|| format!("Can't read {}", rune.display()))?;
+        let parent = rune
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        Ok(Self {
+            dir: parent.join(STATE_DIR),
+            rune_hash: hash(&source),
+        })
+    }
+
+    pub fn path(&self) -> PathBuf {
+        self.dir.join("state.json")
+    }
+
+    /// Directory that keeps the logs of every run, see
+    /// [RunLog](super::run_log::RunLog).
+    pub fn runs_dir(&self) -> PathBuf {
+        self.dir.join("runs")
+    }
+
+    /// Hash of the .runer file as it is now.
+    pub fn rune_hash(&self) -> &str {
+        &self.rune_hash
+    }
+
+    /// Loads the persisted state, which is empty if nothing was run yet.
+    pub async fn load(&self) -> Result<RunState> {
+        let _lock = self.lock(false).await?;
+        self.read()
+    }
+
+    /// Applies the given change to the persisted state.
+    pub async fn update(&self, change: impl FnOnce(&mut RunState)) -> Result<()> {
+        let _lock = self.lock(true).await?;
+        let mut state = self.read()?;
+        change(&mut state);
+        let temporary = self.dir.join("state.json.tmp");
+        fs::write(&temporary, serde_json::to_vec_pretty(&state)?)?;
+        fs::rename(&temporary, self.path())?;
+        Ok(())
+    }
+
+    /// Waits for the lock, exclusive or shared, which is held until the
+    /// returned file is dropped. Another invocation may hold it for a while,
+    /// so it is waited for on a blocking thread rather than the executor.
+    async fn lock(&self, exclusive: bool) -> Result<File> {
+        fs::create_dir_all(&self.dir)
+            .with_context(|| format!("Can't create {}", self.dir.display()))?;
+        let path = self.dir.join("state.lock");
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .with_context(|| format!("Can't open {}", path.display()))?;
+        smol::unblock(move || {
+            if exclusive {
+                file.lock()
+            } else {
+                file.lock_shared()
+            }
+            .map(|()| file)
+        })
+        .await
+        .with_context(|| format!("Can't lock {}", path.display()))
+    }
+
+    fn read(&self) -> Result<RunState> {
+        let path = self.path();
+        match fs::read(&path) {
+            Ok(content) => serde_json::from_slice(&content)
+                .with_context(|| format!("Malformed state file {}", path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(RunState::default()),
+            Err(e) => Err(e).with_context(|| format!("Can't read {}", path.display())),
+        }
+    }
+
+    /// Records that the given Flow starts, replacing its previous run. The
+    /// networks that the previous run created are carried over, they are
+    /// still owned by the Flow.
+    pub async fn begin_flow(&self, flow: &Flow) {
+        let mut run = FlowRun {
+            rune_hash: self.rune_hash.clone(),
+            started_at: now(),
+            finished_at: None,
+            tasks: flow
+                .tasks
+                .iter()
+                .map(|task| {
+                    let record = TaskRun {
+                        name: task.name.clone(),
+                        job: format!("{}/{}", task.typ, task.job),
+                        status: TaskStatus::Pending,
+                        reason: None,
+                        started_at: None,
+                        finished_at: None,
+                        container: None,
+                        image: None,
+                        pid: None,
+                    };
+                    (task.id, record)
+                })
+                .collect(),
+            networks: Vec::new(),
+        };
+        self.record(|state| {
+            if let Some(previous) = state.flows.remove(&flow.name) {
+                run.networks = previous.networks;
+            }
+            state.flows.insert(flow.name.clone(), run);
+        })
+        .await;
+    }
+
+    /// Records that the run of the given Flow created a network.
+    pub async fn network_created(&self, flow: &str, network: NetworkRecord) {
+        self.record(|state| {
+            let Some(run) = state.flows.get_mut(flow) else {
+                return;
+            };
+            if !run
+                .networks
+                .iter()
+                .any(|n| n.name == network.name && n.runtime == network.runtime)
+            {
+                run.networks.push(network);
+            }
+        })
+        .await;
+    }
+
+    /// Records that the given Flow is finished.
+    pub async fn finish_flow(&self, flow: &str) {
+        self.record(|state| {
+            if let Some(run) = state.flows.get_mut(flow) {
+                run.finished_at = Some(now());
+            }
+        })
+        .await;
+    }
+
+    /// Forgets the given Flow, e.g. once it is torn down.
+    pub async fn remove_flow(&self, flow: &str) {
+        self.record(|state| {
+            state.flows.remove(flow);
+        })
+        .await;
+    }
+
+    pub async fn task_started(&self, flow: &str, task: u32) {
+        self.record_task(flow, task, |run| {
+            run.status = TaskStatus::Running;
+            run.started_at = Some(now());
+        })
+        .await;
+    }
+
+    pub async fn task_launched(&self, flow: &str, task: u32, launched: Launched) {
+        self.record_task(flow, task, |run| match launched {
+            Launched::Container(container) => run.container = Some(container),
+            Launched::Image(image) => run.image = Some(image),
+            Launched::Process(pid) => run.pid = Some(pid),
+        })
+        .await;
+    }
+
+    pub async fn task_finished(&self, flow: &str, task: u32, outcome: &TaskOutcome) {
+        self.record_task(flow, task, |run| {
+            let (status, reason) = match outcome {
+                TaskOutcome::Succeeded => (TaskStatus::Succeeded, None),
+                TaskOutcome::Failed { reason, .. } => (TaskStatus::Failed, Some(reason.clone())),
+                TaskOutcome::Skipped(_) => (TaskStatus::Skipped, Some(outcome.to_string())),
+            };
+            run.status = status;
+            run.reason = reason;
+            if status != TaskStatus::Skipped {
+                run.finished_at = Some(now());
+            }
+        })
+        .await;
+    }
+
+    async fn record_task(&self, flow: &str, task: u32, change: impl FnOnce(&mut TaskRun)) {
+        self.record(|state| {
+            if let Some(run) = state
+                .flows
+                .get_mut(flow)
+                .and_then(|run| run.tasks.get_mut(&task))
+            {
+                change(run);
+            }
+        })
+        .await;
+    }
+
+    /// Failing to persist the state doesn't stop a run, it is only reported.
+    async fn record(&self, change: impl FnOnce(&mut RunState)) {
+        if let Err(e) = self.update(change).await {
+            warn!("Failed to update {}: {e:#}", self.path().display());
+        }
+    }
+}
+
+fn now() -> String {
+    Utc::now().to_rfc3339()
+}
+
+/// 64-bit FNV-1a hash, which stays the same across Rust versions unlike the
+/// hasher of the standard library.
+fn hash(content: &[u8]) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in content {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("{hash:016x}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::error::ErrorKind;
+    use super::*;
+
+    const RUNE: &str = r#"
+name: stack
+tasks:
+    - { id: 1, type: Blueprint, name: db, job: container }
+    - { id: 2, type: Blueprint, name: migrate, job: shell, depends: 1 }
+"#;
+
+    /// A .runer file of the Flow in a directory of its own.
+    fn rune_file() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("runer-state-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let rune = dir.join("stack.runer");
+        fs::write(&rune, RUNE).unwrap();
+        rune
+    }
+
+    #[test]
+    fn recorded_runs_are_reloaded() {
+        let rune = rune_file();
+        let state_file = StateFile::for_rune(&rune).unwrap();
+        let flow: Flow = serde_yaml::from_str(RUNE).unwrap();
+        smol::block_on(async {
+            assert!(state_file.load().await.unwrap().flows.is_empty());
+
+            state_file.begin_flow(&flow).await;
+            state_file.task_started("stack", 1).await;
+            let container = ContainerRecord {
+                name: "db".to_owned(),
+                id: "c0ffee".to_owned(),
+                runtime: "docker".to_owned(),
+            };
+            state_file
+                .task_launched("stack", 1, Launched::Container(container))
+                .await;
+            for _ in 0..2 {
+                let network = NetworkRecord {
+                    name: "backend".to_owned(),
+                    runtime: "docker".to_owned(),
+                };
+                state_file.network_created("stack", network).await;
+            }
+            state_file
+                .task_finished("stack", 1, &TaskOutcome::Succeeded)
+                .await;
+            let failure = TaskOutcome::Failed {
+                reason: "exit status: 1".to_owned(),
+                kind: ErrorKind::Task,
+            };
+            state_file.task_finished("stack", 2, &failure).await;
+            state_file.finish_flow("stack").await;
+
+            let reloaded =
+                StateFile::for_rune(&state_file.dir.parent().unwrap().join("stack.runer"))
+                    .unwrap()
+                    .load()
+                    .await
+                    .unwrap();
+            let run = &reloaded.flows["stack"];
+            assert_eq!(run.rune_hash, state_file.rune_hash());
+            assert!(run.finished_at.is_some());
+            assert_eq!(run.networks.len(), 1);
+            let db = &run.tasks[&1];
+            assert_eq!(db.status, TaskStatus::Succeeded);
+            assert_eq!(db.container.as_ref().unwrap().id, "c0ffee");
+            assert!(db.started_at.is_some() && db.finished_at.is_some());
+            let migrate = &run.tasks[&2];
+            assert_eq!(migrate.status, TaskStatus::Failed);
+            assert_eq!(migrate.reason.as_deref(), Some("exit status: 1"));
+
+            // A new run replaces the tasks, but still owns the networks
+            state_file.begin_flow(&flow).await;
+            let run = &state_file.load().await.unwrap().flows["stack"];
+            assert_eq!(run.tasks[&1].status, TaskStatus::Pending);
+            assert!(run.finished_at.is_none());
+            assert_eq!(run.networks[0].name, "backend");
+
+            state_file.remove_flow("stack").await;
+            assert!(state_file.load().await.unwrap().flows.is_empty());
+        });
+        let _ = fs::remove_dir_all(rune.parent().unwrap());
+    }
+}
@@ -0,0 +1,1207 @@
+//! Runtime that talks to the Docker Engine API over its Unix socket, instead
+//! of spawning the `docker` command line.
+//!
+//! The socket is `/var/run/docker.sock` unless `DOCKER_HOST` points to
+//! another one, e.g. `DOCKER_HOST=unix:///run/user/1000/docker.sock`. Any
+//! server that speaks the subset of the API below can stand in for the
+//! daemon.
+
+use std::io::Write;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use log::{info, warn};
+use serde_json::{json, Map, Value};
+use smol::net::unix::UnixStream;
+
+use crate::model::runer::{Container, Image, Network, Volume};
+
+use super::super::duration::parse_duration;
+use super::super::http::{self, Request, Response};
+use super::super::output::{follow, LineSink};
+use super::cli::parse_inspect;
+use super::{
+    volume_helper, ContainerInfo, ContainerRuntime, ExecOutput, CLEAR_VOLUME_COMMAND,
+    VOLUME_HELPER_TARGET,
+};
+
+const DEFAULT_SOCKET: &str = "/var/run/docker.sock";
+
+/// Status of the daemon's answer to starting or stopping a container that is
+/// already started or stopped.
+const NOT_MODIFIED: u16 = 304;
+
+/// Container runtime backed by the Docker Engine API.
+pub struct ApiRuntime {
+    socket: String,
+}
+
+impl ApiRuntime {
+    pub fn new(socket: impl Into<String>) -> Self {
+        Self {
+            socket: socket.into(),
+        }
+    }
+
+    /// Creates an ApiRuntime that connects to the socket `DOCKER_HOST` points
+    /// to, or to the default socket of the daemon.
+    pub fn from_env() -> Self {
+        match std::env::var("DOCKER_HOST") {
+            Ok(host) if host.starts_with("unix://") => Self::new(&host["unix://".len()..]),
+            Ok(host) if !host.is_empty() => {
+                warn!("DOCKER_HOST={host} is not a unix socket, using {DEFAULT_SOCKET}");
+                Self::new(DEFAULT_SOCKET)
+            }
+            _ => Self::new(DEFAULT_SOCKET),
+        }
+    }
+
+    /// Sends the given Request to the daemon.
+    async fn send(&self, request: Request) -> Result<Response<UnixStream>> {
+        let stream = UnixStream::connect(&self.socket).await.with_context(|| {
+            format!("Failed to connect to the Docker daemon at {}", self.socket)
+        })?;
+        http::send(stream, "docker", &request).await
+    }
+
+    /// Sends the given Request and returns its JSON response body.
+    ///
+    /// Returns error with the message of the daemon if the response status
+    /// is not a success.
+    async fn call(&self, request: Request) -> Result<Value> {
+        self.call_accepting(request, &[]).await
+    }
+
+    /// Like [call](Self::call), but the given statuses count as a success
+    /// too, e.g. the 304 that the daemon answers with when a container is
+    /// already in the state it is asked to put it in.
+    async fn call_accepting(&self, request: Request, accepted: &[u16]) -> Result<Value> {
+        let description = format!("{} {}", request.method, request.path);
+        let response = self.send(request).await?;
+        let status = response.status;
+        let success = response.is_success() || accepted.contains(&status);
+        let body = response.bytes().await?;
+        if !success {
+            return Err(anyhow!(
+                "{description} failed with {status}: {}",
+                error_message(&body)
+            ));
+        }
+        if body.is_empty() {
+            return Ok(Value::Null);
+        }
+        serde_json::from_slice(&body)
+            .with_context(|| format!("Unexpected response to {description}"))
+    }
+
+    /// Sends the given Request, whose response is a stream of JSON messages
+    /// (e.g. build or pull progress), and passes the progress to `progress`
+    /// as it arrives.
+    ///
+    /// Returns error if the stream reports an error.
+    async fn call_progress(
+        &self,
+        request: Request,
+        progress: &(dyn Fn(&str) + Sync),
+    ) -> Result<()> {
+        let description = format!("{} {}", request.method, request.path);
+        let mut response = self.send(request).await?;
+        if !response.is_success() {
+            let status = response.status;
+            let body = response.bytes().await?;
+            return Err(anyhow!(
+                "{description} failed with {status}: {}",
+                error_message(&body)
+            ));
+        }
+        let mut lines = JsonLines::default();
+        while let Some(chunk) = response.next_chunk().await? {
+            for message in lines.push(&chunk) {
+                if let Some(error) = message["error"].as_str() {
+                    return Err(anyhow!("{}", error.trim()));
+                }
+                if let Some(stream) = message["stream"].as_str() {
+                    for line in stream.lines().filter(|line| !line.trim().is_empty()) {
+                        progress(line);
+                    }
+                } else if let Some(status) = message["status"].as_str() {
+                    progress(status);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Pulls the given image from its registry.
+    async fn pull(&self, image: &str) -> Result<()> {
+        info!("Pulling {image}");
+        let (name, tag) = split_tag(image);
+        self.call_progress(
+            Request::new(
+                "POST",
+                format!(
+                    "/images/create?fromImage={}&tag={}",
+                    encode(name),
+                    encode(tag)
+                ),
+            ),
+            &|status| info!("{status}"),
+        )
+        .await
+    }
+
+    /// Creates the given Container without starting it. Returns the ID of
+    /// the created container.
+    async fn create(&self, container: &Container) -> Result<String> {
+        let request = || {
+            Request::new(
+                "POST",
+                format!("/containers/create?name={}", encode(&container.name)),
+            )
+            .json(&create_body(container))
+        };
+        let created = match self.call(request()).await {
+            Err(e) if e.to_string().contains("No such image") => {
+                self.pull(&container.image).await?;
+                self.call(request()).await?
+            }
+            created => created?,
+        };
+        for warning in created["Warnings"].as_array().into_iter().flatten() {
+            warn!(
+                "{}: {}",
+                container.name,
+                warning.as_str().unwrap_or_default()
+            );
+        }
+        created["Id"]
+            .as_str()
+            .map(str::to_owned)
+            .ok_or_else(|| anyhow!("Docker daemon didn't return the ID of {}", container.name))
+    }
+
+    /// Sends the archive Request built for the ID of a short-lived container
+    /// that mounts the named volume with the given name, and hands the
+    /// response body over to the given sink as it arrives. The container is
+    /// removed afterwards, whether the copy succeeds or not.
+    async fn copy_through_helper(
+        &self,
+        volume: &str,
+        copy: impl FnOnce(&str) -> Request,
+        sink: impl FnMut(&[u8]) -> Result<()>,
+    ) -> Result<()> {
+        let helper = volume_helper(volume);
+        let id = self.create(&helper).await?;
+        let copied = self.copy(copy(&id), sink).await;
+        self.call(Request::new("DELETE", format!("/containers/{id}?force=1")))
+            .await?;
+        copied
+    }
+
+    /// Deletes the content of the named volume with the given name, through
+    /// a short-lived container that mounts it.
+    async fn clear_volume(&self, volume: &str) -> Result<()> {
+        let helper = Container {
+            entrypoint: Some(CLEAR_VOLUME_COMMAND.map(str::to_owned).to_vec()),
+            ..volume_helper(volume)
+        };
+        let id = self.create(&helper).await?;
+        let cleared = self.run_to_completion(&id).await;
+        self.call(Request::new("DELETE", format!("/containers/{id}?force=1")))
+            .await?;
+        cleared
+    }
+
+    /// Starts the created container with the given ID and waits until it
+    /// exits.
+    ///
+    /// Returns error if it exits with a non-zero code.
+    async fn run_to_completion(&self, id: &str) -> Result<()> {
+        self.call(Request::new("POST", format!("/containers/{id}/start")))
+            .await?;
+        let waited = self
+            .call(Request::new("POST", format!("/containers/{id}/wait")))
+            .await?;
+        match waited["StatusCode"].as_i64() {
+            Some(0) => Ok(()),
+            code => Err(anyhow!(
+                "{id} exited with code {}",
+                code.map_or_else(|| "unknown".to_owned(), |code| code.to_string())
+            )),
+        }
+    }
+
+    /// Sends the given Request and hands its body over to the given sink as
+    /// it arrives.
+    async fn copy(
+        &self,
+        request: Request,
+        mut sink: impl FnMut(&[u8]) -> Result<()>,
+    ) -> Result<()> {
+        let description = format!("{} {}", request.method, request.path);
+        let mut response = self.send(request).await?;
+        if !response.is_success() {
+            let status = response.status;
+            return Err(anyhow!(
+                "{description} failed with {status}: {}",
+                error_message(&response.bytes().await?)
+            ));
+        }
+        while let Some(chunk) = response.next_chunk().await? {
+            sink(&chunk)?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ContainerRuntime for ApiRuntime {
+    fn name(&self) -> &'static str {
+        "docker-api"
+    }
+
+    fn describe_build(&self, image: &Image) -> String {
+        format!("POST {} (context: {})", build_path(image), image.context)
+    }
+
+    fn describe_run(&self, container: &Container) -> String {
+        let mut lines = vec![format!(
+            "POST /containers/create?name={} {}",
+            encode(&container.name),
+            create_body(container)
+        )];
+        for network in container.networks.iter().flatten().skip(1) {
+            lines.push(format!(
+                "POST /networks/{}/connect {}",
+                encode(&network.name),
+                connect_body(&container.name, &network.aliases)
+            ));
+        }
+        lines.push(format!(
+            "POST /containers/{}/start",
+            encode(&container.name)
+        ));
+        lines.join("\n")
+    }
+
+    fn describe_network(&self, name: &str, network: &Network) -> String {
+        format!("POST /networks/create {}", network_body(name, network))
+    }
+
+    fn describe_volume(&self, name: &str, volume: &Volume) -> String {
+        format!("POST /volumes/create {}", volume_body(name, volume))
+    }
+
+    async fn build(&self, image: &Image, sink: LineSink) -> Result<()> {
+        let context = archive(Path::new(&image.context))
+            .with_context(|| format!("Failed to archive build context {}", image.context))?;
+        self.call_progress(
+            Request::new("POST", build_path(image)).body("application/x-tar", context),
+            &*sink,
+        )
+        .await
+        .with_context(|| format!("Failed to build {}", image.tag))
+    }
+
+    async fn image_id(&self, tag: &str) -> Result<String> {
+        let inspected = self
+            .call(Request::new("GET", format!("/images/{}/json", encode(tag))))
+            .await?;
+        inspected["Id"]
+            .as_str()
+            .map(str::to_owned)
+            .ok_or_else(|| anyhow!("No ID in the inspection of image {tag}"))
+    }
+
+    async fn remove_image(&self, tag: &str) -> Result<()> {
+        self.call(Request::new("DELETE", format!("/images/{}", encode(tag))))
+            .await?;
+        Ok(())
+    }
+
+    async fn run(&self, container: &Container) -> Result<String> {
+        let id = self.create(container).await?;
+        // A container can only join its first network on <create>, the rest
+        // of them are connected before it gets started.
+        for network in container.networks.iter().flatten().skip(1) {
+            self.call(
+                Request::new(
+                    "POST",
+                    format!("/networks/{}/connect", encode(&network.name)),
+                )
+                .json(&connect_body(&id, &network.aliases)),
+            )
+            .await
+            .with_context(|| format!("Failed to connect {} to {}", container.name, network.name))?;
+        }
+        self.call_accepting(
+            Request::new("POST", format!("/containers/{id}/start")),
+            &[NOT_MODIFIED],
+        )
+        .await
+        .with_context(|| format!("Failed to start {}", container.name))?;
+        info!("Started {} ({})", container.name, id);
+        Ok(id)
+    }
+
+    async fn create_network(&self, name: &str, network: &Network) -> Result<bool> {
+        let response = self
+            .send(Request::new("GET", format!("/networks/{}", encode(name))))
+            .await?;
+        if response.is_success() {
+            return Ok(false);
+        }
+        let created = self
+            .send(Request::new("POST", "/networks/create").json(&network_body(name, network)))
+            .await?;
+        match created.status {
+            201 | 200 => Ok(true),
+            // Someone else created it in the meantime
+            409 => Ok(false),
+            status => Err(anyhow!(
+                "Failed to create network {name} ({status}): {}",
+                error_message(&created.bytes().await?)
+            )),
+        }
+    }
+
+    async fn remove_network(&self, name: &str) -> Result<()> {
+        self.call(Request::new(
+            "DELETE",
+            format!("/networks/{}", encode(name)),
+        ))
+        .await?;
+        Ok(())
+    }
+
+    async fn create_volume(&self, name: &str, volume: &Volume) -> Result<bool> {
+        let response = self
+            .send(Request::new("GET", format!("/volumes/{}", encode(name))))
+            .await?;
+        if response.is_success() {
+            return Ok(false);
+        }
+        self.call(Request::new("POST", "/volumes/create").json(&volume_body(name, volume)))
+            .await?;
+        Ok(true)
+    }
+
+    async fn remove_volume(&self, name: &str) -> Result<()> {
+        self.call(Request::new("DELETE", format!("/volumes/{}", encode(name))))
+            .await?;
+        Ok(())
+    }
+
+    async fn backup_volume(&self, name: &str, file: &Path) -> Result<()> {
+        let response = self
+            .send(Request::new("GET", format!("/volumes/{}", encode(name))))
+            .await?;
+        if !response.is_success() {
+            return Err(anyhow!("No such volume: {name}"));
+        }
+        let mut output = std::fs::File::create(file)
+            .with_context(|| format!("Failed to create {}", file.display()))?;
+        let path = encode(&format!("{VOLUME_HELPER_TARGET}/."));
+        self.copy_through_helper(
+            name,
+            |id| Request::new("GET", format!("/containers/{id}/archive?path={path}")),
+            |chunk| Ok(output.write_all(chunk)?),
+        )
+        .await
+        .with_context(|| format!("Failed to back up {name}"))
+    }
+
+    async fn restore_volume(&self, name: &str, file: &Path) -> Result<()> {
+        let archive =
+            std::fs::read(file).with_context(|| format!("Failed to read {}", file.display()))?;
+        // <archive> only extracts on top of what the volume holds
+        self.clear_volume(name)
+            .await
+            .with_context(|| format!("Failed to clear {name}"))?;
+        let path = encode(VOLUME_HELPER_TARGET);
+        self.copy_through_helper(
+            name,
+            |id| {
+                Request::new("PUT", format!("/containers/{id}/archive?path={path}"))
+                    .body("application/x-tar", archive)
+            },
+            |_| Ok(()),
+        )
+        .await
+        .with_context(|| format!("Failed to restore {name}"))
+    }
+
+    async fn stop(&self, container: &str) -> Result<()> {
+        self.call_accepting(
+            Request::new("POST", format!("/containers/{}/stop", encode(container))),
+            &[NOT_MODIFIED],
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn remove(&self, container: &str) -> Result<()> {
+        self.call(Request::new(
+            "DELETE",
+            format!("/containers/{}", encode(container)),
+        ))
+        .await?;
+        Ok(())
+    }
+
+    async fn inspect(&self, container: &str) -> Result<ContainerInfo> {
+        let inspected = self
+            .call(Request::new(
+                "GET",
+                format!("/containers/{}/json", encode(container)),
+            ))
+            .await?;
+        Ok(parse_inspect(&inspected))
+    }
+
+    async fn logs(&self, container: &str) -> Result<String> {
+        let response = self
+            .send(Request::new(
+                "GET",
+                format!("/containers/{}/logs?stdout=1&stderr=1", encode(container)),
+            ))
+            .await?;
+        if !response.is_success() {
+            let status = response.status;
+            let body = response.bytes().await?;
+            return Err(anyhow!(
+                "Failed to get the logs of {container} ({status}): {}",
+                error_message(&body)
+            ));
+        }
+        Ok(demultiplex(&response.bytes().await?))
+    }
+
+    async fn follow_logs(&self, container: &str, sink: LineSink) -> Result<()> {
+        let mut response = self
+            .send(Request::new(
+                "GET",
+                format!(
+                    "/containers/{}/logs?follow=1&stdout=1&stderr=1",
+                    encode(container)
+                ),
+            ))
+            .await?;
+        if !response.is_success() {
+            let status = response.status;
+            let body = response.bytes().await?;
+            return Err(anyhow!(
+                "Failed to follow the logs of {container} ({status}): {}",
+                error_message(&body)
+            ));
+        }
+        follow(smol::spawn(async move {
+            let mut lines = LogLines::default();
+            while let Ok(Some(chunk)) = response.next_chunk().await {
+                for line in lines.push(&chunk) {
+                    sink(&line);
+                }
+            }
+        }));
+        Ok(())
+    }
+
+    async fn wait_for_event(&self, container: &str, since: SystemTime) -> Result<Option<String>> {
+        let since = since.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let filters = json!({
+            "type": ["container"],
+            "container": [container],
+            "event": ["die", "health_status"],
+        });
+        let mut response = self
+            .send(Request::new(
+                "GET",
+                format!(
+                    "/events?since={}.{:09}&filters={}",
+                    since.as_secs(),
+                    since.subsec_nanos(),
+                    encode(&filters.to_string())
+                ),
+            ))
+            .await?;
+        if !response.is_success() {
+            let status = response.status;
+            let body = response.bytes().await?;
+            return Err(anyhow!(
+                "Failed to watch the events of {container} ({status}): {}",
+                error_message(&body)
+            ));
+        }
+        let mut lines = JsonLines::default();
+        while let Some(chunk) = response.next_chunk().await? {
+            for event in lines.push(&chunk) {
+                let action = event["Action"].as_str().unwrap_or_default();
+                if action == "die" || action.starts_with("health_status") {
+                    return Ok(Some(action.to_owned()));
+                }
+            }
+        }
+        Err(anyhow!(
+            "Docker daemon closed the event stream of {container}"
+        ))
+    }
+
+    async fn exec(&self, container: &str, command: &[String]) -> Result<ExecOutput> {
+        let created = self
+            .call(
+                Request::new("POST", format!("/containers/{}/exec", encode(container)))
+                    .json(&json!({ "AttachStdout": true, "AttachStderr": true, "Cmd": command })),
+            )
+            .await?;
+        let id = created["Id"]
+            .as_str()
+            .ok_or_else(|| anyhow!("Docker daemon didn't return the ID of the exec"))?;
+
+        let response = self
+            .send(
+                Request::new("POST", format!("/exec/{id}/start"))
+                    .json(&json!({ "Detach": false, "Tty": false })),
+            )
+            .await?;
+        let success = response.is_success();
+        let body = response.bytes().await?;
+        if !success {
+            return Err(anyhow!(
+                "Failed to exec in {container}: {}",
+                error_message(&body)
+            ));
+        }
+
+        let inspected = self
+            .call(Request::new("GET", format!("/exec/{id}/json")))
+            .await?;
+        Ok(ExecOutput {
+            exit_code: inspected["ExitCode"].as_i64().unwrap_or(-1),
+            output: demultiplex(&body),
+        })
+    }
+}
+
+/// Path of the <build> request of the given Image.
+///
+/// Command line options of the Image that have an API counterpart are
+/// translated, the rest are ignored with a warning.
+fn build_path(image: &Image) -> String {
+    let mut query = vec![("t".to_owned(), image.tag.clone())];
+
+    let options = image.options.as_deref().unwrap_or_default();
+    let mut options = options.iter().peekable();
+    while let Some(option) = options.next() {
+        let (flag, inline) = match option.split_once('=') {
+            Some((flag, value)) => (flag, Some(value.to_owned())),
+            None => (option.as_str(), None),
+        };
+        let mut value = || inline.clone().or_else(|| options.next().cloned());
+        match flag {
+            "--rm" => query.push(("rm".to_owned(), "1".to_owned())),
+            "--no-cache" => query.push(("nocache".to_owned(), "1".to_owned())),
+            "--pull" => query.push(("pull".to_owned(), "1".to_owned())),
+            "-q" | "--quiet" => query.push(("q".to_owned(), "1".to_owned())),
+            "-f" | "--file" => query.push(("dockerfile".to_owned(), value().unwrap_or_default())),
+            "--target" => query.push(("target".to_owned(), value().unwrap_or_default())),
+            "--platform" => query.push(("platform".to_owned(), value().unwrap_or_default())),
+            _ => warn!("Build option {option} is not supported by the docker-api runtime"),
+        }
+    }
+
+    if let Some(build_args) = &image.build_args {
+        let build_args: Map<String, Value> = build_args
+            .iter()
+            .map(|arg| match arg.split_once('=') {
+                Some((key, value)) => (key.to_owned(), Value::from(value)),
+                None => (
+                    arg.clone(),
+                    Value::from(std::env::var(arg).unwrap_or_default()),
+                ),
+            })
+            .collect();
+        query.push((
+            "buildargs".to_owned(),
+            Value::Object(build_args).to_string(),
+        ));
+    }
+
+    let query: Vec<String> = query
+        .iter()
+        .map(|(key, value)| format!("{key}={}", encode(value)))
+        .collect();
+    format!("/build?{}", query.join("&"))
+}
+
+/// Body of the <create> request of the given Container.
+///
+/// Command line options of the Container that have an API counterpart are
+/// translated, the rest are ignored with a warning. An empty <entrypoint>
+/// keeps the entrypoint of the image, and a health check with an empty
+/// command is left out.
+fn create_body(container: &Container) -> Value {
+    let mut config = json!({ "Image": container.image });
+    let mut host_config = json!({});
+
+    if let Some(network) = container.networks.iter().flatten().next() {
+        host_config["NetworkMode"] = json!(network.name);
+        config["NetworkingConfig"] = json!({
+            "EndpointsConfig": { &network.name: { "Aliases": network.aliases } }
+        });
+    }
+
+    if let Some(env) = &container.env {
+        config["Env"] = env.iter().map(|(k, v)| format!("{k}={v}")).collect();
+    }
+
+    if let Some(ports) = &container.ports {
+        let mut exposed = Map::new();
+        let mut bindings = Map::new();
+        for mapping in ports {
+            for port in mapping.container.ports() {
+                let key = format!("{port}/{}", mapping.protocol);
+                let mut binding = json!({
+                    "HostPort": mapping.host_port(port).map(|p| p.to_string()).unwrap_or_default()
+                });
+                if let Some(ip) = mapping.host_ip {
+                    binding["HostIp"] = json!(ip.to_string());
+                }
+                exposed.insert(key.clone(), json!({}));
+                match bindings.get_mut(&key) {
+                    Some(Value::Array(existing)) => existing.push(binding),
+                    _ => {
+                        bindings.insert(key, json!([binding]));
+                    }
+                }
+            }
+        }
+        config["ExposedPorts"] = Value::Object(exposed);
+        host_config["PortBindings"] = Value::Object(bindings);
+    }
+
+    if let Some(volumes) = &container.volumes {
+        host_config["Mounts"] = volumes
+            .iter()
+            .map(|mount| {
+                let mut body = json!({
+                    "Type": mount.typ.to_string(),
+                    "Target": mount.target,
+                    "ReadOnly": mount.read_only,
+                });
+                match mount.host_path() {
+                    Some(path) => body["Source"] = json!(path.display().to_string()),
+                    None => {
+                        if let Some(source) = &mount.source {
+                            body["Source"] = json!(source);
+                        }
+                    }
+                }
+                body
+            })
+            .collect();
+    }
+
+    // An empty <entrypoint> or <healthcheck> command is left out, the
+    // validator rejects them before a Container gets this far
+    if let Some(entrypoint) = container.entrypoint.as_ref().filter(|e| !e.is_empty()) {
+        config["Entrypoint"] = json!(entrypoint);
+    }
+
+    // Health checks other than container commands are run on the host by
+    // the engine
+    if let Some(hc) = &container.hc {
+        if let Some(command) = hc.container_command().filter(|c| !c.is_empty()) {
+            let mut healthcheck = json!({ "Test": ["CMD-SHELL", command] });
+            if let Some(interval) = hc.interval.as_deref().and_then(parse_duration) {
+                healthcheck["Interval"] = json!(interval.as_nanos() as u64);
+            }
+            if let Some(retries) = hc.retries {
+                healthcheck["Retries"] = json!(retries);
+            }
+            config["Healthcheck"] = healthcheck;
+        }
+    }
+
+    let options = container.options.as_deref().unwrap_or_default();
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        let (flag, inline) = match option.split_once('=') {
+            Some((flag, value)) => (flag, Some(value.to_owned())),
+            None => (option.as_str(), None),
+        };
+        let mut value = || inline.clone().or_else(|| options.next().cloned());
+        match flag {
+            "--rm" => host_config["AutoRemove"] = json!(true),
+            "--privileged" => host_config["Privileged"] = json!(true),
+            "--restart" => {
+                host_config["RestartPolicy"] = json!({ "Name": value().unwrap_or_default() })
+            }
+            "-u" | "--user" => config["User"] = json!(value().unwrap_or_default()),
+            "-w" | "--workdir" => config["WorkingDir"] = json!(value().unwrap_or_default()),
+            "-h" | "--hostname" => config["Hostname"] = json!(value().unwrap_or_default()),
+            _ => warn!("Container option {option} is not supported by the docker-api runtime"),
+        }
+    }
+
+    config["HostConfig"] = host_config;
+    config
+}
+
+/// Body of the <network connect> request of the given container.
+fn connect_body(container: &str, aliases: &[String]) -> Value {
+    json!({ "Container": container, "EndpointConfig": { "Aliases": aliases } })
+}
+
+/// Body of the <volume create> request of the given Volume.
+fn volume_body(name: &str, volume: &Volume) -> Value {
+    let mut body = json!({ "Name": name, "Labels": volume.labels });
+    if let Some(driver) = &volume.driver {
+        body["Driver"] = json!(driver);
+    }
+    body
+}
+
+/// Body of the <network create> request of the given Network.
+fn network_body(name: &str, network: &Network) -> Value {
+    let mut body = json!({ "Name": name, "Internal": network.internal });
+    if let Some(driver) = &network.driver {
+        body["Driver"] = json!(driver);
+    }
+    if let Some(subnet) = &network.subnet {
+        body["IPAM"] = json!({ "Config": [{ "Subnet": subnet }] });
+    }
+    body
+}
+
+/// Archives the given build context directory as a tar stream. Sockets,
+/// FIFOs and other special files can't be archived, so they are left out.
+fn archive(context: &Path) -> Result<Vec<u8>> {
+    let mut builder = tar::Builder::new(Vec::new());
+    builder.follow_symlinks(false);
+    let mut directories = vec![context.to_path_buf()];
+    while let Some(directory) = directories.pop() {
+        for entry in std::fs::read_dir(&directory)? {
+            let entry = entry?;
+            let path = entry.path();
+            let name = path.strip_prefix(context)?;
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                builder.append_dir(name, &path)?;
+                directories.push(path);
+            } else if file_type.is_file() || file_type.is_symlink() {
+                builder.append_path_with_name(&path, name)?;
+            }
+        }
+    }
+    Ok(builder.into_inner()?)
+}
+
+/// Splits the tag out of the given image reference. Registry ports are not
+/// mistaken for tags.
+fn split_tag(image: &str) -> (&str, &str) {
+    match image.rsplit_once(':') {
+        Some((name, tag)) if !tag.contains('/') => (name, tag),
+        _ => (image, "latest"),
+    }
+}
+
+/// Percent-encodes the given query or path component.
+fn encode(component: &str) -> String {
+    component
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+/// Extracts the message out of an error response of the daemon.
+fn error_message(body: &[u8]) -> String {
+    serde_json::from_slice::<Value>(body)
+        .ok()
+        .and_then(|error| error["message"].as_str().map(str::to_owned))
+        .unwrap_or_else(|| String::from_utf8_lossy(body).trim().to_owned())
+}
+
+/// Joins the frames of a multiplexed stdout/stderr stream, as it is returned
+/// for containers without a TTY. Every frame starts with an 8 byte header,
+/// whose last 4 bytes are the big endian length of the frame.
+fn demultiplex(stream: &[u8]) -> String {
+    let mut output = Vec::new();
+    let mut rest = stream;
+    while rest.len() >= 8 {
+        let length = u32::from_be_bytes([rest[4], rest[5], rest[6], rest[7]]) as usize;
+        let end = (8 + length).min(rest.len());
+        output.extend_from_slice(&rest[8..end]);
+        rest = &rest[end..];
+    }
+    String::from_utf8_lossy(&output).into_owned()
+}
+
+/// Splits the multiplexed output of a container, whose frames and lines may
+/// be split across chunks, into lines.
+#[derive(Default)]
+struct LogLines {
+    frames: Vec<u8>,
+    line: Vec<u8>,
+}
+
+impl LogLines {
+    /// Appends the given chunk, and returns the lines it completes.
+    fn push(&mut self, chunk: &[u8]) -> Vec<String> {
+        self.frames.extend_from_slice(chunk);
+        while self.frames.len() >= 8 {
+            let length = u32::from_be_bytes([
+                self.frames[4],
+                self.frames[5],
+                self.frames[6],
+                self.frames[7],
+            ]) as usize;
+            if self.frames.len() < 8 + length {
+                break;
+            }
+            self.line.extend(self.frames.drain(..8 + length).skip(8));
+        }
+        let mut lines = Vec::new();
+        while let Some(newline) = self.line.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.line.drain(..=newline).collect();
+            lines.push(String::from_utf8_lossy(&line[..newline]).into_owned());
+        }
+        lines
+    }
+}
+
+/// Splits a stream of newline delimited JSON messages, whose lines may be
+/// split across chunks.
+#[derive(Default)]
+struct JsonLines {
+    pending: Vec<u8>,
+}
+
+impl JsonLines {
+    /// Appends the given chunk, and returns the messages it completes.
+    fn push(&mut self, chunk: &[u8]) -> Vec<Value> {
+        self.pending.extend_from_slice(chunk);
+        let mut messages = Vec::new();
+        while let Some(newline) = self.pending.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.pending.drain(..=newline).collect();
+            match serde_json::from_slice(&line) {
+                Ok(message) => messages.push(message),
+                Err(_) if line.iter().all(u8::is_ascii_whitespace) => {}
+                Err(e) => warn!("Ignoring malformed message from the Docker daemon: {e}"),
+            }
+        }
+        messages
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    use smol::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+    use std::time::{Duration, Instant};
+
+    use smol::net::unix::UnixListener;
+
+    use super::super::super::readiness::wait_until_ready;
+    use super::*;
+
+    /// Stand-in for the daemon, that answers every request with the
+    /// response the given function returns for its request line, e.g.
+    /// `POST /containers/db/stop`.
+    fn stub(respond: impl Fn(&str) -> Vec<u8> + Send + 'static) -> (ApiRuntime, PathBuf) {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let socket = std::env::temp_dir().join(format!(
+            "runer-api-{}-{}.sock",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = std::fs::remove_file(&socket);
+        let listener = UnixListener::bind(&socket).unwrap();
+        smol::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let mut reader = BufReader::new(stream);
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).await.unwrap();
+                let mut length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).await.unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                    if let Some(value) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                        length = value.trim().parse().unwrap();
+                    }
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).await.unwrap();
+                let request_line = request_line.rsplit_once(' ').unwrap().0;
+                let mut stream = reader.into_inner();
+                stream.write_all(&respond(request_line)).await.unwrap();
+            }
+        })
+        .detach();
+        (ApiRuntime::new(socket.display().to_string()), socket)
+    }
+
+    fn response(status: &str, body: &str) -> Vec<u8> {
+        format!(
+            "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        )
+        .into_bytes()
+    }
+
+    /// A response whose body is sent in the given chunks.
+    fn chunked(chunks: &[&[u8]]) -> Vec<u8> {
+        let mut response = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n".to_vec();
+        for chunk in chunks {
+            response.extend(format!("{:x}\r\n", chunk.len()).into_bytes());
+            response.extend(*chunk);
+            response.extend(b"\r\n");
+        }
+        response.extend(b"0\r\n\r\n");
+        response
+    }
+
+    /// A frame of a multiplexed stdout/stderr stream.
+    fn frame(stream: u8, data: &str) -> Vec<u8> {
+        let mut frame = vec![stream, 0, 0, 0];
+        frame.extend((data.len() as u32).to_be_bytes());
+        frame.extend(data.as_bytes());
+        frame
+    }
+
+    #[test]
+    fn call_returns_the_json_body() {
+        let (runtime, socket) = stub(|request| match request {
+            "GET /images/me%2Fapi%3Alatest/json" => response("200 OK", r#"{"Id":"sha256:1234"}"#),
+            _ => response("404 Not Found", "{}"),
+        });
+        let id = smol::block_on(runtime.image_id("me/api:latest")).unwrap();
+        assert_eq!(id, "sha256:1234");
+        std::fs::remove_file(socket).unwrap();
+    }
+
+    #[test]
+    fn call_reports_the_message_of_the_daemon() {
+        let (runtime, socket) = stub(|request| match request {
+            "DELETE /images/missing" => {
+                response("404 Not Found", r#"{"message":"No such image: missing"}"#)
+            }
+            _ => response("500 Internal Server Error", "  daemon is broken\n"),
+        });
+        let error = smol::block_on(runtime.remove_image("missing")).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "DELETE /images/missing failed with 404: No such image: missing"
+        );
+        let error = smol::block_on(runtime.remove_network("any")).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "DELETE /networks/any failed with 500: daemon is broken"
+        );
+        std::fs::remove_file(socket).unwrap();
+    }
+
+    #[test]
+    fn stopping_a_stopped_container_succeeds() {
+        let (runtime, socket) = stub(|request| match request {
+            "POST /containers/db/stop" => response("304 Not Modified", ""),
+            _ => response("404 Not Found", "{}"),
+        });
+        smol::block_on(runtime.stop("db")).unwrap();
+        std::fs::remove_file(socket).unwrap();
+    }
+
+    #[test]
+    fn exec_returns_the_output_and_the_exit_code() {
+        let (runtime, socket) = stub(|request| match request {
+            "POST /containers/db/exec" => response("201 Created", r#"{"Id":"e1"}"#),
+            "POST /exec/e1/start" => {
+                let mut body = frame(1, "accepting\n");
+                body.extend(frame(2, "slowly\n"));
+                let mut response =
+                    format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n", body.len())
+                        .into_bytes();
+                response.extend(body);
+                response
+            }
+            "GET /exec/e1/json" => response("200 OK", r#"{"ExitCode":3}"#),
+            _ => response("404 Not Found", "{}"),
+        });
+        let command = ["pg_isready".to_owned()];
+        let output = smol::block_on(runtime.exec("db", &command)).unwrap();
+        assert_eq!(output.exit_code, 3);
+        assert_eq!(output.output, "accepting\nslowly\n");
+        std::fs::remove_file(socket).unwrap();
+    }
+
+    #[test]
+    fn events_of_other_kinds_are_skipped() {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = requests.clone();
+        let (runtime, socket) = stub(move |request| {
+            recorded.lock().unwrap().push(request.to_owned());
+            chunked(&[
+                b"{\"Action\":\"exec_start: pg_isready\"}\n{\"Act",
+                b"ion\":\"health_status: healthy\"}\n",
+            ])
+        });
+        let since = UNIX_EPOCH + Duration::from_millis(1_700_000_000_250);
+        let event = smol::block_on(runtime.wait_for_event("db", since)).unwrap();
+        assert_eq!(event.as_deref(), Some("health_status: healthy"));
+        let filters =
+            r#"{"container":["db"],"event":["die","health_status"],"type":["container"]}"#;
+        assert_eq!(
+            *requests.lock().unwrap(),
+            [format!(
+                "GET /events?since=1700000000.250000000&filters={}",
+                encode(filters)
+            )]
+        );
+        std::fs::remove_file(socket).unwrap();
+    }
+
+    #[test]
+    fn readiness_wakes_up_on_events() {
+        let inspections = Arc::new(AtomicUsize::new(0));
+        let counted = inspections.clone();
+        let (runtime, socket) = stub(move |request| match request {
+            "GET /containers/db/json" => {
+                let health = match counted.fetch_add(1, Ordering::Relaxed) {
+                    0 => "starting",
+                    _ => "healthy",
+                };
+                response(
+                    "200 OK",
+                    &format!(r#"{{"State":{{"Running":true,"Health":{{"Status":"{health}"}}}}}}"#),
+                )
+            }
+            r if r.starts_with("GET /events?") => {
+                chunked(&[b"{\"Action\":\"health_status: healthy\"}\n"])
+            }
+            _ => response("404 Not Found", "{}"),
+        });
+        let container: Container = serde_yaml::from_str(
+            "{ name: db, image: postgres, hc: { command: [Container, pg_isready] } }",
+        )
+        .unwrap();
+        let started = Instant::now();
+        smol::block_on(wait_until_ready(&container, &runtime)).unwrap();
+        // Polling would have waited a second before inspecting again
+        assert!(started.elapsed() < Duration::from_millis(500));
+        assert_eq!(inspections.load(Ordering::Relaxed), 2);
+        std::fs::remove_file(socket).unwrap();
+    }
+
+    #[test]
+    fn restoring_a_volume_clears_it_first() {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = requests.clone();
+        let (runtime, socket) = stub(move |request| {
+            recorded.lock().unwrap().push(request.to_owned());
+            match request {
+                r if r.starts_with("POST /containers/create") => {
+                    let count = recorded.lock().unwrap().len();
+                    response("201 Created", &format!(r#"{{"Id":"helper{count}"}}"#))
+                }
+                r if r.ends_with("/wait") => response("200 OK", r#"{"StatusCode":0}"#),
+                _ => response("204 No Content", ""),
+            }
+        });
+        let archive = socket.with_extension("tar");
+        std::fs::write(&archive, b"").unwrap();
+        smol::block_on(runtime.restore_volume("pg", &archive)).unwrap();
+
+        assert_eq!(
+            *requests.lock().unwrap(),
+            [
+                "POST /containers/create?name=runer-volume-pg",
+                "POST /containers/helper1/start",
+                "POST /containers/helper1/wait",
+                "DELETE /containers/helper1?force=1",
+                "POST /containers/create?name=runer-volume-pg",
+                "PUT /containers/helper5/archive?path=%2Fvolume",
+                "DELETE /containers/helper5?force=1",
+            ]
+        );
+        std::fs::remove_file(archive).unwrap();
+        std::fs::remove_file(socket).unwrap();
+    }
+
+    #[test]
+    fn progress_is_passed_on_as_it_arrives() {
+        let (runtime, socket) = stub(|request| match request {
+            "POST /images/create?fromImage=postgres&tag=16" => chunked(&[
+                b"{\"status\":\"Pulling from library/postgres\"}\n{\"stre",
+                b"am\":\"Step 1/2\\n\\nStep 2/2\\n\"}\n",
+                b"{\"status\":\"Done\"}\n",
+            ]),
+            _ => chunked(&[b"{\"status\":\"Pulling\"}\n{\"error\":\"manifest unknown \"}\n"]),
+        });
+        let progress = Mutex::new(Vec::new());
+        let request = Request::new("POST", "/images/create?fromImage=postgres&tag=16");
+        smol::block_on(runtime.call_progress(request, &|line| {
+            progress.lock().unwrap().push(line.to_owned())
+        }))
+        .unwrap();
+        assert_eq!(
+            *progress.lock().unwrap(),
+            [
+                "Pulling from library/postgres",
+                "Step 1/2",
+                "Step 2/2",
+                "Done"
+            ]
+        );
+
+        let request = Request::new("POST", "/images/create?fromImage=nope&tag=latest");
+        let error = smol::block_on(runtime.call_progress(request, &|_| {})).unwrap_err();
+        assert_eq!(error.to_string(), "manifest unknown");
+        std::fs::remove_file(socket).unwrap();
+    }
+
+    #[test]
+    fn followed_logs_are_split_into_lines_across_chunks() {
+        let mut stream = frame(1, "first line\nsecond ");
+        stream.extend(frame(2, "line\nthird line\n"));
+        let (first, rest) = stream.split_at(5);
+        let (second, third) = rest.split_at(20);
+        let response = chunked(&[first, second, third]);
+        let (runtime, socket) = stub(move |_| response.clone());
+
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let sink = lines.clone();
+        smol::block_on(async {
+            runtime
+                .follow_logs(
+                    "api",
+                    Arc::new(move |line: &str| sink.lock().unwrap().push(line.to_owned())),
+                )
+                .await
+                .unwrap();
+            let logs = runtime.logs("api").await.unwrap();
+            assert_eq!(logs, "first line\nsecond line\nthird line\n");
+        });
+        // Following goes on in the background until the stream ends
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        while lines.lock().unwrap().len() < 3 && std::time::Instant::now() < deadline {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert_eq!(
+            *lines.lock().unwrap(),
+            ["first line", "second line", "third line"]
+        );
+        std::fs::remove_file(socket).unwrap();
+    }
+}
@@ -0,0 +1,567 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use log::info;
+use serde_json::Value;
+use smol::process::Stdio;
+
+use crate::model::runer::{Container, Image, Mount, Network, Volume};
+
+use super::super::error::RunerError;
+use super::super::invocation::Invocation;
+use super::super::output::{follow, forward_lines, LineSink};
+use super::{
+    volume_helper, ContainerInfo, ContainerRuntime, ExecOutput, CLEAR_VOLUME_COMMAND,
+    VOLUME_HELPER_TARGET,
+};
+
+/// Runtime that drives a Docker compatible command line interface. Docker
+/// and Podman share the same command line for every operation runer needs,
+/// so they only differ in the binary that gets spawned.
+pub struct CliRuntime {
+    name: &'static str,
+    binary: &'static str,
+}
+
+impl CliRuntime {
+    pub fn docker() -> Self {
+        Self {
+            name: "docker",
+            binary: "docker",
+        }
+    }
+
+    pub fn podman() -> Self {
+        Self {
+            name: "podman",
+            binary: "podman",
+        }
+    }
+
+    /// Assembles the <build> command of the given Image.
+    pub fn build_invocation(&self, image: &Image) -> Invocation {
+        let mut build = Invocation::new(self.binary).arg("build");
+
+        if let Some(cmd_options) = &image.options {
+            build = build.args(cmd_options);
+        }
+
+        build = build.args(["-t", &image.tag]);
+
+        if let Some(build_args) = &image.build_args {
+            for build_arg in build_args {
+                build = build.arg(format!("--build-arg={build_arg}"));
+            }
+        }
+
+        build.arg(&image.context)
+    }
+
+    /// Assembles the <run> command of the given Container.
+    ///
+    /// An empty <entrypoint> or <healthcheck> command is left out, the
+    /// validator rejects them before a Container gets this far.
+    pub fn run_invocation(&self, container: &Container) -> Invocation {
+        let mut run = Invocation::new(self.binary)
+            .arg("run")
+            .arg("-d")
+            .args(["--name", &container.name]);
+
+        if let Some(env) = &container.env {
+            for p in env {
+                run = run.args(["--env", &format!("{}={}", p.0, p.1)]);
+            }
+        }
+
+        for mapping in container.ports.iter().flatten() {
+            run = run.args(["-p", &mapping.to_string()]);
+        }
+
+        if let Some(volumes) = &container.volumes {
+            for mount in volumes {
+                run = run.args(["--mount", &mount_option(mount)]);
+            }
+        }
+
+        if let Some(options) = &container.options {
+            run = run.args(options);
+        }
+
+        if let Some((program, args)) = container.entrypoint.as_deref().and_then(<[_]>::split_first)
+        {
+            run = run.args(["--entrypoint", program]).args(args);
+        }
+
+        // Health checks other than container commands run on the host, the
+        // engine takes care of them while it waits for the container to
+        // become ready.
+        if let Some(hc) = &container.hc {
+            if let Some(command) = hc.container_command().filter(|c| !c.is_empty()) {
+                run = run.args(["--health-cmd", command]);
+                if let Some(interval) = &hc.interval {
+                    run = run.args(["--health-interval", interval]);
+                }
+                if let Some(retries) = hc.retries {
+                    run = run.args(["--health-retries", &retries.to_string()]);
+                }
+            }
+        }
+
+        // A container can only join its first network on <run>, the rest of
+        // them are connected afterwards.
+        if let Some(network) = container.networks.iter().flatten().next() {
+            run = run.args(["--network", &network.name]);
+            for alias in &network.aliases {
+                run = run.args(["--network-alias", alias]);
+            }
+        }
+
+        run.arg(&container.image)
+    }
+
+    /// Assembles the <network connect> commands that connect the given
+    /// Container to every network but its first one.
+    pub fn connect_invocations(&self, container: &Container) -> Vec<Invocation> {
+        container
+            .networks
+            .iter()
+            .flatten()
+            .skip(1)
+            .map(|network| {
+                let mut connect = Invocation::new(self.binary).args(["network", "connect"]);
+                for alias in &network.aliases {
+                    connect = connect.args(["--alias", alias]);
+                }
+                connect.args([&network.name, &container.name])
+            })
+            .collect()
+    }
+
+    /// Assembles the <network create> command of the given Network.
+    pub fn network_invocation(&self, name: &str, network: &Network) -> Invocation {
+        let mut create = Invocation::new(self.binary).args(["network", "create"]);
+        if let Some(driver) = &network.driver {
+            create = create.args(["--driver", driver]);
+        }
+        if network.internal {
+            create = create.arg("--internal");
+        }
+        if let Some(subnet) = &network.subnet {
+            create = create.args(["--subnet", subnet]);
+        }
+        create.arg(name)
+    }
+
+    /// Assembles the <volume create> command of the given Volume.
+    pub fn volume_invocation(&self, name: &str, volume: &Volume) -> Invocation {
+        let mut create = Invocation::new(self.binary).args(["volume", "create"]);
+        if let Some(driver) = &volume.driver {
+            create = create.args(["--driver", driver]);
+        }
+        let mut labels: Vec<_> = volume.labels.iter().collect();
+        labels.sort();
+        for (key, value) in labels {
+            create = create.args(["--label", &format!("{key}={value}")]);
+        }
+        create.arg(name)
+    }
+
+    /// Assembles the <create> command of the given Container, which creates
+    /// it without starting it. Only its image and mounts are taken into
+    /// account.
+    fn create_invocation(&self, container: &Container) -> Invocation {
+        let mut create = Invocation::new(self.binary).args(["create", "--name", &container.name]);
+        for mount in container.volumes.iter().flatten() {
+            create = create.args(["--mount", &mount_option(mount)]);
+        }
+        create.arg(&container.image)
+    }
+
+    /// Runs the given <cp> Invocation through a short-lived container that
+    /// mounts the named volume with the given name. The container is
+    /// removed afterwards, whether the copy succeeds or not.
+    async fn copy_through_helper(
+        &self,
+        volume: &str,
+        copy: Invocation,
+        stdin: Stdio,
+        stdout: Stdio,
+    ) -> Result<()> {
+        let helper = volume_helper(volume);
+        self.output(self.create_invocation(&helper)).await?;
+        let copied = copy
+            .command()
+            .stdin(stdin)
+            .stdout(stdout)
+            .stderr(Stdio::piped())
+            .output()
+            .await;
+        self.output(Invocation::new(self.binary).args(["rm", "-f", &helper.name]))
+            .await?;
+        let copied = copied.with_context(|| RunerError::Spawn(self.binary.to_owned()))?;
+        if !copied.status.success() {
+            return Err(anyhow!(
+                "{copy} exited with {}: {}",
+                copied.status,
+                String::from_utf8_lossy(&copied.stderr).trim()
+            ));
+        }
+        Ok(())
+    }
+
+    /// Deletes the content of the named volume with the given name, through
+    /// a short-lived container that mounts it.
+    async fn clear_volume(&self, volume: &str) -> Result<()> {
+        let helper = volume_helper(volume);
+        let mut clear = Invocation::new(self.binary).args(["run", "--rm"]);
+        for mount in helper.volumes.iter().flatten() {
+            clear = clear.args(["--mount", &mount_option(mount)]);
+        }
+        self.output(clear.arg(&helper.image).args(CLEAR_VOLUME_COMMAND))
+            .await?;
+        Ok(())
+    }
+
+    /// Returns whether an object of the given kind (network, volume) with
+    /// the given name exists.
+    async fn exists(&self, kind: &str, name: &str) -> Result<bool> {
+        let status = Invocation::new(self.binary)
+            .args([kind, "inspect", name])
+            .command()
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .await
+            .with_context(|| RunerError::Spawn(self.binary.to_owned()))?;
+        Ok(status.success())
+    }
+
+    /// Runs the given Invocation to completion and returns its stdout.
+    ///
+    /// Returns error with the stderr of the process if it exits with a
+    /// non-success code.
+    async fn output(&self, invocation: Invocation) -> Result<String> {
+        let output = invocation
+            .command()
+            .output()
+            .await
+            .with_context(|| RunerError::Spawn(self.binary.to_owned()))?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "{} exited with {}: {}",
+                invocation,
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+    }
+
+    /// Runs the given Invocation to completion, passing the lines of its
+    /// stdout and stderr to `sink` as they arrive.
+    ///
+    /// Returns error with the last lines of the output if the process exits
+    /// with a non-success code.
+    async fn stream(&self, invocation: Invocation, sink: LineSink) -> Result<()> {
+        let mut child = invocation
+            .command()
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .with_context(|| RunerError::Spawn(self.binary.to_owned()))?;
+        let tail = Arc::new(Mutex::new(VecDeque::new()));
+        let tee: LineSink = {
+            let tail = tail.clone();
+            Arc::new(move |line: &str| {
+                let mut tail = tail.lock().unwrap();
+                if tail.len() == ERROR_TAIL {
+                    tail.pop_front();
+                }
+                tail.push_back(line.to_owned());
+                sink(line);
+            })
+        };
+        let stdout = forward_lines(child.stdout.take().unwrap(), tee.clone());
+        let stderr = forward_lines(child.stderr.take().unwrap(), tee);
+        stdout.await;
+        stderr.await;
+        let status = child.status().await?;
+        if !status.success() {
+            let tail: Vec<String> = tail.lock().unwrap().drain(..).collect();
+            return Err(anyhow!(
+                "{invocation} exited with {status}: {}",
+                tail.join("\n").trim()
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// How many of the last lines of a failed streamed process are reported.
+const ERROR_TAIL: usize = 20;
+
+#[async_trait]
+impl ContainerRuntime for CliRuntime {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn describe_build(&self, image: &Image) -> String {
+        format!("$ {}", self.build_invocation(image))
+    }
+
+    fn describe_run(&self, container: &Container) -> String {
+        let mut lines = vec![format!("$ {}", self.run_invocation(container))];
+        for connect in self.connect_invocations(container) {
+            lines.push(format!("$ {connect}"));
+        }
+        lines.join("\n")
+    }
+
+    fn describe_network(&self, name: &str, network: &Network) -> String {
+        format!("$ {}", self.network_invocation(name, network))
+    }
+
+    fn describe_volume(&self, name: &str, volume: &Volume) -> String {
+        format!("$ {}", self.volume_invocation(name, volume))
+    }
+
+    async fn build(&self, image: &Image, sink: LineSink) -> Result<()> {
+        self.stream(self.build_invocation(image), sink).await
+    }
+
+    async fn image_id(&self, tag: &str) -> Result<String> {
+        let output = self
+            .output(
+                Invocation::new(self.binary).args(["image", "inspect", "--format", "{{.Id}}", tag]),
+            )
+            .await?;
+        Ok(output.trim().to_owned())
+    }
+
+    async fn remove_image(&self, tag: &str) -> Result<()> {
+        self.output(Invocation::new(self.binary).args(["rmi", tag]))
+            .await?;
+        Ok(())
+    }
+
+    async fn create_network(&self, name: &str, network: &Network) -> Result<bool> {
+        if self.exists("network", name).await? {
+            return Ok(false);
+        }
+        match self.output(self.network_invocation(name, network)).await {
+            Ok(_) => Ok(true),
+            // Someone else might have created it in the meantime
+            Err(_) if self.exists("network", name).await? => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn remove_network(&self, name: &str) -> Result<()> {
+        self.output(Invocation::new(self.binary).args(["network", "rm", name]))
+            .await?;
+        Ok(())
+    }
+
+    async fn create_volume(&self, name: &str, volume: &Volume) -> Result<bool> {
+        if self.exists("volume", name).await? {
+            return Ok(false);
+        }
+        self.output(self.volume_invocation(name, volume)).await?;
+        Ok(true)
+    }
+
+    async fn remove_volume(&self, name: &str) -> Result<()> {
+        self.output(Invocation::new(self.binary).args(["volume", "rm", name]))
+            .await?;
+        Ok(())
+    }
+
+    async fn backup_volume(&self, name: &str, file: &Path) -> Result<()> {
+        if !self.exists("volume", name).await? {
+            return Err(anyhow!("No such volume: {name}"));
+        }
+        let output =
+            File::create(file).with_context(|| format!("Failed to create {}", file.display()))?;
+        let helper = volume_helper(name);
+        let copy = Invocation::new(self.binary).args([
+            "cp",
+            &format!("{}:{VOLUME_HELPER_TARGET}/.", helper.name),
+            "-",
+        ]);
+        self.copy_through_helper(name, copy, Stdio::null(), Stdio::from(output))
+            .await
+    }
+
+    async fn restore_volume(&self, name: &str, file: &Path) -> Result<()> {
+        let input =
+            File::open(file).with_context(|| format!("Failed to open {}", file.display()))?;
+        // <cp> only extracts on top of what the volume holds
+        self.clear_volume(name).await?;
+        let helper = volume_helper(name);
+        let copy = Invocation::new(self.binary).args([
+            "cp",
+            "-",
+            &format!("{}:{VOLUME_HELPER_TARGET}", helper.name),
+        ]);
+        self.copy_through_helper(name, copy, Stdio::from(input), Stdio::null())
+            .await
+    }
+
+    async fn run(&self, container: &Container) -> Result<String> {
+        let id = self.output(self.run_invocation(container)).await?;
+        for connect in self.connect_invocations(container) {
+            self.output(connect).await?;
+        }
+        info!("Started {} ({})", container.name, id);
+        Ok(id)
+    }
+
+    async fn stop(&self, container: &str) -> Result<()> {
+        self.output(Invocation::new(self.binary).args(["stop", container]))
+            .await?;
+        Ok(())
+    }
+
+    async fn remove(&self, container: &str) -> Result<()> {
+        self.output(Invocation::new(self.binary).args(["rm", container]))
+            .await?;
+        Ok(())
+    }
+
+    async fn inspect(&self, container: &str) -> Result<ContainerInfo> {
+        let output = self
+            .output(Invocation::new(self.binary).args([
+                "inspect",
+                "--type",
+                "container",
+                container,
+            ]))
+            .await?;
+        let inspected: Value = serde_json::from_str(&output)
+            .with_context(|| format!("Unexpected {} inspect output", self.binary))?;
+        let inspected = inspected
+            .get(0)
+            .ok_or_else(|| anyhow!("No such container: {container}"))?;
+        Ok(parse_inspect(inspected))
+    }
+
+    async fn logs(&self, container: &str) -> Result<String> {
+        let output = Invocation::new(self.binary)
+            .args(["logs", container])
+            .command()
+            .output()
+            .await
+            .with_context(|| RunerError::Spawn(self.binary.to_owned()))?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "{} logs exited with {}: {}",
+                self.binary,
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        // Containers write to both streams, their order can't be restored
+        let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+        text.push_str(&String::from_utf8_lossy(&output.stderr));
+        Ok(text)
+    }
+
+    async fn follow_logs(&self, container: &str, sink: LineSink) -> Result<()> {
+        let mut child = Invocation::new(self.binary)
+            .args(["logs", "--follow", container])
+            .command()
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            // Following stops once runer stops following
+            .kill_on_drop(true)
+            .spawn()
+            .with_context(|| RunerError::Spawn(self.binary.to_owned()))?;
+        let stdout = forward_lines(child.stdout.take().unwrap(), sink.clone());
+        let stderr = forward_lines(child.stderr.take().unwrap(), sink);
+        follow(smol::spawn(async move {
+            stdout.await;
+            stderr.await;
+            let _ = child.status().await;
+        }));
+        Ok(())
+    }
+
+    async fn exec(&self, container: &str, command: &[String]) -> Result<ExecOutput> {
+        let output = Invocation::new(self.binary)
+            .args(["exec", container])
+            .args(command)
+            .command()
+            .output()
+            .await
+            .with_context(|| RunerError::Spawn(self.binary.to_owned()))?;
+        let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+        text.push_str(&String::from_utf8_lossy(&output.stderr));
+        Ok(ExecOutput {
+            exit_code: output.status.code().unwrap_or(-1) as i64,
+            output: text,
+        })
+    }
+}
+
+/// Value of the <--mount> option of the given Mount.
+fn mount_option(mount: &Mount) -> String {
+    let mut option = format!("type={}", mount.typ);
+    let source = match mount.host_path() {
+        Some(path) => Some(path.display().to_string()),
+        None => mount.source.clone(),
+    };
+    if let Some(source) = source {
+        option.push_str(&format!(",source={source}"));
+    }
+    option.push_str(&format!(",target={}", mount.target));
+    if mount.read_only {
+        option.push_str(",readonly");
+    }
+    option
+}
+
+/// Extracts the ContainerInfo out of a single element of the <inspect>
+/// output. Docker and Podman share the same layout for the fields in use,
+/// except that older Podman versions call the health state _Healthcheck_.
+pub fn parse_inspect(inspected: &Value) -> ContainerInfo {
+    let state = &inspected["State"];
+    let health_state = state.get("Health").or_else(|| state.get("Healthcheck"));
+    let health = health_state
+        .and_then(|h| h["Status"].as_str())
+        .filter(|status| !status.is_empty())
+        .map(str::to_owned);
+    let health_output = health_state
+        .and_then(|h| h["Log"].as_array())
+        .and_then(|log| log.last())
+        .and_then(|run| run["Output"].as_str())
+        .map(|output| output.trim().to_owned());
+
+    let mut ports = Vec::new();
+    if let Some(bindings) = inspected["NetworkSettings"]["Ports"].as_object() {
+        for (container_port, hosts) in bindings {
+            for host in hosts.as_array().into_iter().flatten() {
+                ports.push(format!(
+                    "{}:{}->{}",
+                    host["HostIp"].as_str().unwrap_or_default(),
+                    host["HostPort"].as_str().unwrap_or_default(),
+                    container_port
+                ));
+            }
+        }
+    }
+
+    ContainerInfo {
+        id: inspected["Id"].as_str().unwrap_or_default().to_owned(),
+        status: state["Status"].as_str().unwrap_or_default().to_owned(),
+        running: state["Running"].as_bool().unwrap_or_default(),
+        health,
+        health_output,
+        exit_code: state["ExitCode"].as_i64(),
+        ports,
+    }
+}
@@ -0,0 +1,448 @@
+//! In-memory container runtime that doesn't need any container engine.
+//!
+//! It records every request it receives, keeps track of the containers it
+//! "started" and simulates their lifecycle, so that flows can be exercised
+//! end to end on machines without Docker or Podman.
+//!
+//! It is scripted through environment variables:
+//!
+//! * `RUNER_FAKE_FAILURES`: comma separated `operation:target` pairs that
+//!   should fail, e.g. `build:me/api:latest,run:postgres`. Operations are
+//!   `build`, `remove-image`, `run`, `stop`, `remove`, `inspect`, `exec`, `create-network`,
+//!   `remove-network`, `create-volume`, `remove-volume`, `backup`, `restore`
+//!   and `logs`. The special `health` operation makes the container with the
+//!   given name report itself as unhealthy, and the special `exit` operation
+//!   makes it exit with code 1 right after it starts.
+//! * `RUNER_FAKE_JOURNAL`: path of a file that every recorded operation is
+//!   appended to, one per line.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use log::info;
+
+use crate::model::runer::{Container, Image, MountType, Network, Volume};
+
+use super::super::output::LineSink;
+use super::{ContainerInfo, ContainerRuntime, ExecOutput};
+
+/// A request received by the FakeRuntime.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Operation {
+    Build(String),
+    RemoveImage(String),
+    Run(String),
+    Stop(String),
+    Remove(String),
+    Inspect(String),
+    Exec(String, Vec<String>),
+    CreateNetwork(String),
+    RemoveNetwork(String),
+    CreateVolume(String),
+    RemoveVolume(String),
+    Backup(String),
+    Restore(String),
+    Logs(String),
+}
+
+impl Operation {
+    fn kind(&self) -> &'static str {
+        match self {
+            Operation::Build(_) => "build",
+            Operation::RemoveImage(_) => "remove-image",
+            Operation::Run(_) => "run",
+            Operation::Stop(_) => "stop",
+            Operation::Remove(_) => "remove",
+            Operation::Inspect(_) => "inspect",
+            Operation::Exec(..) => "exec",
+            Operation::CreateNetwork(_) => "create-network",
+            Operation::RemoveNetwork(_) => "remove-network",
+            Operation::CreateVolume(_) => "create-volume",
+            Operation::RemoveVolume(_) => "remove-volume",
+            Operation::Backup(_) => "backup",
+            Operation::Restore(_) => "restore",
+            Operation::Logs(_) => "logs",
+        }
+    }
+
+    fn target(&self) -> &str {
+        match self {
+            Operation::Build(target)
+            | Operation::RemoveImage(target)
+            | Operation::Run(target)
+            | Operation::Stop(target)
+            | Operation::Remove(target)
+            | Operation::Inspect(target)
+            | Operation::Exec(target, _)
+            | Operation::CreateNetwork(target)
+            | Operation::RemoveNetwork(target)
+            | Operation::CreateVolume(target)
+            | Operation::RemoveVolume(target)
+            | Operation::Backup(target)
+            | Operation::Restore(target)
+            | Operation::Logs(target) => target,
+        }
+    }
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.kind(), self.target())?;
+        if let Operation::Exec(_, command) = self {
+            write!(f, " {}", command.join(" "))?;
+        }
+        Ok(())
+    }
+}
+
+struct FakeContainer {
+    id: String,
+    running: bool,
+    health: Option<String>,
+    health_output: Option<String>,
+    exit_code: Option<i64>,
+    networks: Vec<String>,
+    volumes: Vec<String>,
+}
+
+#[derive(Default)]
+struct FakeState {
+    operations: Vec<Operation>,
+    containers: HashMap<String, FakeContainer>,
+    networks: HashSet<String>,
+    volumes: HashSet<String>,
+    next_id: u64,
+}
+
+/// Container runtime that simulates a container engine in memory.
+#[derive(Default)]
+pub struct FakeRuntime {
+    state: Mutex<FakeState>,
+    failures: HashSet<(String, String)>,
+    journal: Option<String>,
+}
+
+impl FakeRuntime {
+    /// Creates a FakeRuntime scripted by the `RUNER_FAKE_FAILURES` and
+    /// `RUNER_FAKE_JOURNAL` environment variables.
+    pub fn from_env() -> Self {
+        let mut runtime = Self {
+            journal: std::env::var("RUNER_FAKE_JOURNAL").ok(),
+            ..Self::default()
+        };
+        if let Ok(failures) = std::env::var("RUNER_FAKE_FAILURES") {
+            for failure in failures.split(',').filter(|f| !f.trim().is_empty()) {
+                if let Some((operation, target)) = failure.trim().split_once(':') {
+                    runtime = runtime.fail(operation, target);
+                }
+            }
+        }
+        runtime
+    }
+
+    /// Makes the given operation fail for the given target. The target is the
+    /// image tag for `build`, and the container name for every other
+    /// operation.
+    pub fn fail(mut self, operation: &str, target: &str) -> Self {
+        self.failures
+            .insert((operation.to_owned(), target.to_owned()));
+        self
+    }
+
+    /// Every operation received so far, in the order they arrived.
+    #[cfg(test)]
+    pub fn operations(&self) -> Vec<Operation> {
+        self.state.lock().unwrap().operations.clone()
+    }
+
+    /// Records the given operation, and returns error if it is scripted to
+    /// fail.
+    fn record(&self, state: &mut FakeState, operation: Operation) -> Result<()> {
+        info!("fake runtime: {operation}");
+        if let Some(journal) = &self.journal {
+            std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(journal)
+                .and_then(|mut file| writeln!(file, "{operation}"))?;
+        }
+        let failure = (operation.kind().to_owned(), operation.target().to_owned());
+        state.operations.push(operation);
+        if self.failures.contains(&failure) {
+            return Err(anyhow!("fake {} of {} failed", failure.0, failure.1));
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ContainerRuntime for FakeRuntime {
+    fn name(&self) -> &'static str {
+        "fake"
+    }
+
+    fn describe_build(&self, image: &Image) -> String {
+        format!("fake build {} from {}", image.tag, image.context)
+    }
+
+    fn describe_run(&self, container: &Container) -> String {
+        format!("fake run {} from {}", container.name, container.image)
+    }
+
+    fn describe_network(&self, name: &str, _network: &Network) -> String {
+        format!("fake create network {name}")
+    }
+
+    fn describe_volume(&self, name: &str, _volume: &Volume) -> String {
+        format!("fake create volume {name}")
+    }
+
+    async fn build(&self, image: &Image, _sink: LineSink) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        self.record(&mut state, Operation::Build(image.tag.clone()))
+    }
+
+    async fn image_id(&self, tag: &str) -> Result<String> {
+        Ok(format!("fake-image-{tag}"))
+    }
+
+    async fn remove_image(&self, tag: &str) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        self.record(&mut state, Operation::RemoveImage(tag.to_owned()))
+    }
+
+    async fn create_network(&self, name: &str, _network: &Network) -> Result<bool> {
+        let mut state = self.state.lock().unwrap();
+        self.record(&mut state, Operation::CreateNetwork(name.to_owned()))?;
+        Ok(state.networks.insert(name.to_owned()))
+    }
+
+    async fn remove_network(&self, name: &str) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        self.record(&mut state, Operation::RemoveNetwork(name.to_owned()))?;
+        if state
+            .containers
+            .values()
+            .any(|c| c.networks.iter().any(|n| n == name))
+        {
+            return Err(anyhow!("network {name} has active endpoints"));
+        }
+        if !state.networks.remove(name) {
+            return Err(anyhow!("network {name} not found"));
+        }
+        Ok(())
+    }
+
+    async fn create_volume(&self, name: &str, _volume: &Volume) -> Result<bool> {
+        let mut state = self.state.lock().unwrap();
+        self.record(&mut state, Operation::CreateVolume(name.to_owned()))?;
+        Ok(state.volumes.insert(name.to_owned()))
+    }
+
+    async fn remove_volume(&self, name: &str) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        self.record(&mut state, Operation::RemoveVolume(name.to_owned()))?;
+        if state
+            .containers
+            .values()
+            .any(|c| c.volumes.iter().any(|v| v == name))
+        {
+            return Err(anyhow!("volume {name} is in use"));
+        }
+        if !state.volumes.remove(name) {
+            return Err(anyhow!("No such volume: {name}"));
+        }
+        Ok(())
+    }
+
+    /// Writes an empty tar archive, the fake volumes have no content.
+    async fn backup_volume(&self, name: &str, file: &Path) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        self.record(&mut state, Operation::Backup(name.to_owned()))?;
+        if !state.volumes.contains(name) {
+            return Err(anyhow!("No such volume: {name}"));
+        }
+        tar::Builder::new(std::fs::File::create(file)?).finish()?;
+        Ok(())
+    }
+
+    async fn restore_volume(&self, name: &str, file: &Path) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        self.record(&mut state, Operation::Restore(name.to_owned()))?;
+        tar::Archive::new(std::fs::File::open(file)?)
+            .entries()?
+            .try_for_each(|entry| entry.map(|_| ()))?;
+        state.volumes.insert(name.to_owned());
+        Ok(())
+    }
+
+    async fn run(&self, container: &Container) -> Result<String> {
+        let mut state = self.state.lock().unwrap();
+        self.record(&mut state, Operation::Run(container.name.clone()))?;
+        if state.containers.contains_key(&container.name) {
+            return Err(anyhow!(
+                "container name {} is already in use",
+                container.name
+            ));
+        }
+        let networks: Vec<String> = container
+            .networks
+            .iter()
+            .flatten()
+            .map(|n| n.name.clone())
+            .collect();
+        if let Some(missing) = networks.iter().find(|n| !state.networks.contains(*n)) {
+            return Err(anyhow!("network {missing} not found"));
+        }
+        // Like real engines, missing named volumes are created on the fly
+        let volumes: Vec<String> = container
+            .volumes
+            .iter()
+            .flatten()
+            .filter(|m| m.typ == MountType::Volume)
+            .filter_map(|m| m.source.clone())
+            .collect();
+        state.volumes.extend(volumes.iter().cloned());
+        state.next_id += 1;
+        let id = format!("fake{:012}", state.next_id);
+        let scripted = |kind: &str| {
+            self.failures
+                .contains(&(kind.to_owned(), container.name.clone()))
+        };
+        // Only container-native health checks are reported by the runtime
+        let native_hc = container.hc.as_ref().and_then(|hc| hc.container_command());
+        let (health, health_output) = match (native_hc, scripted("health")) {
+            (None, _) => (None, None),
+            (Some(_), false) => (Some("healthy".to_owned()), Some(String::new())),
+            (Some(_), true) => (
+                Some("unhealthy".to_owned()),
+                Some("fake health check failed".to_owned()),
+            ),
+        };
+        let exited = scripted("exit");
+        state.containers.insert(
+            container.name.clone(),
+            FakeContainer {
+                id: id.clone(),
+                running: !exited,
+                health,
+                health_output,
+                exit_code: exited.then_some(1),
+                networks,
+                volumes,
+            },
+        );
+        Ok(id)
+    }
+
+    async fn stop(&self, container: &str) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        self.record(&mut state, Operation::Stop(container.to_owned()))?;
+        let fake = state
+            .containers
+            .get_mut(container)
+            .ok_or_else(|| anyhow!("No such container: {container}"))?;
+        fake.running = false;
+        fake.exit_code = Some(0);
+        Ok(())
+    }
+
+    async fn remove(&self, container: &str) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        self.record(&mut state, Operation::Remove(container.to_owned()))?;
+        match state.containers.get(container) {
+            None => Err(anyhow!("No such container: {container}")),
+            Some(fake) if fake.running => Err(anyhow!(
+                "cannot remove container {container}: container is running"
+            )),
+            Some(_) => {
+                state.containers.remove(container);
+                Ok(())
+            }
+        }
+    }
+
+    async fn inspect(&self, container: &str) -> Result<ContainerInfo> {
+        let mut state = self.state.lock().unwrap();
+        self.record(&mut state, Operation::Inspect(container.to_owned()))?;
+        let fake = state
+            .containers
+            .get(container)
+            .ok_or_else(|| anyhow!("No such container: {container}"))?;
+        Ok(ContainerInfo {
+            id: fake.id.clone(),
+            status: if fake.running { "running" } else { "exited" }.to_owned(),
+            running: fake.running,
+            health: fake.health.clone(),
+            health_output: fake.health_output.clone(),
+            exit_code: fake.exit_code,
+            ports: vec![],
+        })
+    }
+
+    async fn logs(&self, container: &str) -> Result<String> {
+        let mut state = self.state.lock().unwrap();
+        self.record(&mut state, Operation::Logs(container.to_owned()))?;
+        match state.containers.get(container) {
+            Some(fake) if fake.exit_code == Some(1) => Ok("fake container crashed\n".to_owned()),
+            Some(_) => Ok(String::new()),
+            None => Err(anyhow!("No such container: {container}")),
+        }
+    }
+
+    async fn follow_logs(&self, container: &str, sink: LineSink) -> Result<()> {
+        for line in self.logs(container).await?.lines() {
+            sink(line);
+        }
+        Ok(())
+    }
+
+    async fn exec(&self, container: &str, command: &[String]) -> Result<ExecOutput> {
+        let mut state = self.state.lock().unwrap();
+        self.record(
+            &mut state,
+            Operation::Exec(container.to_owned(), command.to_vec()),
+        )?;
+        match state.containers.get(container) {
+            Some(fake) if fake.running => Ok(ExecOutput {
+                exit_code: 0,
+                output: String::new(),
+            }),
+            Some(_) => Err(anyhow!("container {container} is not running")),
+            None => Err(anyhow!("No such container: {container}")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exec_needs_a_running_container() {
+        let runtime = FakeRuntime::default();
+        let container: Container = serde_yaml::from_str("{ name: db, image: postgres }").unwrap();
+        let command = ["pg_isready".to_owned()];
+        smol::block_on(async {
+            let error = runtime.exec("db", &command).await.unwrap_err();
+            assert_eq!(error.to_string(), "No such container: db");
+
+            runtime.run(&container).await.unwrap();
+            let output = runtime.exec("db", &command).await.unwrap();
+            assert_eq!(output.exit_code, 0);
+
+            runtime.stop("db").await.unwrap();
+            let error = runtime.exec("db", &command).await.unwrap_err();
+            assert_eq!(error.to_string(), "container db is not running");
+        });
+        assert_eq!(
+            runtime.operations()[0],
+            Operation::Exec("db".to_owned(), command.to_vec())
+        );
+    }
+}
@@ -0,0 +1,269 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use clap::ValueEnum;
+
+use crate::model::runer::{Container, Image, Mount, MountType, Network, RuntimeKind, Volume};
+
+use super::output::LineSink;
+
+pub mod api;
+pub mod cli;
+pub mod fake;
+
+use api::ApiRuntime;
+use cli::CliRuntime;
+use fake::FakeRuntime;
+
+/// What the engine learns about a container by inspecting it.
+#[derive(Clone, Debug, Default)]
+pub struct ContainerInfo {
+    pub id: String,
+    /// created, running, exited, etc.
+    pub status: String,
+    pub running: bool,
+    /// healthy, unhealthy or starting. None if the container has no health
+    /// check.
+    pub health: Option<String>,
+    /// Output of the latest health check run, if any.
+    pub health_output: Option<String>,
+    pub exit_code: Option<i64>,
+    /// Published ports, as `host_ip:host_port->container_port/protocol`
+    pub ports: Vec<String>,
+}
+
+/// Result of a command executed inside a container.
+#[allow(dead_code)]
+#[derive(Clone, Debug)]
+pub struct ExecOutput {
+    pub exit_code: i64,
+    pub output: String,
+}
+
+/// Every container operation the engine needs, independent from the
+/// container engine that carries it out.
+#[async_trait]
+pub trait ContainerRuntime: Send + Sync {
+    /// Name of the runtime, as it is written in a .runer file.
+    fn name(&self) -> &'static str;
+
+    /// Human readable description of what [build](ContainerRuntime::build)
+    /// would do with the given Image, e.g. the command line it spawns.
+    fn describe_build(&self, image: &Image) -> String;
+
+    /// Human readable description of what [run](ContainerRuntime::run)
+    /// would do with the given Container.
+    fn describe_run(&self, container: &Container) -> String;
+
+    /// Human readable description of what
+    /// [create_network](ContainerRuntime::create_network) would do with the
+    /// given Network.
+    fn describe_network(&self, name: &str, network: &Network) -> String;
+
+    /// Human readable description of what
+    /// [create_volume](ContainerRuntime::create_volume) would do with the
+    /// given Volume.
+    fn describe_volume(&self, name: &str, volume: &Volume) -> String;
+
+    /// Builds the given Image, passing the build output to `sink`.
+    async fn build(&self, image: &Image, sink: LineSink) -> Result<()>;
+
+    /// ID of the image with the given tag.
+    async fn image_id(&self, tag: &str) -> Result<String>;
+
+    /// Removes the image with the given tag.
+    async fn remove_image(&self, tag: &str) -> Result<()>;
+
+    /// Creates the network with the given name and settings, unless a
+    /// network with that name already exists. Returns whether it was created.
+    async fn create_network(&self, name: &str, network: &Network) -> Result<bool>;
+
+    /// Removes the network with the given name. Fails if containers are
+    /// still attached to it, see [network_in_use].
+    async fn remove_network(&self, name: &str) -> Result<()>;
+
+    /// Creates the named volume with the given name and settings, unless a
+    /// volume with that name already exists. Returns whether it was created.
+    async fn create_volume(&self, name: &str, volume: &Volume) -> Result<bool>;
+
+    /// Removes the named volume with the given name. Fails if the volume is
+    /// still in use by a container.
+    async fn remove_volume(&self, name: &str) -> Result<()>;
+
+    /// Writes the content of the named volume with the given name into the
+    /// given local file, as a tar archive.
+    async fn backup_volume(&self, name: &str, file: &Path) -> Result<()>;
+
+    /// Replaces the content of the named volume with the given name with the
+    /// given tar archive, as written by
+    /// [backup_volume](ContainerRuntime::backup_volume). Whatever the volume
+    /// held before is deleted, not only the files the archive overwrites.
+    async fn restore_volume(&self, name: &str, file: &Path) -> Result<()>;
+
+    /// Creates and starts the given Container in the background, connected
+    /// to its networks. Returns the ID of the started container.
+    async fn run(&self, container: &Container) -> Result<String>;
+
+    /// Stops the container with the given name or ID.
+    async fn stop(&self, container: &str) -> Result<()>;
+
+    /// Removes the (stopped) container with the given name or ID.
+    async fn remove(&self, container: &str) -> Result<()>;
+
+    /// Inspects the container with the given name or ID.
+    async fn inspect(&self, container: &str) -> Result<ContainerInfo>;
+
+    /// Output of the container with the given name or ID, so far.
+    async fn logs(&self, container: &str) -> Result<String>;
+
+    /// Starts passing the output of the container with the given name or ID
+    /// to `sink` as it arrives, from its start until it stops. Following
+    /// goes on in the background, registered with [follow].
+    ///
+    /// [follow]: super::output::follow
+    async fn follow_logs(&self, container: &str, sink: LineSink) -> Result<()>;
+
+    /// Waits for the first event since the given time, in which the
+    /// container with the given name or ID dies or reports its health, and
+    /// returns its action, e.g. `health_status: healthy`.
+    ///
+    /// Returns None right away if the runtime can't watch events, its
+    /// containers are polled with [inspect](ContainerRuntime::inspect)
+    /// instead.
+    async fn wait_for_event(&self, _container: &str, _since: SystemTime) -> Result<Option<String>> {
+        Ok(None)
+    }
+
+    /// Executes the given command inside the running container with the
+    /// given name or ID.
+    #[allow(dead_code)]
+    async fn exec(&self, container: &str, command: &[String]) -> Result<ExecOutput>;
+}
+
+/// Whether the given error of
+/// [remove_network](ContainerRuntime::remove_network) is due to containers
+/// that are still attached to the network, as Docker ("has active
+/// endpoints") and Podman ("is being used") report it.
+pub fn network_in_use(error: &anyhow::Error) -> bool {
+    let message = format!("{error:#}");
+    message.contains("active endpoints") || message.contains("is being used")
+}
+
+/// Image of the short-lived containers that volumes get backed up and
+/// restored through.
+pub const VOLUME_HELPER_IMAGE: &str = "busybox:latest";
+
+/// Where the volume is mounted inside the [volume_helper].
+pub const VOLUME_HELPER_TARGET: &str = "/volume";
+
+/// Command of the [volume_helper] that deletes everything in the volume,
+/// hidden files included.
+pub const CLEAR_VOLUME_COMMAND: [&str; 5] =
+    ["find", VOLUME_HELPER_TARGET, "-mindepth", "1", "-delete"];
+
+/// Short-lived container that mounts the named volume with the given name, so
+/// that its content can be copied out or in.
+pub fn volume_helper(volume: &str) -> Container {
+    Container {
+        name: format!("runer-volume-{volume}"),
+        image: VOLUME_HELPER_IMAGE.to_owned(),
+        options: None,
+        ports: None,
+        env: None,
+        volumes: Some(vec![Mount {
+            typ: MountType::Volume,
+            source: Some(volume.to_owned()),
+            target: VOLUME_HELPER_TARGET.to_owned(),
+            read_only: false,
+        }]),
+        entrypoint: None,
+        hc: None,
+        networks: None,
+        ready: None,
+    }
+}
+
+/// Creates the runtime of the given kind.
+pub fn create_runtime(kind: RuntimeKind) -> Arc<dyn ContainerRuntime> {
+    match kind {
+        RuntimeKind::Docker => Arc::new(CliRuntime::docker()),
+        RuntimeKind::Podman => Arc::new(CliRuntime::podman()),
+        RuntimeKind::DockerApi => Arc::new(ApiRuntime::from_env()),
+        RuntimeKind::Fake => Arc::new(FakeRuntime::from_env()),
+    }
+}
+
+/// Runtimes selected by a Rune: a default one for the entire Rune, and the
+/// ones that are overridden by individual Blueprints.
+#[derive(Clone)]
+pub struct Runtimes {
+    default: Arc<dyn ContainerRuntime>,
+    blueprints: HashMap<String, Arc<dyn ContainerRuntime>>,
+}
+
+impl Runtimes {
+    pub fn new(default: RuntimeKind, overrides: HashMap<String, RuntimeKind>) -> Self {
+        let mut created: HashMap<RuntimeKind, Arc<dyn ContainerRuntime>> = HashMap::new();
+        let mut create = |kind: RuntimeKind| {
+            created
+                .entry(kind)
+                .or_insert_with(|| create_runtime(kind))
+                .clone()
+        };
+        Self {
+            default: create(default),
+            blueprints: overrides
+                .into_iter()
+                .map(|(blueprint, kind)| (blueprint, create(kind)))
+                .collect(),
+        }
+    }
+
+    /// Uses a single runtime of the given kind for every Blueprint,
+    /// regardless of what the Rune selects.
+    pub fn uniform(kind: RuntimeKind) -> Self {
+        Self::new(kind, HashMap::new())
+    }
+
+    /// Uses the given runtime for every Blueprint.
+    #[cfg(test)]
+    pub fn single(runtime: Arc<dyn ContainerRuntime>) -> Self {
+        Self {
+            default: runtime,
+            blueprints: HashMap::new(),
+        }
+    }
+
+    /// Runtime of the Blueprints that don't select their own.
+    pub fn default(&self) -> Arc<dyn ContainerRuntime> {
+        self.default.clone()
+    }
+
+    /// Runtime that carries out the container jobs of the given Blueprint.
+    pub fn for_blueprint(&self, blueprint: &str) -> Arc<dyn ContainerRuntime> {
+        self.blueprints
+            .get(blueprint)
+            .unwrap_or(&self.default)
+            .clone()
+    }
+
+    /// Runtime with the given name, as it is recorded in the state file. The
+    /// runtimes of the Rune are reused, any other one is created.
+    ///
+    /// Returns error if no runtime has the given name.
+    pub fn named(&self, name: &str) -> Result<Arc<dyn ContainerRuntime>> {
+        if let Some(runtime) = std::iter::once(&self.default)
+            .chain(self.blueprints.values())
+            .find(|runtime| runtime.name() == name)
+        {
+            return Ok(runtime.clone());
+        }
+        let kind = RuntimeKind::from_str(name, false)
+            .map_err(|_| anyhow!("Unknown container runtime '{name}'"))?;
+        Ok(create_runtime(kind))
+    }
+}
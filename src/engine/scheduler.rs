@@ -0,0 +1,109 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+
+use super::error::ErrorKind;
+use super::graph::TaskGraph;
+
+/// The way a Task has finished. Every Task of a Flow ends up with exactly
+/// one outcome.
+#[derive(Clone, Debug)]
+pub enum TaskOutcome {
+    Succeeded,
+    Failed {
+        reason: String,
+        kind: ErrorKind,
+    },
+    /// The Task was never started since the Task with the given ID, which it
+    /// (transitively) depends on, has failed.
+    Skipped(u32),
+}
+
+impl fmt::Display for TaskOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TaskOutcome::Succeeded => write!(f, "succeeded"),
+            TaskOutcome::Failed { reason, .. } => write!(f, "failed: {reason}"),
+            TaskOutcome::Skipped(parent) => write!(f, "skipped: task {parent} failed"),
+        }
+    }
+}
+
+/// Keeps track of the progress of a Flow according to its TaskGraph.
+///
+/// The executor notifies the Scheduler exactly once per finished Task, and
+/// in return learns which Tasks became ready to run. A failure is propagated
+/// to every dependent Task right away, so they are marked as skipped instead
+/// of waiting for a parent that will never succeed.
+pub struct Scheduler<'a> {
+    graph: &'a TaskGraph,
+    pending_parents: HashMap<u32, usize>,
+    outcomes: HashMap<u32, TaskOutcome>,
+}
+
+impl<'a> Scheduler<'a> {
+    pub fn new(graph: &'a TaskGraph) -> Self {
+        Self {
+            graph,
+            pending_parents: graph
+                .ids()
+                .iter()
+                .map(|id| (*id, graph.parents(*id).len()))
+                .collect(),
+            outcomes: HashMap::new(),
+        }
+    }
+
+    /// Tasks that can be started right away.
+    pub fn initial(&self) -> Vec<u32> {
+        self.graph.roots()
+    }
+
+    /// Records the outcome of a finished Task.
+    ///
+    /// Returns the Tasks that became ready to run, and the Tasks that got
+    /// skipped as a consequence of the given outcome.
+    pub fn complete(&mut self, id: u32, outcome: TaskOutcome) -> (Vec<u32>, Vec<u32>) {
+        let mut ready = Vec::new();
+        let mut skipped = Vec::new();
+        match outcome {
+            TaskOutcome::Succeeded => {
+                for child in self.graph.children(id) {
+                    let pending = self.pending_parents.get_mut(child).unwrap();
+                    *pending -= 1;
+                    if *pending == 0 && !self.outcomes.contains_key(child) {
+                        ready.push(*child);
+                    }
+                }
+            }
+            _ => {
+                let mut queue: VecDeque<u32> = self.graph.children(id).iter().copied().collect();
+                while let Some(child) = queue.pop_front() {
+                    if self.outcomes.contains_key(&child) {
+                        continue;
+                    }
+                    self.outcomes.insert(child, TaskOutcome::Skipped(id));
+                    skipped.push(child);
+                    queue.extend(self.graph.children(child));
+                }
+            }
+        }
+        self.outcomes.insert(id, outcome);
+        (ready, skipped)
+    }
+
+    /// Outcome of the given Task, if it is already known.
+    pub fn outcome(&self, id: u32) -> Option<&TaskOutcome> {
+        self.outcomes.get(&id)
+    }
+
+    /// Number of succeeded, failed and skipped Tasks.
+    pub fn summary(&self) -> (usize, usize, usize) {
+        self.outcomes
+            .values()
+            .fold((0, 0, 0), |(succeeded, failed, skipped), o| match o {
+                TaskOutcome::Succeeded => (succeeded + 1, failed, skipped),
+                TaskOutcome::Failed { .. } => (succeeded, failed + 1, skipped),
+                TaskOutcome::Skipped(_) => (succeeded, failed, skipped + 1),
+            })
+    }
+}
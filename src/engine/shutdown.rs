@@ -0,0 +1,114 @@
+use std::collections::BTreeSet;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use async_signal::{Signal, Signals};
+use log::{error, warn};
+use smol::stream::StreamExt;
+use smol::Timer;
+
+use super::error::ErrorKind;
+
+/// Process groups of the processes that jobs spawned on the host, which get
+/// the signals that runer receives, see [ProcessGroup].
+static GROUPS: Mutex<BTreeSet<u32>> = Mutex::new(BTreeSet::new());
+
+/// How often the process groups are checked while waiting for them to exit.
+const EXIT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How runer behaves once it is interrupted by SIGINT or SIGTERM.
+#[derive(Clone, Copy, Debug)]
+pub struct ShutdownOptions {
+    /// How long the running processes get to exit after the signal is
+    /// forwarded to them, before they are killed.
+    pub grace_period: Duration,
+    /// Whether the containers that the Flows started get stopped.
+    pub stop_containers: bool,
+}
+
+/// A process spawned by a job in its own process group, so that a Ctrl-C in
+/// the terminal reaches runer only, and runer decides what its processes
+/// get. The group is known to runer for as long as this value lives.
+pub struct ProcessGroup(u32);
+
+impl ProcessGroup {
+    /// Registers the group of the given process, which is expected to be
+    /// the leader of its own group, see
+    /// [Invocation::group_command](super::invocation::Invocation::group_command).
+    pub fn register(pid: u32) -> Self {
+        GROUPS.lock().unwrap().insert(pid);
+        Self(pid)
+    }
+}
+
+impl Drop for ProcessGroup {
+    fn drop(&mut self) {
+        GROUPS.lock().unwrap().remove(&self.0);
+    }
+}
+
+/// Starts listening to SIGINT and SIGTERM, which no longer terminate runer
+/// on their own from then on.
+pub fn listen() -> std::io::Result<Signals> {
+    Signals::new([Signal::Int, Signal::Term])
+}
+
+/// Waits until runer receives one of the signals it listens to.
+pub async fn interrupted(signals: &mut Signals) -> Signal {
+    loop {
+        if let Some(Ok(signal)) = signals.next().await {
+            return signal;
+        }
+    }
+}
+
+/// Forwards the given signal to every registered process group, and kills
+/// the groups that are still alive after the grace period.
+pub async fn stop_processes(signal: Signal, grace_period: Duration) {
+    send_to_groups(signal as i32);
+    let deadline = Instant::now() + grace_period;
+    while Instant::now() < deadline {
+        GROUPS.lock().unwrap().retain(|group| group_alive(*group));
+        if GROUPS.lock().unwrap().is_empty() {
+            return;
+        }
+        Timer::after(EXIT_POLL_INTERVAL).await;
+    }
+    let left = GROUPS.lock().unwrap().len();
+    if left > 0 {
+        warn!("Killing {left} process group(s) still running after {grace_period:?}");
+        send_to_groups(libc::SIGKILL);
+    }
+}
+
+/// Waits for another signal, then kills every registered process group and
+/// exits right away.
+pub async fn force_exit(signals: &mut Signals) {
+    let signal = interrupted(signals).await;
+    error!("Interrupted again, exiting immediately");
+    send_to_groups(libc::SIGKILL);
+    std::process::exit(ErrorKind::Interrupted(signal as i32).exit_code());
+}
+
+/// Name of the given signal, as in `kill -l`.
+pub fn signal_name(signal: Signal) -> &'static str {
+    match signal {
+        Signal::Int => "SIGINT",
+        Signal::Term => "SIGTERM",
+        _ => "signal",
+    }
+}
+
+fn send_to_groups(signal: i32) {
+    for group in GROUPS.lock().unwrap().iter() {
+        // A negative ID addresses every process of the group
+        unsafe {
+            libc::kill(-(*group as libc::pid_t), signal);
+        }
+    }
+}
+
+/// Whether any process of the given group is still alive.
+fn group_alive(group: u32) -> bool {
+    unsafe { libc::kill(-(group as libc::pid_t), 0) == 0 }
+}
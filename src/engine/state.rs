@@ -1,25 +1,34 @@
 use std::collections::HashMap;
 use std::sync::Arc;
 
-use smol::lock::Mutex;
-use smol::process::Child;
+use anyhow::{anyhow, Result};
 
-use crate::model::runer::{Blueprint, Flow, Rune};
+use crate::model::runer::{
+    Blueprint, Container, EnvSets, Flow, JobType, MountType, Network, Rune, RuntimeKind, TaskType,
+    Volume,
+};
+
+use super::run_log::RunLog;
+use super::run_state::StateFile;
+use super::runtime::{ContainerRuntime, Runtimes};
 
 /// It represents the Application State throughout the Application
 /// lifetime. It consists fields that should be available to Application
 /// threads without compromsing thread safety.
 ///
-/// Care that _blueprints_, _env_, and _flows_ fields are behind an Arc
-/// pointer which makes them implicitly immutable.
-///
-/// _handles_ field on the other hand is behind a Mutex guard which allows
-/// the Application to access the data safely.
+/// Care that _blueprints_, _env_, _networks_, _volumes_ and _flows_ fields are
+/// behind an Arc pointer which makes them implicitly immutable, and cheap to
+/// clone.
+#[derive(Clone)]
 pub struct State {
     pub blueprints: Option<Arc<HashMap<String, Blueprint>>>,
-    pub env: Option<Arc<HashMap<String, Vec<(String, String)>>>>,
+    pub env: Option<Arc<EnvSets>>,
+    pub networks: Option<Arc<HashMap<String, Network>>>,
+    pub volumes: Option<Arc<HashMap<String, Volume>>>,
     pub flows: Option<Arc<Vec<Flow>>>,
-    pub handles: Option<Arc<Mutex<HashMap<u32, Child>>>>,
+    pub runtimes: Option<Arc<Runtimes>>,
+    pub state_file: Option<Arc<StateFile>>,
+    pub run_log: Option<Arc<RunLog>>,
 }
 
 /// By default the Application has no state.
@@ -28,8 +37,12 @@ impl Default for State {
         Self {
             blueprints: None,
             env: None,
+            networks: None,
+            volumes: None,
             flows: None,
-            handles: None,
+            runtimes: None,
+            state_file: None,
+            run_log: None,
         }
     }
 }
@@ -37,17 +50,180 @@ impl Default for State {
 impl State {
     /// This function builds the Application State, according to the given
     /// Rune's Fragments.
-    pub fn from_rune(mut self, rune: Rune) -> Self {
-        if let Some(blueprints) = rune.blueprints {
-            self.blueprints = Some(Arc::new(blueprints));
-        }
-        if let Some(env) = rune.env {
-            self.env = Some(Arc::new(env));
-        }
+    ///
+    /// Missing _blueprints_, _env_, _networks_ and _volumes_ Fragments are
+    /// represented as empty maps, so that the executor doesn't have to care
+    /// whether they were declared.
+    pub fn from_rune(rune: Rune) -> Self {
+        let overrides = rune
+            .blueprints
+            .iter()
+            .flatten()
+            .filter_map(|(name, b)| b.runtime.map(|kind| (name.clone(), kind)))
+            .collect();
+        let runtimes = Runtimes::new(rune.runtime.unwrap_or_default(), overrides);
+        let mut state = Self {
+            runtimes: Some(Arc::new(runtimes)),
+            blueprints: Some(Arc::new(rune.blueprints.unwrap_or_default())),
+            env: Some(Arc::new(rune.env.unwrap_or_default())),
+            networks: Some(Arc::new(rune.networks.unwrap_or_default())),
+            volumes: Some(Arc::new(rune.volumes.unwrap_or_default())),
+            ..Self::default()
+        };
         if let Some(flows) = rune.flows {
-            self.flows = Some(Arc::new(flows));
-            self.handles = Some(Arc::new(Mutex::new(HashMap::<u32, Child>::new())));
+            state.flows = Some(Arc::new(flows));
         }
+        state
+    }
+
+    /// Replaces the runtimes selected by the Rune with a single runtime of
+    /// the given kind.
+    pub fn with_runtime(mut self, kind: RuntimeKind) -> Self {
+        self.runtimes = Some(Arc::new(Runtimes::uniform(kind)));
         self
     }
+
+    /// Persists what the executor launches into the given state file.
+    pub fn with_state_file(mut self, state_file: StateFile) -> Self {
+        self.state_file = Some(Arc::new(state_file));
+        self
+    }
+
+    /// Keeps the output of every Task in the given run directory.
+    pub fn with_run_log(mut self, run_log: RunLog) -> Self {
+        self.run_log = Some(Arc::new(run_log));
+        self
+    }
+
+    /// Names of the Flows in their declaration order.
+    pub fn flow_names(&self) -> Vec<&str> {
+        self.flows
+            .iter()
+            .flat_map(|flows| flows.iter())
+            .map(|flow| flow.name.as_str())
+            .collect()
+    }
+
+    /// Finds the Flow with the given name.
+    ///
+    /// Returns error listing the available Flows if there is no such Flow.
+    pub fn find_flow(&self, name: &str) -> Result<&Flow> {
+        self.flows
+            .iter()
+            .flat_map(|flows| flows.iter())
+            .find(|flow| flow.name == name)
+            .ok_or_else(|| {
+                anyhow!(
+                    "No flow named '{name}'. Available flows: {}",
+                    self.flow_names().join(", ")
+                )
+            })
+    }
+
+    /// Resolves the names of the Flows to run.
+    ///
+    /// If no name is given, the only Flow of the Rune is selected. It is an
+    /// error to omit the names when the Rune has more than one Flow.
+    pub fn select_flows(&self, names: &[String]) -> Result<Vec<String>> {
+        if names.is_empty() {
+            return match self.flow_names().as_slice() {
+                [name] => Ok(vec![name.to_string()]),
+                [] => Err(anyhow!("Application state has no flow to run.")),
+                names => Err(anyhow!(
+                    "Rune has {} flows, select the ones to run with --flow <NAME>. Available flows: {}",
+                    names.len(),
+                    names.join(", ")
+                )),
+            };
+        }
+        for name in names {
+            self.find_flow(name)?;
+        }
+        Ok(names.to_vec())
+    }
+
+    /// Containers that the given Flow runs, paired with the runtime that runs
+    /// them, in the order of the Flow's Tasks.
+    fn flow_containers(&self, flow: &Flow) -> Vec<(Arc<dyn ContainerRuntime>, &Container)> {
+        let blueprints = self.blueprints.as_ref().unwrap();
+        let runtimes = self.runtimes.as_ref().unwrap();
+        flow.tasks
+            .iter()
+            .filter(|task| {
+                matches!(
+                    (&task.typ, &task.job),
+                    (TaskType::Blueprint, JobType::Container)
+                )
+            })
+            .filter_map(|task| {
+                let container = blueprints[&task.name].container.as_ref()?;
+                Some((runtimes.for_blueprint(&task.name), container))
+            })
+            .collect()
+    }
+
+    /// Networks that the containers of the given Flow join, paired with the
+    /// runtime that has to create them. Every network is listed once per
+    /// runtime, in the order the Flow's Tasks first reference them.
+    pub fn flow_networks(&self, flow: &Flow) -> Vec<(Arc<dyn ContainerRuntime>, String, Network)> {
+        let networks = self.networks.as_ref().unwrap();
+        let mut seen = Vec::new();
+        let mut result = Vec::new();
+        for (runtime, container) in self.flow_containers(flow) {
+            for attachment in container.networks.iter().flatten() {
+                let key = (runtime.name(), attachment.name.clone());
+                if seen.contains(&key) {
+                    continue;
+                }
+                seen.push(key);
+                let network = networks.get(&attachment.name).cloned().unwrap_or_default();
+                result.push((runtime.clone(), attachment.name.clone(), network));
+            }
+        }
+        result
+    }
+
+    /// Named volumes that the containers of the given Flow mount, paired with
+    /// the runtime that has to create them. Every volume is listed once per
+    /// runtime, in the order the Flow's Tasks first reference them.
+    pub fn flow_volumes(&self, flow: &Flow) -> Vec<(Arc<dyn ContainerRuntime>, String, Volume)> {
+        let volumes = self.volumes.as_ref().unwrap();
+        let mut seen = Vec::new();
+        let mut result = Vec::new();
+        for (runtime, container) in self.flow_containers(flow) {
+            for mount in container.volumes.iter().flatten() {
+                let (MountType::Volume, Some(name)) = (mount.typ, &mount.source) else {
+                    continue;
+                };
+                let key = (runtime.name(), name.clone());
+                if seen.contains(&key) {
+                    continue;
+                }
+                seen.push(key);
+                let volume = volumes.get(name).cloned().unwrap_or_default();
+                result.push((runtime.clone(), name.clone(), volume));
+            }
+        }
+        result
+    }
+
+    /// Runtime that manages the named volume with the given name: the
+    /// runtime of the first Blueprint whose container mounts it, or the
+    /// default runtime of the Rune.
+    pub fn volume_runtime(&self, name: &str) -> Arc<dyn ContainerRuntime> {
+        let runtimes = self.runtimes.as_ref().unwrap();
+        let mut blueprints: Vec<_> = self.blueprints.as_ref().unwrap().iter().collect();
+        blueprints.sort_by_key(|(blueprint, _)| blueprint.as_str());
+        blueprints
+            .into_iter()
+            .find(|(_, blueprint)| {
+                blueprint.container.iter().any(|container| {
+                    container.volumes.iter().flatten().any(|mount| {
+                        mount.typ == MountType::Volume && mount.source.as_deref() == Some(name)
+                    })
+                })
+            })
+            .map(|(blueprint, _)| runtimes.for_blueprint(blueprint))
+            .unwrap_or_else(|| runtimes.default())
+    }
 }
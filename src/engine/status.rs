@@ -0,0 +1,252 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+
+use super::listing::render_table;
+use super::run_state::{ContainerRecord, FlowRun, StateFile, TaskRun, TaskStatus};
+use super::runtime::Runtimes;
+use super::state::State;
+
+/// Describes the latest run of the Flow with the given name, as recorded in
+/// the given state file, together with the live state of what it launched:
+/// whether its containers are still running and healthy, and whether the
+/// processes of its shell jobs are still alive.
+pub async fn describe_status(
+    flow_name: &str,
+    state: &State,
+    state_file: &StateFile,
+) -> Result<String> {
+    let flow = state.find_flow(flow_name)?;
+    let run_state = state_file.load().await?;
+    let Some(run) = run_state.flows.get(&flow.name) else {
+        return Ok(format!("Flow '{}' has not been run\n", flow.name));
+    };
+
+    let mut out = format!(
+        "Flow '{}' started {}{}\n",
+        flow.name,
+        run.started_at,
+        match &run.finished_at {
+            Some(finished_at) => format!(", finished {finished_at}"),
+            None => ", still running or interrupted".to_owned(),
+        }
+    );
+    if run.rune_hash != state_file.rune_hash() {
+        out.push_str("  (the .runer file has changed since)\n");
+    }
+
+    let runtimes = state.runtimes.as_ref().unwrap();
+    let mut rows = Vec::new();
+    for (id, task) in &run.tasks {
+        let live = if let Some(container) = &task.container {
+            describe_container(runtimes, container).await
+        } else if let Some(pid) = task.pid {
+            let alive = if process_alive(pid) {
+                "alive"
+            } else {
+                "exited"
+            };
+            format!("pid {pid} {alive}")
+        } else if let Some(image) = &task.image {
+            format!("image {} {}", image.tag, short_id(&image.id))
+        } else {
+            "-".to_owned()
+        };
+        rows.push(vec![
+            id.to_string(),
+            task.name.clone(),
+            task.job.clone(),
+            outcome(task),
+            duration(run, task),
+            live,
+        ]);
+    }
+    out.push_str(&render_table(
+        &["ID", "TASK", "JOB", "OUTCOME", "DURATION", "LIVE"],
+        &rows,
+    ));
+    Ok(out)
+}
+
+/// Live state of the given container, inspected with the runtime that
+/// launched it.
+async fn describe_container(runtimes: &Runtimes, container: &ContainerRecord) -> String {
+    let runtime = match runtimes.named(&container.runtime) {
+        Ok(runtime) => runtime,
+        Err(e) => return format!("container {}: {e}", container.name),
+    };
+    let Ok(info) = runtime.inspect(&container.name).await else {
+        return format!("container {} gone", container.name);
+    };
+    let mut live = format!("container {} {}", container.name, info.status);
+    if let Some(health) = &info.health {
+        live.push_str(&format!(" ({health})"));
+    }
+    if let (false, Some(code)) = (info.running, info.exit_code) {
+        live.push_str(&format!(" with code {code}"));
+    }
+    if !info.ports.is_empty() {
+        live.push_str(&format!(", ports {}", info.ports.join(", ")));
+    }
+    if !info.id.starts_with(&container.id) && !container.id.starts_with(&info.id) {
+        live.push_str(", replaced since");
+    }
+    live
+}
+
+fn outcome(task: &TaskRun) -> String {
+    let status = match task.status {
+        TaskStatus::Pending => "pending",
+        TaskStatus::Running => "running",
+        TaskStatus::Succeeded => "succeeded",
+        TaskStatus::Failed => "failed",
+        TaskStatus::Skipped => "skipped",
+    };
+    match (&task.status, &task.reason) {
+        (TaskStatus::Failed, Some(reason)) => {
+            // Only the first line, the rest of it doesn't fit in a table
+            let reason = reason.lines().next().unwrap_or_default();
+            format!("{status}: {reason}")
+        }
+        _ => status.to_owned(),
+    }
+}
+
+/// How long the Task took, or has been taking if it is still running.
+fn duration(run: &FlowRun, task: &TaskRun) -> String {
+    let parse = |timestamp: &str| DateTime::parse_from_rfc3339(timestamp).ok();
+    let Some(started_at) = task.started_at.as_deref().and_then(parse) else {
+        return "-".to_owned();
+    };
+    let finished_at = match task.finished_at.as_deref().and_then(parse) {
+        Some(finished_at) => finished_at.with_timezone(&Utc),
+        // An interrupted run never finishes its Tasks
+        None if run.finished_at.is_some() => return "-".to_owned(),
+        None => Utc::now(),
+    };
+    let elapsed = (finished_at - started_at.with_timezone(&Utc))
+        .to_std()
+        .unwrap_or_default();
+    let seconds = elapsed.as_secs();
+    match seconds {
+        0..=59 => format!("{:.1}s", elapsed.as_secs_f64()),
+        60..=3599 => format!("{}m{}s", seconds / 60, seconds % 60),
+        _ => format!("{}h{}m", seconds / 3600, seconds % 3600 / 60),
+    }
+}
+
+fn short_id(id: &str) -> &str {
+    let id = id.strip_prefix("sha256:").unwrap_or(id);
+    &id[..id.len().min(12)]
+}
+
+/// Whether a process with the given ID exists. The ID may have been reused
+/// by an unrelated process since it was recorded.
+fn process_alive(pid: u32) -> bool {
+    // Signal 0 only checks the process, which may belong to another user
+    let signaled = unsafe { libc::kill(pid as libc::pid_t, 0) == 0 };
+    signaled || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::model::runer::{Container, Rune};
+
+    use super::super::runtime::fake::FakeRuntime;
+    use super::super::runtime::ContainerRuntime;
+    use super::*;
+
+    const RUNE: &str = r#"
+blueprints:
+    db:
+        container: { name: db, image: postgres }
+    cache:
+        container: { name: cache, image: redis }
+    api:
+        image: { tag: me/api, context: . }
+    migrate:
+        shell: { commands: [./migrate.sh] }
+    seed:
+        shell: { commands: [./seed.sh] }
+flows:
+    - name: stack
+      tasks:
+          - { id: 1, type: Blueprint, name: db, job: container }
+          - { id: 2, type: Blueprint, name: cache, job: container }
+          - { id: 3, type: Blueprint, name: api, job: image }
+          - { id: 4, type: Blueprint, name: migrate, job: shell, depends: 1 }
+          - { id: 5, type: Blueprint, name: seed, job: shell, depends: 4 }
+"#;
+
+    /// The latest run of `stack`, as the state file has it.
+    fn state_json(live_pid: u32, dead_pid: u32) -> String {
+        format!(
+            r#"{{ "flows": {{ "stack": {{
+  "rune_hash": "0000000000000000",
+  "started_at": "2026-10-17T10:00:00+00:00",
+  "finished_at": "2026-10-17T10:02:10+00:00",
+  "tasks": {{
+    "1": {{ "name": "db", "job": "Blueprint/container", "status": "succeeded",
+      "started_at": "2026-10-17T10:00:00+00:00", "finished_at": "2026-10-17T10:00:01.500+00:00",
+      "container": {{ "name": "db", "id": "fake000000000001", "runtime": "fake" }} }},
+    "2": {{ "name": "cache", "job": "Blueprint/container", "status": "succeeded",
+      "started_at": "2026-10-17T10:00:00+00:00", "finished_at": "2026-10-17T10:00:02+00:00",
+      "container": {{ "name": "cache", "id": "0123456789abcdef", "runtime": "fake" }} }},
+    "3": {{ "name": "api", "job": "Blueprint/image", "status": "succeeded",
+      "started_at": "2026-10-17T10:00:00+00:00", "finished_at": "2026-10-17T10:02:05+00:00",
+      "image": {{ "tag": "me/api", "id": "sha256:9f86d081884c7d65", "runtime": "fake" }} }},
+    "4": {{ "name": "migrate", "job": "Blueprint/shell", "status": "failed",
+      "reason": "exit status: 2\nlast output: relation exists",
+      "started_at": "2026-10-17T10:00:02+00:00", "finished_at": "2026-10-17T10:00:03+00:00",
+      "pid": {dead_pid} }},
+    "5": {{ "name": "seed", "job": "Blueprint/shell", "status": "running",
+      "started_at": "2026-10-17T10:00:03+00:00", "pid": {live_pid} }}
+  }}
+}} }} }}"#
+        )
+    }
+
+    #[test]
+    fn status_shows_the_recorded_run_and_what_is_still_alive() {
+        let dir = std::env::temp_dir().join(format!("runer-status-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join(".runer.d")).unwrap();
+        let file = dir.join("stack.runer");
+        std::fs::write(&file, RUNE).unwrap();
+        let mut exited = std::process::Command::new("true").spawn().unwrap();
+        exited.wait().unwrap();
+        std::fs::write(
+            dir.join(".runer.d/state.json"),
+            state_json(std::process::id(), exited.id()),
+        )
+        .unwrap();
+
+        let runtime = Arc::new(FakeRuntime::default());
+        let db: Container = serde_yaml::from_str("{ name: db, image: postgres }").unwrap();
+        smol::block_on(runtime.run(&db)).unwrap();
+        let rune: Rune = serde_yaml::from_str(RUNE).unwrap();
+        let state = State {
+            runtimes: Some(Arc::new(Runtimes::single(runtime))),
+            ..State::from_rune(rune)
+        };
+        let state_file = StateFile::for_rune(&file).unwrap();
+        let status = smol::block_on(describe_status("stack", &state, &state_file));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let (dead, live) = (exited.id(), std::process::id());
+        assert_eq!(
+            status.unwrap(),
+            format!(
+                "Flow 'stack' started 2026-10-17T10:00:00+00:00, finished 2026-10-17T10:02:10+00:00
+  (the .runer file has changed since)
+  ID  TASK     JOB                  OUTCOME                 DURATION  LIVE
+  1   db       Blueprint/container  succeeded               1.5s      container db running
+  2   cache    Blueprint/container  succeeded               2.0s      container cache gone
+  3   api      Blueprint/image      succeeded               2m5s      image me/api 9f86d081884c
+  4   migrate  Blueprint/shell      failed: exit status: 2  1.0s      pid {dead} exited
+  5   seed     Blueprint/shell      running                 -         pid {live} alive
+"
+            )
+        );
+    }
+}
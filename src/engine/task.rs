@@ -1,130 +1,105 @@
-use std::collections::HashMap;
 use std::sync::Arc;
-use std::time::Duration;
 
+use anyhow::{anyhow, Result};
 use smol::channel::Sender;
-use smol::lock::Mutex;
-use smol::process::Child;
-use smol::Timer;
+use smol::future::Boxed;
 
-use crate::model::runer::{Blueprint, JobType, Task, TaskType};
+use crate::model::runer::{JobType, Task, TaskType};
 
+use super::error::{error_kind, ErrorKind};
 use super::job::{
     create_docker_image, run_docker_container, run_shell_script, set_environment_variables,
 };
+use super::output::TaskLog;
+use super::run_state::Launched;
+use super::scheduler::TaskOutcome;
+use super::state::State;
 
-pub async fn run_task(
-    tx: Sender<Option<(u32, Child)>>,
-    task: Task,
-    handles: Arc<Mutex<HashMap<u32, Child>>>,
-    blueprints: Arc<HashMap<String, Blueprint>>,
-    env: Arc<HashMap<String, Vec<(String, String)>>>,
-) {
-    if let Some(depends) = task.depends {
-        wait_until_parent_task_command_is_finished(depends, task.id, handles.clone()).await;
+/// Runs the given Task of the Flow with the given name until its job is
+/// finished, then notifies the executor with its ID and outcome through the
+/// given channel. The start of the Task is recorded in the state file first.
+///
+/// The Task is expected to be started only after all of its parent Tasks
+/// are finished, it doesn't wait for them on its own.
+pub async fn run_task(tx: Sender<(u32, TaskOutcome)>, flow: String, task: Task, state: State) {
+    if let Some(state_file) = &state.state_file {
+        state_file.task_started(&flow, task.id).await;
     }
+    let outcome = match run_job(&flow, &task, &state).await {
+        Ok(()) => TaskOutcome::Succeeded,
+        Err(e) => TaskOutcome::Failed {
+            reason: format!("{e:#}"),
+            // Errors that aren't told apart are the Task's own failure
+            kind: match error_kind(&e) {
+                ErrorKind::Other => ErrorKind::Task,
+                kind => kind,
+            },
+        },
+    };
+    let _ = tx.send((task.id, outcome)).await;
+}
 
+/// Runs the job of the given Task.
+///
+/// The Rune is validated before it gets run, so the Fragments that the Task
+/// refers to are expected to exist. If they don't, the Task fails rather
+/// than runer.
+async fn run_job(flow: &str, task: &Task, state: &State) -> Result<()> {
+    let missing = |what: &str| anyhow!("task {} has no {what} '{}'", task.id, task.name);
     match task.typ {
         TaskType::Blueprint => {
-            let (_, blueprint) = blueprints.get_key_value(&task.name).unwrap_or_else(|| {
-                panic!(
-                    "Task ID:{}, Name: {}, no bluprint found",
-                    task.id, task.name
-                );
-            });
-            let handle = match task.job {
+            let blueprints = state.blueprints.as_ref().unwrap();
+            let blueprint = blueprints
+                .get(&task.name)
+                .ok_or_else(|| missing("blueprint"))?;
+            let runtime = state.runtimes.as_ref().unwrap().for_blueprint(&task.name);
+            let sink = Arc::new(TaskLog::for_task(task, state.run_log.as_deref())).sink();
+            let record = |launched: Launched| -> Boxed<()> {
+                let state_file = state.state_file.clone();
+                let (flow, id) = (flow.to_owned(), task.id);
+                Box::pin(async move {
+                    if let Some(state_file) = state_file {
+                        state_file.task_launched(&flow, id, launched).await;
+                    }
+                })
+            };
+            match task.job {
                 JobType::Image => {
-                    create_docker_image(blueprint.image.as_ref().unwrap_or_else(|| {
-                        panic!(
-                            "Task ID: {}, Name: {}, no image job found",
-                            task.id, task.name
-                        );
-                    }))
-                    .await
+                    let image = blueprint
+                        .image
+                        .as_ref()
+                        .ok_or_else(|| missing("image job in blueprint"))?;
+                    create_docker_image(image, runtime.as_ref(), sink, &record).await
                 }
                 JobType::Container => {
-                    run_docker_container(blueprint.container.as_ref().unwrap_or_else(|| {
-                        panic!(
-                            "Task ID: {}, Name: {}, no container job found",
-                            task.id, task.name
-                        );
-                    }))
-                }
-                JobType::Set => {
-                    todo!("Decide how to handle 'Set' jobs inside blueprints");
+                    let container = blueprint
+                        .container
+                        .as_ref()
+                        .ok_or_else(|| missing("container job in blueprint"))?;
+                    run_docker_container(container, runtime.as_ref(), sink, &record).await
                 }
+                JobType::Set => Err(anyhow!(
+                    "task {} can't run a 'set' job on blueprint '{}', 'set' jobs are only \
+                     valid for Env tasks",
+                    task.id,
+                    task.name
+                )),
                 JobType::Shell => {
-                    run_shell_script(blueprint.shell.as_ref().unwrap_or_else(|| {
-                        panic!(
-                            "Task ID: {}, Name: {}, no shell job found",
-                            task.id, task.name
-                        );
-                    }))
-                    .await
+                    let shell = blueprint
+                        .shell
+                        .as_ref()
+                        .ok_or_else(|| missing("shell job in blueprint"))?;
+                    run_shell_script(shell, sink, &record).await
                 }
-            };
-            let _ = tx
-                .send(Some((
-                    task.id,
-                    handle.unwrap_or_else(|_| {
-                        panic!("FATAL ERROR: Something wrong with process handles")
-                    }),
-                )))
-                .await;
+            }
         }
         TaskType::Env => {
-            let (_, env) = env.get_key_value(&task.name).unwrap_or_else(|| {
-                panic!(
-                    "Task ID: {}, Name: {}, no environment variable list is found",
-                    task.id, task.name
-                );
-            });
-            let _ = tx
-                .send(Some((
-                    task.id,
-                    set_environment_variables(env).unwrap_or_else(|_| {
-                        panic!("FATAL ERROR: Something wrong with process handles")
-                    }),
-                )))
-                .await;
-        }
-    }
-}
-
-async fn wait_until_parent_task_command_is_finished(
-    parent_handle_id: u32,
-    child_task_id: u32,
-    handles: Arc<Mutex<HashMap<u32, Child>>>,
-) {
-    // Here it waits until the parent handle becomes available ================
-    let mut available = false;
-    while !available {
-        let handles = handles.lock().await;
-        if handles.contains_key(&parent_handle_id) {
-            available = true;
-        }
-        Timer::after(Duration::from_millis(5)).await;
-    }
-
-    // Then it makes sure that the parent task finishes successfully ==========
-    let mut finished_successfully = false;
-    while !finished_successfully {
-        let mut handles = handles.lock().await;
-        let handle = handles.get_mut(&parent_handle_id).unwrap_or_else(|| {
-            panic!(
-                "Task {} can not find the parent process handle",
-                child_task_id
-            )
-        });
-        match handle.try_status() {
-            Ok(Some(status)) => {
-                if status.success() {
-                    finished_successfully = true;
-                }
-            }
-            Ok(None) => {}
-            Err(e) => panic!("error attempting to wait: {e}"),
+            let env = state.env.as_ref().unwrap();
+            let env = env
+                .get(&task.name)
+                .ok_or_else(|| missing("environment variable list"))?;
+            set_environment_variables(env);
+            Ok(())
         }
-        Timer::after(Duration::from_millis(5)).await;
     }
 }
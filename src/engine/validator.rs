@@ -0,0 +1,865 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::net::IpAddr;
+
+use regex::Regex;
+
+use crate::model::runer::{
+    Blueprint, EnvSets, ExecutionEnvironment, Flow, HealthCheck, JobType, Mount, MountType,
+    Network, PortMapping, ReadyCondition, Rune, Task, TaskType, Volume,
+};
+
+use super::diagnostic::{did_you_mean, suggestion};
+use super::duration::parse_duration;
+use super::graph::TaskGraph;
+use super::http::split_url;
+
+/// A single step of the path that leads from the root of a Rune to the
+/// offending value. Keys correspond to yaml mapping keys and indexes to
+/// yaml sequence positions.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+/// A semantic problem found inside a Rune.
+///
+/// _path_ points to the value that caused the problem, so that it can be
+/// reported back to the user with enough context to fix it. _help_ carries an
+/// optional hint on how to fix it.
+#[derive(Clone, Debug)]
+pub struct Issue {
+    pub path: Vec<Segment>,
+    pub message: String,
+    pub help: Option<String>,
+}
+
+impl Issue {
+    fn new(path: &[Segment], message: impl Into<String>) -> Self {
+        Self {
+            path: path.to_vec(),
+            message: message.into(),
+            help: None,
+        }
+    }
+
+    /// Attaches a "did you mean" hint if one of the candidates is close
+    /// enough to the misspelled name.
+    fn suggest<'a>(mut self, name: &str, candidates: impl IntoIterator<Item = &'a String>) -> Self {
+        self.help = did_you_mean(name, candidates.into_iter().map(String::as_str)).map(suggestion);
+        self
+    }
+
+    /// Dotted representation of the _path_, e.g. `flows[0].tasks[1].depends`
+    pub fn location(&self) -> String {
+        let mut location = String::new();
+        for segment in &self.path {
+            match segment {
+                Segment::Key(key) => {
+                    if !location.is_empty() {
+                        location.push('.');
+                    }
+                    location.push_str(key);
+                }
+                Segment::Index(idx) => location.push_str(&format!("[{idx}]")),
+            }
+        }
+        location
+    }
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.path.is_empty() {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "{}: {}", self.location(), self.message)
+        }
+    }
+}
+
+/// Walks every Fragment of the given Rune and collects all the semantic
+/// problems it can find, instead of stopping at the first one.
+///
+/// A Rune that passes this validation can be executed without the engine
+/// having to double check references between its Fragments.
+pub fn validate_rune(rune: &Rune) -> Vec<Issue> {
+    let mut issues = Vec::new();
+
+    let empty_blueprints = HashMap::new();
+    let blueprints = rune.blueprints.as_ref().unwrap_or(&empty_blueprints);
+    let empty_env = HashMap::new();
+    let env = rune.env.as_ref().unwrap_or(&empty_env);
+    let empty_networks = HashMap::new();
+    let networks = rune.networks.as_ref().unwrap_or(&empty_networks);
+    let empty_volumes = HashMap::new();
+    let volumes = rune.volumes.as_ref().unwrap_or(&empty_volumes);
+
+    let mut path = vec![Segment::Key("blueprints".to_owned())];
+    let mut names: Vec<&String> = blueprints.keys().collect();
+    names.sort();
+    for name in names {
+        path.push(Segment::Key(name.clone()));
+        validate_blueprint(&blueprints[name], networks, volumes, &mut path, &mut issues);
+        path.pop();
+    }
+
+    let mut path = vec![Segment::Key("env".to_owned())];
+    let mut names: Vec<&String> = env.keys().collect();
+    names.sort();
+    for name in names {
+        path.push(Segment::Key(name.clone()));
+        for (idx, (key, _)) in env[name].iter().enumerate() {
+            if key.trim().is_empty() {
+                path.push(Segment::Index(idx));
+                issues.push(Issue::new(&path, "environment variable name is empty"));
+                path.pop();
+            }
+        }
+        path.pop();
+    }
+
+    let mut path = vec![Segment::Key("networks".to_owned())];
+    let mut names: Vec<&String> = networks.keys().collect();
+    names.sort();
+    for name in names {
+        path.push(Segment::Key(name.clone()));
+        validate_network(&networks[name], &mut path, &mut issues);
+        path.pop();
+    }
+
+    let mut path = vec![Segment::Key("volumes".to_owned())];
+    let mut names: Vec<&String> = volumes.keys().collect();
+    names.sort();
+    for name in names {
+        path.push(Segment::Key(name.clone()));
+        if let Some(driver) = &volumes[name].driver {
+            if driver.trim().is_empty() {
+                path.push(Segment::Key("driver".to_owned()));
+                issues.push(Issue::new(&path, "volume driver is empty"));
+                path.pop();
+            }
+        }
+        path.pop();
+    }
+
+    let mut path = vec![Segment::Key("flows".to_owned())];
+    match &rune.flows {
+        Some(flows) if !flows.is_empty() => {
+            let mut flow_names = HashSet::new();
+            for (idx, flow) in flows.iter().enumerate() {
+                path.push(Segment::Index(idx));
+                if !flow_names.insert(flow.name.as_str()) {
+                    path.push(Segment::Key("name".to_owned()));
+                    issues.push(Issue::new(
+                        &path,
+                        format!("duplicate flow name '{}'", flow.name),
+                    ));
+                    path.pop();
+                }
+                validate_flow(flow, blueprints, env, &mut path, &mut issues);
+                path.pop();
+            }
+        }
+        _ => issues.push(Issue::new(&[], "rune has no flow to run")),
+    }
+
+    issues
+}
+
+fn validate_network(network: &Network, path: &mut Vec<Segment>, issues: &mut Vec<Issue>) {
+    if let Some(driver) = &network.driver {
+        if driver.trim().is_empty() {
+            path.push(Segment::Key("driver".to_owned()));
+            issues.push(Issue::new(path, "network driver is empty"));
+            path.pop();
+        }
+    }
+    if let Some(subnet) = &network.subnet {
+        let valid = subnet.split_once('/').is_some_and(|(address, prefix)| {
+            match (address.parse::<IpAddr>(), prefix.parse::<u8>()) {
+                (Ok(IpAddr::V4(_)), Ok(prefix)) => prefix <= 32,
+                (Ok(IpAddr::V6(_)), Ok(prefix)) => prefix <= 128,
+                _ => false,
+            }
+        });
+        if !valid {
+            path.push(Segment::Key("subnet".to_owned()));
+            issues.push(Issue::new(
+                path,
+                format!("'{subnet}' is not a subnet in CIDR notation, e.g. 172.28.0.0/16"),
+            ));
+            path.pop();
+        }
+    }
+}
+
+/// First host port that both given mappings publish, if any.
+fn overlapping_host_port(a: &PortMapping, b: &PortMapping) -> Option<u16> {
+    let (host_a, host_b) = (a.host?, b.host?);
+    let same_ip = match (a.host_ip, b.host_ip) {
+        (Some(ip_a), Some(ip_b)) => ip_a == ip_b || ip_a.is_unspecified() || ip_b.is_unspecified(),
+        _ => true,
+    };
+    if a.protocol != b.protocol || !same_ip {
+        return None;
+    }
+    let start = host_a.start.max(host_b.start);
+    (start <= host_a.end.min(host_b.end)).then_some(start)
+}
+
+fn validate_mount(
+    mount: &Mount,
+    volumes: &HashMap<String, Volume>,
+    path: &[Segment],
+    issues: &mut Vec<Issue>,
+) {
+    if !mount.target.starts_with('/') {
+        issues.push(Issue::new(
+            path,
+            format!("mount target '{}' is not an absolute path", mount.target),
+        ));
+    }
+    match (mount.typ, &mount.source) {
+        (MountType::Tmpfs, Some(_)) => {
+            issues.push(Issue::new(path, "tmpfs mounts don't have a source"));
+        }
+        (MountType::Tmpfs, None) => {}
+        (typ, None) => {
+            issues.push(Issue::new(path, format!("{typ} mount has no source")));
+        }
+        (MountType::Bind, Some(source)) => {
+            if !mount.host_path().is_some_and(|p| p.exists()) {
+                issues.push(Issue::new(
+                    path,
+                    format!("bind mount source '{source}' doesn't exist"),
+                ));
+            }
+        }
+        (MountType::Volume, Some(source)) => {
+            if !volumes.contains_key(source) {
+                issues.push(
+                    Issue::new(
+                        path,
+                        format!("volume '{source}' is not declared in 'volumes'"),
+                    )
+                    .suggest(source, volumes.keys()),
+                );
+            }
+        }
+    }
+}
+
+fn validate_blueprint(
+    blueprint: &Blueprint,
+    networks: &HashMap<String, Network>,
+    volumes: &HashMap<String, Volume>,
+    path: &mut Vec<Segment>,
+    issues: &mut Vec<Issue>,
+) {
+    if blueprint.image.is_none() && blueprint.container.is_none() && blueprint.shell.is_none() {
+        issues.push(Issue::new(
+            path,
+            "blueprint defines none of 'image', 'container' or 'shell'",
+        ));
+    }
+
+    if let Some(image) = &blueprint.image {
+        path.push(Segment::Key("image".to_owned()));
+        if image.context.trim().is_empty() {
+            path.push(Segment::Key("context".to_owned()));
+            issues.push(Issue::new(path, "image context is empty"));
+            path.pop();
+        }
+        if image.tag.trim().is_empty() {
+            path.push(Segment::Key("tag".to_owned()));
+            issues.push(Issue::new(path, "image tag is empty"));
+            path.pop();
+        }
+        path.pop();
+    }
+
+    if let Some(container) = &blueprint.container {
+        path.push(Segment::Key("container".to_owned()));
+        if container.name.trim().is_empty() {
+            path.push(Segment::Key("name".to_owned()));
+            issues.push(Issue::new(path, "container name is empty"));
+            path.pop();
+        }
+        if container.image.trim().is_empty() {
+            path.push(Segment::Key("image".to_owned()));
+            issues.push(Issue::new(path, "container image is empty"));
+            path.pop();
+        }
+        if let Some(entrypoint) = &container.entrypoint {
+            if entrypoint.is_empty() {
+                path.push(Segment::Key("entrypoint".to_owned()));
+                issues.push(Issue::new(path, "missing entrypoint command/arguments"));
+                path.pop();
+            }
+        }
+        if let Some(hc) = &container.hc {
+            path.push(Segment::Key("hc".to_owned()));
+            validate_health_check(hc, true, path, issues);
+            path.pop();
+        }
+        if let Some(ready) = &container.ready {
+            path.push(Segment::Key("ready".to_owned()));
+            if ready.condition == Some(ReadyCondition::Healthy) && container.hc.is_none() {
+                path.push(Segment::Key("condition".to_owned()));
+                issues.push(Issue::new(
+                    path,
+                    "container can't become healthy without a health check ('hc')",
+                ));
+                path.pop();
+            }
+            if let Some(timeout) = &ready.timeout {
+                validate_timeout(timeout, path, issues);
+            }
+            path.pop();
+        }
+        if let Some(ports) = &container.ports {
+            path.push(Segment::Key("ports".to_owned()));
+            for (idx, mapping) in ports.iter().enumerate() {
+                let overlapping = ports[..idx].iter().find_map(|other| {
+                    let port = overlapping_host_port(mapping, other)?;
+                    Some((other, port))
+                });
+                if let Some((other, port)) = overlapping {
+                    path.push(Segment::Index(idx));
+                    issues.push(Issue::new(
+                        path,
+                        format!(
+                            "host port {port}/{} is already published by '{other}'",
+                            mapping.protocol
+                        ),
+                    ));
+                    path.pop();
+                } else if ports[..idx].contains(mapping) {
+                    path.push(Segment::Index(idx));
+                    issues.push(Issue::new(path, format!("'{mapping}' is listed twice")));
+                    path.pop();
+                }
+            }
+            path.pop();
+        }
+        if let Some(mounts) = &container.volumes {
+            path.push(Segment::Key("volumes".to_owned()));
+            for (idx, mount) in mounts.iter().enumerate() {
+                path.push(Segment::Index(idx));
+                validate_mount(mount, volumes, path, issues);
+                path.pop();
+            }
+            path.pop();
+        }
+        if let Some(attachments) = &container.networks {
+            path.push(Segment::Key("networks".to_owned()));
+            let mut joined = HashSet::new();
+            for (idx, attachment) in attachments.iter().enumerate() {
+                path.push(Segment::Index(idx));
+                if !networks.contains_key(&attachment.name) {
+                    issues.push(
+                        Issue::new(
+                            path,
+                            format!(
+                                "network '{}' is not declared in 'networks'",
+                                attachment.name
+                            ),
+                        )
+                        .suggest(&attachment.name, networks.keys()),
+                    );
+                } else if !joined.insert(attachment.name.as_str()) {
+                    issues.push(Issue::new(
+                        path,
+                        format!("container joins network '{}' twice", attachment.name),
+                    ));
+                }
+                if attachment.aliases.iter().any(|a| a.trim().is_empty()) {
+                    issues.push(Issue::new(path, "network alias is empty"));
+                }
+                path.pop();
+            }
+            path.pop();
+        }
+        path.pop();
+    }
+
+    if let Some(shell) = &blueprint.shell {
+        path.push(Segment::Key("shell".to_owned()));
+        if shell.commands.is_empty() {
+            path.push(Segment::Key("commands".to_owned()));
+            issues.push(Issue::new(path, "shell has no commands"));
+            path.pop();
+        }
+        if let Some(hc) = &shell.hc {
+            path.push(Segment::Key("hc".to_owned()));
+            validate_health_check(hc, false, path, issues);
+            path.pop();
+        }
+        if let Some(timeout) = &shell.timeout {
+            if shell.hc.is_none() {
+                path.push(Segment::Key("timeout".to_owned()));
+                issues.push(Issue::new(
+                    path,
+                    "only a shell with a health check ('hc') is waited for",
+                ));
+                path.pop();
+            } else {
+                validate_timeout(timeout, path, issues);
+            }
+        }
+        path.pop();
+    }
+}
+
+fn validate_timeout(timeout: &str, path: &mut Vec<Segment>, issues: &mut Vec<Issue>) {
+    if parse_duration(timeout).is_none() {
+        path.push(Segment::Key("timeout".to_owned()));
+        issues.push(Issue::new(
+            path,
+            format!("invalid duration '{timeout}', expected e.g. '90s'"),
+        ));
+        path.pop();
+    }
+}
+
+fn validate_health_check(
+    hc: &HealthCheck,
+    in_container: bool,
+    path: &mut Vec<Segment>,
+    issues: &mut Vec<Issue>,
+) {
+    let kinds = [
+        hc.command.is_some(),
+        hc.tcp.is_some(),
+        hc.http.is_some(),
+        hc.log.is_some(),
+    ];
+    match kinds.iter().filter(|given| **given).count() {
+        0 => issues.push(Issue::new(
+            path,
+            "health check defines none of 'command', 'tcp', 'http' or 'log'",
+        )),
+        1 => {}
+        _ => issues.push(Issue::new(
+            path,
+            "health check defines more than one of 'command', 'tcp', 'http' and 'log'",
+        )),
+    }
+
+    if let Some((environment, command)) = &hc.command {
+        path.push(Segment::Key("command".to_owned()));
+        if command.trim().is_empty() {
+            issues.push(Issue::new(path, "missing healthcheck command/arguments"));
+        }
+        if let (ExecutionEnvironment::Container, false) = (environment, in_container) {
+            issues.push(Issue::new(
+                path,
+                "'Container' health checks need a container, use 'Local'",
+            ));
+        }
+        path.pop();
+    }
+    if let Some(address) = &hc.tcp {
+        let valid = address
+            .rsplit_once(':')
+            .is_some_and(|(host, port)| !host.is_empty() && port.parse::<u16>().is_ok());
+        if !valid {
+            path.push(Segment::Key("tcp".to_owned()));
+            issues.push(Issue::new(
+                path,
+                format!("invalid address '{address}', expected e.g. 'localhost:5432'"),
+            ));
+            path.pop();
+        }
+    }
+    if let Some(probe) = &hc.http {
+        if let Err(e) = split_url(&probe.url) {
+            path.push(Segment::Key("http".to_owned()));
+            path.push(Segment::Key("url".to_owned()));
+            issues.push(Issue::new(path, format!("{e}")));
+            path.pop();
+            path.pop();
+        }
+    }
+    if let Some(pattern) = &hc.log {
+        if let Err(e) = Regex::new(pattern) {
+            path.push(Segment::Key("log".to_owned()));
+            issues.push(Issue::new(path, format!("invalid regular expression: {e}")));
+            path.pop();
+        }
+    }
+    if let Some(interval) = &hc.interval {
+        if parse_duration(interval).is_none() {
+            path.push(Segment::Key("interval".to_owned()));
+            issues.push(Issue::new(
+                path,
+                format!("invalid duration '{interval}', expected e.g. '10s'"),
+            ));
+            path.pop();
+        }
+    }
+}
+
+fn validate_flow(
+    flow: &Flow,
+    blueprints: &HashMap<String, Blueprint>,
+    env: &EnvSets,
+    path: &mut Vec<Segment>,
+    issues: &mut Vec<Issue>,
+) {
+    if flow.name.trim().is_empty() {
+        path.push(Segment::Key("name".to_owned()));
+        issues.push(Issue::new(path, "flow name is empty"));
+        path.pop();
+    }
+
+    path.push(Segment::Key("tasks".to_owned()));
+    if flow.tasks.is_empty() {
+        issues.push(Issue::new(
+            path,
+            format!("flow '{}' should have at least one task", flow.name),
+        ));
+    }
+
+    let mut ids = HashSet::new();
+    for (idx, task) in flow.tasks.iter().enumerate() {
+        path.push(Segment::Index(idx));
+        if !ids.insert(task.id) {
+            path.push(Segment::Key("id".to_owned()));
+            issues.push(Issue::new(
+                path,
+                format!("duplicate task id {} in flow '{}'", task.id, flow.name),
+            ));
+            path.pop();
+        }
+        path.pop();
+    }
+
+    for (idx, task) in flow.tasks.iter().enumerate() {
+        path.push(Segment::Index(idx));
+        validate_task(task, &ids, blueprints, env, path, issues);
+        path.pop();
+    }
+
+    // Cycles can only be reliably detected if each Task is uniquely
+    // identifiable.
+    if ids.len() == flow.tasks.len() {
+        if let Err(e) = TaskGraph::from_flow(flow) {
+            issues.push(Issue::new(path, format!("flow '{}': {e}", flow.name)));
+        }
+    }
+    path.pop();
+}
+
+fn validate_task(
+    task: &Task,
+    ids: &HashSet<u32>,
+    blueprints: &HashMap<String, Blueprint>,
+    env: &EnvSets,
+    path: &mut Vec<Segment>,
+    issues: &mut Vec<Issue>,
+) {
+    path.push(Segment::Key("name".to_owned()));
+    match task.typ {
+        TaskType::Blueprint => match blueprints.get(&task.name) {
+            Some(blueprint) => {
+                path.pop();
+                path.push(Segment::Key("job".to_owned()));
+                let defined = match task.job {
+                    JobType::Image => blueprint.image.is_some(),
+                    JobType::Container => blueprint.container.is_some(),
+                    JobType::Shell => blueprint.shell.is_some(),
+                    JobType::Set => {
+                        issues.push(Issue::new(
+                            path,
+                            format!(
+                                "task {} can't run a 'set' job on blueprint '{}', 'set' jobs \
+                                 are only valid for Env tasks",
+                                task.id, task.name
+                            ),
+                        ));
+                        true
+                    }
+                };
+                if !defined {
+                    issues.push(Issue::new(
+                        path,
+                        format!(
+                            "task {} runs the '{}' job but blueprint '{}' doesn't define it",
+                            task.id, task.job, task.name
+                        ),
+                    ));
+                }
+            }
+            None => issues.push(
+                Issue::new(
+                    path,
+                    format!(
+                        "task {} references unknown blueprint '{}'",
+                        task.id, task.name
+                    ),
+                )
+                .suggest(&task.name, blueprints.keys()),
+            ),
+        },
+        TaskType::Env => {
+            if !env.contains_key(&task.name) {
+                issues.push(
+                    Issue::new(
+                        path,
+                        format!(
+                            "task {} references unknown environment variable list '{}'",
+                            task.id, task.name
+                        ),
+                    )
+                    .suggest(&task.name, env.keys()),
+                );
+            }
+            path.pop();
+            path.push(Segment::Key("job".to_owned()));
+            if !matches!(task.job, JobType::Set) {
+                issues.push(Issue::new(
+                    path,
+                    format!(
+                        "task {} is an Env task, its job should be 'set' instead of '{}'",
+                        task.id, task.job
+                    ),
+                ));
+            }
+        }
+    }
+    path.pop();
+
+    path.push(Segment::Key("depends".to_owned()));
+    for (idx, depends) in task.depends.iter().enumerate() {
+        if task.depends.len() > 1 {
+            path.push(Segment::Index(idx));
+        }
+        if *depends == task.id {
+            issues.push(Issue::new(
+                path,
+                format!("task {} depends on itself", task.id),
+            ));
+        } else if !ids.contains(depends) {
+            issues.push(Issue::new(
+                path,
+                format!("task {} depends on nonexistent task {}", task.id, depends),
+            ));
+        }
+        if task.depends.len() > 1 {
+            path.pop();
+        }
+    }
+    path.pop();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Issues of the given Rune, rendered with their location.
+    fn issues(rune: &str) -> Vec<String> {
+        let rune: Rune = serde_yaml::from_str(rune).unwrap();
+        validate_rune(&rune).iter().map(Issue::to_string).collect()
+    }
+
+    fn helps(rune: &str) -> Vec<Option<String>> {
+        let rune: Rune = serde_yaml::from_str(rune).unwrap();
+        validate_rune(&rune)
+            .into_iter()
+            .map(|issue| issue.help)
+            .collect()
+    }
+
+    #[test]
+    fn every_issue_is_collected_in_one_pass() {
+        let rune = r#"
+blueprints:
+    api:
+        container: { name: "", image: me/api }
+env:
+    dev: [["", x]]
+flows:
+    - name: ""
+      tasks: []
+"#;
+        assert_eq!(
+            issues(rune),
+            [
+                "blueprints.api.container.name: container name is empty",
+                "env.dev[0]: environment variable name is empty",
+                "flows[0].name: flow name is empty",
+                "flows[0].tasks: flow '' should have at least one task",
+            ]
+        );
+    }
+
+    #[test]
+    fn duplicate_task_ids_and_flow_names_are_reported() {
+        let rune = r#"
+env:
+    dev: [[A, "1"]]
+flows:
+    - name: setup
+      tasks:
+          - { id: 1, type: Env, name: dev, job: set }
+          - { id: 1, type: Env, name: dev, job: set }
+    - name: setup
+      tasks:
+          - { id: 1, type: Env, name: dev, job: set }
+"#;
+        assert_eq!(
+            issues(rune),
+            [
+                "flows[0].tasks[1].id: duplicate task id 1 in flow 'setup'",
+                "flows[1].name: duplicate flow name 'setup'",
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_names_come_with_a_suggestion() {
+        let rune = r#"
+blueprints:
+    postgres:
+        container: { name: db, image: postgres }
+env:
+    dev: [[A, "1"]]
+flows:
+    - name: setup
+      tasks:
+          - { id: 1, type: Blueprint, name: postgre, job: container }
+          - { id: 2, type: Env, name: prod, job: set }
+"#;
+        assert_eq!(
+            issues(rune),
+            [
+                "flows[0].tasks[0].name: task 1 references unknown blueprint 'postgre'",
+                "flows[0].tasks[1].name: task 2 references unknown environment variable list \
+                 'prod'",
+            ]
+        );
+        assert_eq!(
+            helps(rune),
+            [Some("did you mean `postgres`?".to_owned()), None]
+        );
+    }
+
+    #[test]
+    fn dependencies_on_itself_or_unknown_tasks_are_reported() {
+        let rune = r#"
+env:
+    dev: [[A, "1"]]
+flows:
+    - name: setup
+      tasks:
+          - { id: 1, type: Env, name: dev, job: set, depends: 1 }
+          - { id: 2, type: Env, name: dev, job: set, depends: [1, 7] }
+"#;
+        assert_eq!(
+            issues(rune),
+            [
+                "flows[0].tasks[0].depends: task 1 depends on itself",
+                "flows[0].tasks[1].depends[1]: task 2 depends on nonexistent task 7",
+            ]
+        );
+    }
+
+    #[test]
+    fn dependency_cycles_are_reported() {
+        let rune = r#"
+env:
+    dev: [[A, "1"]]
+flows:
+    - name: setup
+      tasks:
+          - { id: 1, type: Env, name: dev, job: set, depends: 3 }
+          - { id: 2, type: Env, name: dev, job: set, depends: 1 }
+          - { id: 3, type: Env, name: dev, job: set, depends: 2 }
+"#;
+        assert_eq!(
+            issues(rune),
+            ["flows[0].tasks: flow 'setup': Dependency cycle: 1 -> 2 -> 3 -> 1"]
+        );
+    }
+
+    #[test]
+    fn health_checks_need_exactly_one_probe() {
+        let rune = r#"
+blueprints:
+    none:
+        container: { name: none, image: busybox, hc: { retries: 3 } }
+    both:
+        container:
+            name: both
+            image: busybox
+            hc: { tcp: "localhost:80", log: ready }
+flows:
+    - name: setup
+      tasks:
+          - { id: 1, type: Blueprint, name: none, job: container }
+          - { id: 2, type: Blueprint, name: both, job: container }
+"#;
+        assert_eq!(
+            issues(rune),
+            [
+                "blueprints.both.container.hc: health check defines more than one of 'command', \
+                 'tcp', 'http' and 'log'",
+                "blueprints.none.container.hc: health check defines none of 'command', 'tcp', \
+                 'http' or 'log'",
+            ]
+        );
+    }
+
+    #[test]
+    fn overlapping_host_ports_are_reported() {
+        let rune = r#"
+blueprints:
+    web:
+        container:
+            name: web
+            image: nginx
+            ports: ["8080-8090:80-90", "127.0.0.1:8085:443", "8085:443/udp"]
+flows:
+    - name: setup
+      tasks:
+          - { id: 1, type: Blueprint, name: web, job: container }
+"#;
+        assert_eq!(
+            issues(rune),
+            [
+                "blueprints.web.container.ports[1]: host port 8085/tcp is already published by \
+              '8080-8090:80-90/tcp'"
+            ]
+        );
+    }
+
+    #[test]
+    fn a_timeout_needs_a_health_check() {
+        let rune = r#"
+blueprints:
+    server:
+        shell: { commands: ["make serve"], timeout: 30s }
+    checked:
+        shell:
+            commands: ["make serve"]
+            hc: { tcp: "localhost:8000" }
+            timeout: soon
+flows:
+    - name: setup
+      tasks:
+          - { id: 1, type: Blueprint, name: server, job: shell }
+          - { id: 2, type: Blueprint, name: checked, job: shell }
+"#;
+        assert_eq!(
+            issues(rune),
+            [
+                "blueprints.checked.shell.timeout: invalid duration 'soon', expected e.g. '90s'",
+                "blueprints.server.shell.timeout: only a shell with a health check ('hc') is \
+                 waited for",
+            ]
+        );
+    }
+}
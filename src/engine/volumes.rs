@@ -0,0 +1,42 @@
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+
+use super::state::State;
+
+/// Removes the named volumes declared in the Rune, except the ones that are
+/// still in use by a container. Returns a line per volume that describes
+/// what happened to it.
+pub async fn prune_volumes(state: &State) -> Vec<String> {
+    let mut names: Vec<&String> = state.volumes.as_ref().unwrap().keys().collect();
+    names.sort();
+    let mut lines = Vec::new();
+    for name in names {
+        match state.volume_runtime(name).remove_volume(name).await {
+            Ok(()) => lines.push(format!("removed {name}")),
+            Err(e) => lines.push(format!("kept {name}: {e}")),
+        }
+    }
+    lines
+}
+
+/// Writes the content of the named volume with the given name into the given
+/// tar archive.
+pub async fn backup_volume(state: &State, name: &str, archive: &Path) -> Result<()> {
+    state
+        .volume_runtime(name)
+        .backup_volume(name, archive)
+        .await
+}
+
+/// Replaces the content of the named volume with the given name with the
+/// given tar archive. Files that aren't in the archive don't survive.
+pub async fn restore_volume(state: &State, name: &str, archive: &Path) -> Result<()> {
+    if !archive.is_file() {
+        return Err(anyhow!("No such archive: {}", archive.display()));
+    }
+    state
+        .volume_runtime(name)
+        .restore_volume(name, archive)
+        .await
+}
@@ -2,13 +2,18 @@ mod engine;
 mod model;
 mod start_up;
 
-use log::info;
+use log::{error, info};
 use std::time::Instant;
 
+use engine::error::error_kind;
+
 fn main() {
     let start = Instant::now();
     let args = start_up::parse_cmdline_args();
     start_up::initialize_logger();
-    start_up::handle_mod(args.mode);
+    if let Err(e) = start_up::handle_mod(args.mode) {
+        error!("{e:#}");
+        std::process::exit(error_kind(&e).exit_code());
+    }
     info!("Program executed in {:?}", start.elapsed());
 }
@@ -1,4 +1,11 @@
-use clap::{Args, Parser, Subcommand};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+
+use crate::engine::duration::parse_duration;
+
+use super::runer::RuntimeKind;
 
 #[derive(Parser)]
 #[command(version, author, about)]
@@ -13,6 +20,31 @@ pub enum Mode {
     #[command(alias = "r")]
     Run(RunArgs),
 
+    /// (alias <v>) Validates the given .runer files or the .runer files in the current directory without running them
+    #[command(alias = "v")]
+    Validate(ValidateArgs),
+
+    /// (alias <l>) Lists the flows, blueprints and environment variable lists of the given .runer file
+    #[command(alias = "l")]
+    List(ListArgs),
+
+    /// (alias <g>) Renders the task graph of a flow as Graphviz DOT or Mermaid
+    #[command(alias = "g")]
+    Graph(GraphArgs),
+
+    /// (alias <s>) Shows the outcome of every task of the latest run of the given flows, and what they left running
+    #[command(alias = "s")]
+    Status(StatusArgs),
+
+    /// Stops and removes the containers and networks that the given flows started
+    Down(DownArgs),
+
+    /// Shows the output of a task from the latest or the given run, or lists the runs that kept logs
+    Logs(LogsArgs),
+
+    /// Manages the named volumes of the given .runer file
+    Volumes(VolumesArgs),
+
     /// (alias <c>) Starts runer-cli
     #[command(alias = "c")]
     Cli,
@@ -27,4 +59,166 @@ pub struct RunArgs {
     /// .runer file to run
     #[arg(short, long)]
     pub file: Option<String>,
+
+    /// Name of the flow to run, can be repeated. Optional if the .runer file has a single flow
+    #[arg(long = "flow", value_name = "NAME")]
+    pub flows: Vec<String>,
+
+    /// Runs the given flows in parallel instead of one after another
+    #[arg(long)]
+    pub parallel: bool,
+
+    /// Prints the commands each task would execute, in dependency order, without running anything
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Container runtime to use for every blueprint, overriding the ones selected in the .runer file
+    #[arg(long, value_enum)]
+    pub runtime: Option<RuntimeKind>,
+
+    /// Prints the [task] prefixes of the task output without colors
+    #[arg(long)]
+    pub no_color: bool,
+
+    /// How long the running tasks get to exit once runer is interrupted, before they are killed
+    #[arg(long, value_name = "DURATION", default_value = "10s", value_parser = parse_grace_period)]
+    pub grace_period: Duration,
+
+    /// Stops the containers the flows started, in the reverse of their dependency order, once runer is interrupted
+    #[arg(long)]
+    pub stop_on_interrupt: bool,
+}
+
+fn parse_grace_period(value: &str) -> Result<Duration, String> {
+    parse_duration(value).ok_or_else(|| format!("invalid duration '{value}', expected e.g. 10s"))
+}
+
+#[derive(Args, Debug, PartialEq, Eq, Clone)]
+pub struct ValidateArgs {
+    /// .runer files to validate
+    pub files: Vec<String>,
+}
+
+#[derive(Args, Debug, PartialEq, Eq, Clone)]
+pub struct ListArgs {
+    /// .runer file to inspect
+    #[arg(short, long)]
+    pub file: Option<String>,
+
+    /// Prints the listing as JSON
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(Args, Debug, PartialEq, Eq, Clone)]
+pub struct GraphArgs {
+    /// .runer file to render
+    #[arg(short, long)]
+    pub file: Option<String>,
+
+    /// Name of the flow to render. Optional if the .runer file has a single flow
+    #[arg(long, value_name = "NAME")]
+    pub flow: Option<String>,
+
+    /// Output format
+    #[arg(long, value_enum, default_value_t = GraphFormat::Dot)]
+    pub format: GraphFormat,
+}
+
+#[derive(ValueEnum, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum GraphFormat {
+    Dot,
+    Mermaid,
+}
+
+#[derive(Args, Debug, PartialEq, Eq, Clone)]
+pub struct StatusArgs {
+    /// .runer file that declares the flows
+    #[arg(short, long)]
+    pub file: Option<String>,
+
+    /// Name of the flow to show, can be repeated. Optional if the .runer file has a single flow
+    #[arg(long = "flow", value_name = "NAME")]
+    pub flows: Vec<String>,
+}
+
+#[derive(Args, Debug, PartialEq, Eq, Clone)]
+pub struct DownArgs {
+    /// .runer file that declares the flows
+    #[arg(short, long)]
+    pub file: Option<String>,
+
+    /// Name of the flow to tear down, can be repeated. Optional if the .runer file has a single flow
+    #[arg(long = "flow", value_name = "NAME")]
+    pub flows: Vec<String>,
+
+    /// Also removes the named volumes that the containers mount
+    #[arg(long)]
+    pub volumes: bool,
+
+    /// Also removes the images that the flows build
+    #[arg(long)]
+    pub images: bool,
+
+    /// Container runtime to remove the named volumes with, overriding the ones selected in the .runer file. Everything else is removed with the runtime that created it
+    #[arg(long, value_enum)]
+    pub runtime: Option<RuntimeKind>,
+}
+
+#[derive(Args, Debug, PartialEq, Eq, Clone)]
+pub struct LogsArgs {
+    /// Name of the task whose output to show. Lists the runs and their task logs if omitted. Tasks with the same name, e.g. the image and the container job of a blueprint, share their log
+    pub task: Option<String>,
+
+    /// .runer file that declares the flows
+    #[arg(short, long)]
+    pub file: Option<String>,
+
+    /// Name of the run to show, as listed without a task. Defaults to the latest run of the task
+    #[arg(long, value_name = "RUN")]
+    pub run: Option<String>,
+
+    /// Shows only the last given number of lines
+    #[arg(short = 'n', long, value_name = "LINES")]
+    pub tail: Option<usize>,
+
+    /// Keeps showing the output as it gets appended, until interrupted
+    #[arg(long)]
+    pub follow: bool,
+}
+
+#[derive(Args, Debug, PartialEq, Eq, Clone)]
+pub struct VolumesArgs {
+    /// .runer file that declares the volumes
+    #[arg(short, long)]
+    pub file: Option<String>,
+
+    /// Container runtime to use, overriding the ones selected in the .runer file
+    #[arg(long, value_enum)]
+    pub runtime: Option<RuntimeKind>,
+
+    #[command(subcommand)]
+    pub action: VolumesAction,
+}
+
+#[derive(Debug, Subcommand, PartialEq, Eq, Clone)]
+pub enum VolumesAction {
+    /// Removes the volumes declared in the .runer file that no container uses
+    Prune,
+
+    /// Writes the content of a named volume into a local tar archive
+    Backup {
+        /// Name of the volume
+        volume: String,
+        /// Path of the tar archive to write
+        archive: PathBuf,
+    },
+
+    /// Replaces the content of a named volume with a local tar archive
+    Restore {
+        /// Name of the volume, created if it doesn't exist
+        volume: String,
+        /// Path of the tar archive to read
+        archive: PathBuf,
+    },
 }
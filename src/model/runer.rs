@@ -1,20 +1,94 @@
-use serde::Deserialize;
+use clap::ValueEnum;
+use serde::de::value::{MapAccessDeserializer, SeqAccessDeserializer};
+use serde::de::{Error, MapAccess, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer};
 use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Named environment variable lists, as declared in the _env_ Fragment.
+pub type EnvSets = HashMap<String, Vec<(String, String)>>;
 
 /// This is the main struct that a .runer file is deserialized into.
 /// Throughout the application, whenever Fragment keyword is used, it
 /// refers to the fields of this struct.
 ///
-/// At the current state, a Rune can have 3 fragments. And there could be
-/// only 1 instance of each.
+/// At the current state, a Rune can have 5 fragments. And there could be
+/// only 1 instance of each. _runtime_ selects the container engine that
+/// runs the container jobs of every Blueprint that doesn't select its own.
 #[derive(Default, Deserialize, Clone, Debug)]
 #[serde(deny_unknown_fields)]
 pub struct Rune {
+    pub runtime: Option<RuntimeKind>,
     pub blueprints: Option<HashMap<String, Blueprint>>,
-    pub env: Option<HashMap<String, Vec<(String, String)>>>,
+    pub env: Option<EnvSets>,
+    pub networks: Option<HashMap<String, Network>>,
+    pub volumes: Option<HashMap<String, Volume>>,
     pub flows: Option<Vec<Flow>>,
 }
 
+impl Rune {
+    /// Resolves the relative paths of the Rune against the given directory,
+    /// the directory of its .runer file, so that they don't depend on where
+    /// runer is started from.
+    pub fn resolve_paths(&mut self, dir: &Path) {
+        let containers = self
+            .blueprints
+            .iter_mut()
+            .flat_map(|blueprints| blueprints.values_mut())
+            .filter_map(|blueprint| blueprint.container.as_mut());
+        for container in containers {
+            for mount in container.volumes.iter_mut().flatten() {
+                mount.resolve(dir);
+            }
+        }
+    }
+}
+
+/// Container engines that runer can drive. _docker-api_ talks to the Docker
+/// daemon over its socket instead of spawning the docker CLI. _fake_
+/// simulates a container engine in memory, for running flows where no engine
+/// is available.
+#[derive(Deserialize, ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum RuntimeKind {
+    #[default]
+    Docker,
+    Podman,
+    #[serde(rename = "docker-api")]
+    DockerApi,
+    Fake,
+}
+
+/// A container network, as declared in the _networks_ Fragment. Networks are
+/// created with their declared settings the first time a container joins
+/// them, unless they already exist.
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Network {
+    /// bridge, overlay, etc. Defaults to the default driver of the runtime.
+    pub driver: Option<String>,
+    /// Internal networks are not reachable from outside the host.
+    #[serde(default)]
+    pub internal: bool,
+    /// Subnet in CIDR notation, e.g. 172.28.0.0/16
+    pub subnet: Option<String>,
+}
+
+/// A named volume, as declared in the _volumes_ Fragment. Volumes are created
+/// with their declared settings before the first container mounts them,
+/// unless they already exist.
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Volume {
+    /// Defaults to the default volume driver of the runtime.
+    pub driver: Option<String>,
+    #[serde(default)]
+    pub labels: HashMap<String, String>,
+}
+
 #[derive(Deserialize, Clone, Debug)]
 #[serde(deny_unknown_fields)]
 pub struct Image {
@@ -38,32 +112,601 @@ pub struct Container {
     pub name: String,
     pub image: String,
     pub options: Option<Vec<String>>,
-    pub ports: Option<(String, String)>,
+    #[serde(default, deserialize_with = "port_mappings")]
+    pub ports: Option<Vec<PortMapping>>,
     pub env: Option<Vec<(String, String)>>,
-    pub volumes: Option<Vec<(String, String)>>,
+    pub volumes: Option<Vec<Mount>>,
     pub entrypoint: Option<Vec<String>>,
     pub hc: Option<HealthCheck>,
+    pub networks: Option<Vec<NetworkAttachment>>,
+    pub ready: Option<Readiness>,
+}
+
+/// When a container Task counts as finished, so that its dependent Tasks can
+/// start. By default a container with a health check has to become healthy,
+/// and a container without one has to be running, within 2 minutes.
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Readiness {
+    pub condition: Option<ReadyCondition>,
+    /// How long to wait for the condition, e.g. `90s`
+    pub timeout: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ReadyCondition {
+    /// The health check of the container passes.
+    Healthy,
+    /// The container is running.
+    Running,
+}
+
+impl fmt::Display for ReadyCondition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReadyCondition::Healthy => write!(f, "healthy"),
+            ReadyCondition::Running => write!(f, "running"),
+        }
+    }
+}
+
+/// A port (range) of a Container published on the host.
+///
+/// Written either in the syntax of `docker run -p`, i.e.
+/// `[host_ip:][host_port:]container_port[/protocol]` where ports can be
+/// ranges (`8000-8010`), or spelled out
+/// (`- { host_ip: 127.0.0.1, host: 8080, container: 80, protocol: udp }`).
+/// Without a host port, the runtime picks a free one.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PortMapping {
+    pub host_ip: Option<IpAddr>,
+    pub host: Option<PortRange>,
+    pub container: PortRange,
+    pub protocol: Protocol,
+}
+
+/// An inclusive range of ports. A single port is a range of length 1.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PortRange {
+    pub start: u16,
+    pub end: u16,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    #[default]
+    Tcp,
+    Udp,
+}
+
+impl PortRange {
+    pub fn len(&self) -> u16 {
+        self.end - self.start + 1
+    }
+
+    pub fn ports(&self) -> impl Iterator<Item = u16> {
+        self.start..=self.end
+    }
+}
+
+impl FromStr for PortRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let port = |p: &str| match p.trim().parse::<u16>() {
+            Ok(0) | Err(_) => Err(format!("'{s}' is not a port or a port range")),
+            Ok(port) => Ok(port),
+        };
+        let range = match s.split_once('-') {
+            Some((start, end)) => Self {
+                start: port(start)?,
+                end: port(end)?,
+            },
+            None => {
+                let port = port(s)?;
+                Self {
+                    start: port,
+                    end: port,
+                }
+            }
+        };
+        if range.start > range.end {
+            return Err(format!("port range '{s}' ends before it starts"));
+        }
+        Ok(range)
+    }
+}
+
+impl fmt::Display for PortRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.start == self.end {
+            write!(f, "{}", self.start)
+        } else {
+            write!(f, "{}-{}", self.start, self.end)
+        }
+    }
+}
+
+impl fmt::Display for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Protocol::Tcp => write!(f, "tcp"),
+            Protocol::Udp => write!(f, "udp"),
+        }
+    }
+}
+
+impl PortMapping {
+    /// Checks that the host and container ranges can be mapped onto each
+    /// other.
+    fn new(
+        host_ip: Option<IpAddr>,
+        host: Option<PortRange>,
+        container: PortRange,
+        protocol: Protocol,
+    ) -> Result<Self, String> {
+        if let Some(host) = host {
+            if host.len() != container.len() {
+                return Err(format!(
+                    "host port range {host} and container port range {container} have different lengths"
+                ));
+            }
+        }
+        Ok(Self {
+            host_ip,
+            host,
+            container,
+            protocol,
+        })
+    }
+
+    /// Host port that the given container port is published on, if it is
+    /// pinned.
+    pub fn host_port(&self, container_port: u16) -> Option<u16> {
+        self.host
+            .map(|host| host.start + (container_port - self.container.start))
+    }
+}
+
+impl FromStr for PortMapping {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (mapping, protocol) = match s.rsplit_once('/') {
+            Some((mapping, protocol)) => match protocol {
+                "tcp" => (mapping, Protocol::Tcp),
+                "udp" => (mapping, Protocol::Udp),
+                _ => {
+                    return Err(format!(
+                        "unknown protocol '{protocol}', expected tcp or udp"
+                    ))
+                }
+            },
+            None => (s, Protocol::Tcp),
+        };
+        // IPv6 host addresses are enclosed in brackets
+        let (host_ip, ports) = match mapping.strip_prefix('[') {
+            Some(rest) => {
+                let (ip, ports) = rest
+                    .split_once("]:")
+                    .ok_or_else(|| format!("'{s}' is not a valid port mapping"))?;
+                (Some(ip), ports)
+            }
+            None => match mapping.matches(':').count() {
+                2 => {
+                    let (ip, ports) = mapping.split_once(':').unwrap();
+                    (Some(ip), ports)
+                }
+                0 | 1 => (None, mapping),
+                _ => return Err(format!("'{s}' is not a valid port mapping")),
+            },
+        };
+        let host_ip = host_ip
+            .map(|ip| {
+                ip.parse::<IpAddr>()
+                    .map_err(|_| format!("'{ip}' is not an IP address"))
+            })
+            .transpose()?;
+        let (host, container) = match ports.split_once(':') {
+            Some(("", container)) => (None, container),
+            Some((host, container)) => (Some(host.parse()?), container),
+            None => (None, ports),
+        };
+        Self::new(host_ip, host, container.parse()?, protocol)
+    }
+}
+
+/// Renders the mapping in the syntax of `docker run -p`.
+impl fmt::Display for PortMapping {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.host_ip {
+            Some(IpAddr::V6(ip)) => write!(f, "[{ip}]:")?,
+            Some(IpAddr::V4(ip)) => write!(f, "{ip}:")?,
+            None => {}
+        }
+        if let Some(host) = self.host {
+            write!(f, "{host}:")?;
+        } else if self.host_ip.is_some() {
+            write!(f, ":")?;
+        }
+        write!(f, "{}/{}", self.container, self.protocol)
+    }
 }
 
+/// Deserializes the <ports> of a Container. Every entry is a mapping of its
+/// own, the `[host, container]` pair of older runes is written
+/// `"host:container"` instead.
+///
+/// A list of exactly two bare ports is rejected, since an older rune means
+/// a host and a container port by it. Publishing the two container ports is
+/// spelled with their protocol, e.g. `["80/tcp", "443/tcp"]`.
+fn port_mappings<'de, D>(deserializer: D) -> Result<Option<Vec<PortMapping>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Port {
+        Number(u16),
+        Text(String),
+    }
+
+    impl Port {
+        fn text(&self) -> String {
+            match self {
+                Port::Number(port) => port.to_string(),
+                Port::Text(text) => text.clone(),
+            }
+        }
+    }
+
+    enum Entry {
+        Short(Port),
+        Long(LongEntry),
+    }
+
+    /// Unknown keys are rejected, so that a misspelled one isn't silently
+    /// dropped.
+    #[derive(Deserialize)]
+    #[serde(deny_unknown_fields)]
+    struct LongEntry {
+        host_ip: Option<String>,
+        host: Option<Port>,
+        container: Port,
+        #[serde(default)]
+        protocol: Protocol,
+    }
+
+    /// Tells the two forms apart by their yaml type, like [MountDef].
+    impl<'de> Deserialize<'de> for Entry {
+        fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            struct EntryVisitor;
+
+            impl<'de> Visitor<'de> for EntryVisitor {
+                type Value = Entry;
+
+                fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                    write!(f, "a port mapping or a port with its host and protocol")
+                }
+
+                fn visit_u64<E: Error>(self, port: u64) -> Result<Entry, E> {
+                    Ok(Entry::Short(Port::Text(port.to_string())))
+                }
+
+                fn visit_str<E: Error>(self, text: &str) -> Result<Entry, E> {
+                    Ok(Entry::Short(Port::Text(text.to_owned())))
+                }
+
+                fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Entry, A::Error> {
+                    LongEntry::deserialize(MapAccessDeserializer::new(map)).map(Entry::Long)
+                }
+            }
+
+            deserializer.deserialize_any(EntryVisitor)
+        }
+    }
+
+    let entries = match Option::<Vec<Entry>>::deserialize(deserializer)? {
+        Some(entries) => entries,
+        None => return Ok(None),
+    };
+
+    if let [Entry::Short(host), Entry::Short(container)] = entries.as_slice() {
+        let (host, container) = (host.text(), container.text());
+        let bare = |port: &str| port.chars().all(|c| c.is_ascii_digit());
+        if bare(&host) && bare(&container) {
+            return Err(D::Error::custom(format!(
+                "ports are no longer given as a [host, container] pair, write \
+                 [\"{host}:{container}\"] to map host port {host} to container port \
+                 {container}, or [\"{host}/tcp\", \"{container}/tcp\"] to publish both \
+                 container ports"
+            )));
+        }
+    }
+
+    entries
+        .into_iter()
+        .map(|entry| match entry {
+            Entry::Short(port) => port.text().parse(),
+            Entry::Long(LongEntry {
+                host_ip,
+                host,
+                container,
+                protocol,
+            }) => {
+                let host_ip = host_ip
+                    .map(|ip| {
+                        ip.parse()
+                            .map_err(|_| format!("'{ip}' is not an IP address"))
+                    })
+                    .transpose()?;
+                let host = host.map(|host| host.text().parse()).transpose()?;
+                PortMapping::new(host_ip, host, container.text().parse()?, protocol)
+            }
+        })
+        .collect::<Result<Vec<_>, String>>()
+        .map(Some)
+        .map_err(D::Error::custom)
+}
+
+/// A filesystem mounted into a Container. It is either given as a
+/// `[source, target]` pair, or with its type and options spelled out
+/// (`- { type: bind, source: ./conf, target: /etc/app, read_only: true }`).
+///
+/// When the type is omitted, sources that look like a path (`/`, `./`, `../`
+/// or `~/`) are bind mounts and the rest are named volumes.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(from = "MountDef")]
+pub struct Mount {
+    pub typ: MountType,
+    /// Host path of a bind mount, or name of a named volume. tmpfs mounts
+    /// have no source.
+    pub source: Option<String>,
+    pub target: String,
+    pub read_only: bool,
+}
+
+impl Mount {
+    /// Absolute host path of a bind mount, since container engines only
+    /// accept absolute paths. `~/` is expanded to the home directory. Sources
+    /// are relative to the .runer file once [Rune::resolve_paths] is called,
+    /// and to the working directory otherwise.
+    pub fn host_path(&self) -> Option<PathBuf> {
+        if self.typ != MountType::Bind {
+            return None;
+        }
+        let source = self.source.as_deref()?;
+        let path = match source.strip_prefix("~/") {
+            Some(rest) => PathBuf::from(std::env::var("HOME").unwrap_or_default()).join(rest),
+            None => PathBuf::from(source),
+        };
+        let path = if path.is_absolute() {
+            path
+        } else {
+            std::env::current_dir().ok()?.join(path)
+        };
+        // Drops the `.` components
+        Some(path.components().collect())
+    }
+
+    /// Makes the relative source of a bind mount relative to the given
+    /// directory instead.
+    fn resolve(&mut self, dir: &Path) {
+        if self.typ != MountType::Bind {
+            return;
+        }
+        let Some(source) = self.source.as_mut() else {
+            return;
+        };
+        if !source.starts_with("~/") && Path::new(source.as_str()).is_relative() {
+            // Drops the `.` components
+            let path: PathBuf = dir.join(source.as_str()).components().collect();
+            *source = path.display().to_string();
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MountType {
+    Bind,
+    Volume,
+    Tmpfs,
+}
+
+impl fmt::Display for MountType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MountType::Bind => write!(f, "bind"),
+            MountType::Volume => write!(f, "volume"),
+            MountType::Tmpfs => write!(f, "tmpfs"),
+        }
+    }
+}
+
+enum MountDef {
+    Pair(String, String),
+    Full(MountSpec),
+}
+
+/// The spelled out form of a Mount. Unknown keys are rejected, a misspelled
+/// `read_only` would otherwise leave the mount writable.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MountSpec {
+    #[serde(rename = "type")]
+    typ: Option<MountType>,
+    source: Option<String>,
+    target: String,
+    #[serde(default)]
+    read_only: bool,
+}
+
+/// Tells the two forms apart by their yaml type rather than trying both like
+/// an untagged enum, so that the errors of the spelled out form (e.g. an
+/// unknown key) are reported as they are.
+impl<'de> Deserialize<'de> for MountDef {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct MountVisitor;
+
+        impl<'de> Visitor<'de> for MountVisitor {
+            type Value = MountDef;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(
+                    f,
+                    "a [source, target] pair or a mount with its type and options"
+                )
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<MountDef, A::Error> {
+                let (source, target) = Deserialize::deserialize(SeqAccessDeserializer::new(seq))?;
+                Ok(MountDef::Pair(source, target))
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<MountDef, A::Error> {
+                MountSpec::deserialize(MapAccessDeserializer::new(map)).map(MountDef::Full)
+            }
+        }
+
+        deserializer.deserialize_any(MountVisitor)
+    }
+}
+
+impl From<MountDef> for Mount {
+    fn from(def: MountDef) -> Self {
+        let infer = |source: &Option<String>| match source {
+            Some(s) if ["/", "./", "../", "~/"].iter().any(|p| s.starts_with(p)) || s == "." => {
+                MountType::Bind
+            }
+            Some(_) => MountType::Volume,
+            None => MountType::Tmpfs,
+        };
+        match def {
+            MountDef::Pair(source, target) => Self {
+                typ: infer(&Some(source.clone())),
+                source: Some(source),
+                target,
+                read_only: false,
+            },
+            MountDef::Full(MountSpec {
+                typ,
+                source,
+                target,
+                read_only,
+            }) => Self {
+                typ: typ.unwrap_or_else(|| infer(&source)),
+                source,
+                target,
+                read_only,
+            },
+        }
+    }
+}
+
+/// A network that a Container joins, either given as a plain network name
+/// (`- backend`) or together with the aliases of the container in that
+/// network (`- { name: backend, aliases: [db] }`).
+#[derive(Deserialize, Clone, Debug)]
+#[serde(from = "NetworkAttachmentDef")]
+pub struct NetworkAttachment {
+    pub name: String,
+    pub aliases: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum NetworkAttachmentDef {
+    Name(String),
+    Full {
+        name: String,
+        #[serde(default)]
+        aliases: Vec<String>,
+    },
+}
+
+impl From<NetworkAttachmentDef> for NetworkAttachment {
+    fn from(def: NetworkAttachmentDef) -> Self {
+        match def {
+            NetworkAttachmentDef::Name(name) => Self {
+                name,
+                aliases: vec![],
+            },
+            NetworkAttachmentDef::Full { name, aliases } => Self { name, aliases },
+        }
+    }
+}
+
+/// How to tell whether a Task is healthy. Exactly one kind of check is
+/// given: a shell `command`, or one of the built-in `tcp`, `http` and `log`
+/// probes.
+///
+/// Only `Container` commands are run by the container runtime, every other
+/// check is run by runer itself on the host. Built-in probes are retried
+/// until the readiness timeout unless `retries` is given.
 #[derive(Deserialize, Clone, Debug)]
 #[serde(deny_unknown_fields)]
 pub struct HealthCheck {
-    pub command: (ExecutionEnvironment, String),
+    pub command: Option<(ExecutionEnvironment, String)>,
+    /// Address that accepts TCP connections once healthy, e.g. `localhost:5432`
+    pub tcp: Option<String>,
+    pub http: Option<HttpProbe>,
+    /// Regular expression that a line of the output of the Task matches once
+    /// healthy
+    pub log: Option<String>,
     pub interval: Option<String>,
     pub retries: Option<u32>,
 }
 
+impl HealthCheck {
+    /// The command of the health check if the container runtime runs it
+    /// inside the container.
+    pub fn container_command(&self) -> Option<&str> {
+        match &self.command {
+            Some((ExecutionEnvironment::Container, command)) => Some(command),
+            _ => None,
+        }
+    }
+}
+
+/// An HTTP GET probe. By default any 2xx status counts as healthy.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct HttpProbe {
+    /// e.g. `http://localhost:8081/health`
+    pub url: String,
+    /// Expected status code
+    pub status: Option<u16>,
+    /// Substring that the response body contains once healthy
+    pub body: Option<String>,
+}
+
 #[derive(Deserialize, Clone, Debug)]
 #[serde(deny_unknown_fields)]
 pub struct Shell {
     pub commands: Vec<String>,
     pub env: Option<Vec<(String, String)>>,
+    /// If given, the Task is finished once the check passes while its
+    /// commands keep running, e.g. for a development server.
+    pub hc: Option<HealthCheck>,
+    /// How long to wait for the health check to pass, e.g. `90s`. The same
+    /// 2 minutes as for containers by default.
+    pub timeout: Option<String>,
 }
 
 #[derive(Deserialize, Clone, Debug)]
 #[serde(deny_unknown_fields)]
 pub struct Blueprint {
     pub _env: Option<Vec<(String, String)>>,
+    pub runtime: Option<RuntimeKind>,
     pub image: Option<Image>,
     pub container: Option<Container>,
     pub shell: Option<Shell>,
@@ -85,7 +728,30 @@ pub struct Task {
     pub typ: TaskType,
     pub name: String,
     pub job: JobType,
-    pub depends: Option<u32>,
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub depends: Vec<u32>,
+    /// Hides the output of the Task, e.g. for noisy builds
+    #[serde(default)]
+    pub quiet: bool,
+}
+
+/// Allows a Task to declare a single dependency as a plain ID
+/// (`depends: 0`) next to the list form (`depends: [0, 1]`).
+fn one_or_many<'de, D>(deserializer: D) -> Result<Vec<u32>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(u32),
+        Many(Vec<u32>),
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(id) => vec![id],
+        OneOrMany::Many(ids) => ids,
+    })
 }
 
 #[derive(Deserialize, Clone, Debug)]
@@ -94,6 +760,15 @@ pub enum TaskType {
     Env,
 }
 
+impl fmt::Display for TaskType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TaskType::Blueprint => write!(f, "Blueprint"),
+            TaskType::Env => write!(f, "Env"),
+        }
+    }
+}
+
 #[derive(Deserialize, Clone, Debug)]
 #[serde(rename_all = "lowercase")]
 pub enum JobType {
@@ -102,3 +777,79 @@ pub enum JobType {
     Shell,
     Set,
 }
+
+impl fmt::Display for JobType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JobType::Container => write!(f, "container"),
+            JobType::Image => write!(f, "image"),
+            JobType::Shell => write!(f, "shell"),
+            JobType::Set => write!(f, "set"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn container(yaml: &str) -> Result<Container, serde_yaml::Error> {
+        serde_yaml::from_str(&format!("{{ name: app, image: app, {yaml} }}"))
+    }
+
+    #[test]
+    fn every_listed_port_is_a_mapping_of_its_own() {
+        let ports =
+            container(r#"ports: ["80", 443, "8081:8081", { container: 53, protocol: udp }]"#)
+                .unwrap()
+                .ports
+                .unwrap();
+        let ports: Vec<String> = ports.iter().map(PortMapping::to_string).collect();
+        assert_eq!(ports, ["80/tcp", "443/tcp", "8081:8081/tcp", "53/udp"]);
+    }
+
+    #[test]
+    fn the_pair_of_older_runes_is_rejected() {
+        let error = container(r#"ports: ["8080", 80]"#).unwrap_err();
+        assert!(error.to_string().starts_with(
+            "ports are no longer given as a [host, container] pair, write [\"8080:80\"] to map \
+             host port 8080 to container port 80"
+        ));
+        let ports = container(r#"ports: ["8080/tcp", "80/tcp"]"#)
+            .unwrap()
+            .ports
+            .unwrap();
+        assert_eq!(ports.len(), 2);
+    }
+
+    #[test]
+    fn misspelled_mount_options_are_rejected() {
+        let error =
+            container("volumes: [{ source: ./conf, target: /conf, readonly: true }]").unwrap_err();
+        assert!(error.to_string().contains("unknown field `readonly`"));
+        let mounts = container("volumes: [[./conf, /conf], { target: /tmp }]")
+            .unwrap()
+            .volumes
+            .unwrap();
+        assert_eq!(mounts[0].typ, MountType::Bind);
+        assert_eq!(mounts[1].typ, MountType::Tmpfs);
+    }
+
+    #[test]
+    fn bind_sources_are_relative_to_the_rune() {
+        let mut rune: Rune = serde_yaml::from_str(
+            "blueprints: { app: { container: { name: app, image: app, volumes: [
+                [./conf, /conf], [/abs, /abs], [data, /data]] } } }",
+        )
+        .unwrap();
+        rune.resolve_paths(Path::new("other/dir"));
+        let mounts = rune.blueprints.unwrap()["app"]
+            .container
+            .clone()
+            .unwrap()
+            .volumes
+            .unwrap();
+        let sources: Vec<&str> = mounts.iter().filter_map(|m| m.source.as_deref()).collect();
+        assert_eq!(sources, ["other/dir/conf", "/abs", "data"]);
+    }
+}
@@ -1,10 +1,26 @@
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
 use clap::Parser;
-use log::{error, info};
+use log::{info, warn};
 
+use crate::engine::down::{down_flow, DownOptions};
+use crate::engine::dry_run::describe_flow;
+use crate::engine::error::{error_kind, ErrorKind, RunerError};
 use crate::engine::extractor::*;
-use crate::model::commandline::{Cli, Mode};
+use crate::engine::graph::{to_dot, to_mermaid};
+use crate::engine::listing::list_rune;
+use crate::engine::output::init_color;
+use crate::engine::run_log::{
+    find_run, find_task_log, list_runs, list_task_logs, show_log, RunLog,
+};
+use crate::engine::run_state::StateFile;
+use crate::engine::shutdown::ShutdownOptions;
+use crate::engine::status::describe_status;
+use crate::engine::volumes::{backup_volume, prune_volumes, restore_volume};
+use crate::model::commandline::{Cli, GraphFormat, Mode, VolumesAction};
 
-use crate::engine::executor::execute_flow;
+use crate::engine::executor::execute_flows;
 use crate::engine::state::State;
 
 pub fn parse_cmdline_args() -> Cli {
@@ -15,23 +31,171 @@ pub fn initialize_logger() {
     env_logger::init();
 }
 
-pub fn handle_mod(mode: Mode) {
+/// Carries out the given mode.
+///
+/// Returns error if it fails, which decides the exit code of runer, see
+/// [error_kind].
+pub fn handle_mod(mode: Mode) -> Result<()> {
     match mode {
         Mode::Run(args) => {
-            let rune = extract_rune(&args.file.unwrap_or_else(|| ".runer".to_owned()))
-                .map_err(|e| error!("{e}"))
-                .unwrap();
+            let file = args.file.unwrap_or_else(|| ".runer".to_owned());
+            let rune = load_rune(&file)?;
 
             analyze_fragments(&rune);
 
-            let state = State::default().from_rune(rune);
+            let mut state = State::from_rune(rune);
+            if let Some(runtime) = args.runtime {
+                state = state.with_runtime(runtime);
+            }
+
+            let flow_names = state.select_flows(&args.flows)?;
 
-            smol::block_on(execute_flow(0, state))
-                .map_err(|e| error!("{e}"))
-                .unwrap();
+            if args.dry_run {
+                for name in &flow_names {
+                    let description = describe_flow(name, &state)?;
+                    println!("{description}");
+                }
+                return Ok(());
+            }
+
+            let state_file = StateFile::for_rune(Path::new(&file))?;
+            let mut state = state.with_state_file(state_file);
+            let runs_dir = state.state_file.as_ref().unwrap().runs_dir();
+            match RunLog::create(&runs_dir) {
+                Ok(run_log) => {
+                    info!(
+                        "Keeping the output of the tasks in {}",
+                        run_log.dir().display()
+                    );
+                    state = state.with_run_log(run_log);
+                }
+                Err(e) => warn!("Output of the tasks won't be kept: {e:#}"),
+            }
+            init_color(args.no_color);
+            let shutdown = ShutdownOptions {
+                grace_period: args.grace_period,
+                stop_containers: args.stop_on_interrupt,
+            };
+            smol::block_on(execute_flows(flow_names, state, args.parallel, shutdown))?;
             // let duration = start.elapsed();
             // info!("Time elapsed: {:?}", duration);
         }
+        Mode::Validate(args) => {
+            let files = if args.files.is_empty() {
+                find_rune_files(".")
+            } else {
+                args.files
+            };
+            validate_files(&files)?;
+        }
+        Mode::List(args) => {
+            let rune = extract_rune(&args.file.unwrap_or_else(|| ".runer".to_owned()))?;
+
+            let listing = list_rune(&rune, args.json)?;
+            print!("{listing}");
+        }
+        Mode::Graph(args) => {
+            let rune = load_rune(&args.file.unwrap_or_else(|| ".runer".to_owned()))?;
+
+            let state = State::from_rune(rune);
+
+            let flow_names = state.select_flows(&args.flow.into_iter().collect::<Vec<String>>())?;
+            let flow = state.find_flow(&flow_names[0])?;
+
+            let rendered = match args.format {
+                GraphFormat::Dot => to_dot(flow),
+                GraphFormat::Mermaid => to_mermaid(flow),
+            }?;
+            print!("{rendered}");
+        }
+        Mode::Status(args) => {
+            let file = args.file.unwrap_or_else(|| ".runer".to_owned());
+            let rune = load_rune(&file)?;
+            let state_file = StateFile::for_rune(Path::new(&file))?;
+
+            let state = State::from_rune(rune);
+
+            let flow_names = state.select_flows(&args.flows)?;
+            for name in &flow_names {
+                let status = smol::block_on(describe_status(name, &state, &state_file))?;
+                print!("{status}");
+            }
+        }
+        Mode::Down(args) => {
+            let file = args.file.unwrap_or_else(|| ".runer".to_owned());
+            let rune = load_rune(&file)?;
+            let state_file = StateFile::for_rune(Path::new(&file))?;
+
+            let mut state = State::from_rune(rune).with_state_file(state_file);
+            if let Some(runtime) = args.runtime {
+                state = state.with_runtime(runtime);
+            }
+
+            let flow_names = state.select_flows(&args.flows)?;
+            let options = DownOptions {
+                volumes: args.volumes,
+                images: args.images,
+            };
+            // Flows are torn down in the reverse order they are run
+            for name in flow_names.iter().rev() {
+                let lines = smol::block_on(down_flow(name, &state, options))?;
+                for line in lines {
+                    println!("{line}");
+                }
+            }
+        }
+        Mode::Logs(args) => {
+            let file = args.file.unwrap_or_else(|| ".runer".to_owned());
+            let runs_dir = StateFile::for_rune(Path::new(&file))?.runs_dir();
+
+            match args.task {
+                Some(task) => {
+                    let log = find_task_log(&runs_dir, args.run.as_deref(), &task)?;
+                    smol::block_on(show_log(&log, args.tail, args.follow))?;
+                }
+                None => {
+                    let runs = match args.run {
+                        Some(run) => vec![find_run(&runs_dir, Some(&run))?],
+                        None => list_runs(&runs_dir)?
+                            .into_iter()
+                            .map(|run| runs_dir.join(run))
+                            .collect(),
+                    };
+                    for run in runs {
+                        let tasks = list_task_logs(&run)?;
+                        println!(
+                            "{}  {}",
+                            run.file_name().unwrap_or_default().to_string_lossy(),
+                            tasks.join(", ")
+                        );
+                    }
+                }
+            }
+        }
+        Mode::Volumes(args) => {
+            let rune = load_rune(&args.file.unwrap_or_else(|| ".runer".to_owned()))?;
+
+            let mut state = State::from_rune(rune);
+            if let Some(runtime) = args.runtime {
+                state = state.with_runtime(runtime);
+            }
+
+            match args.action {
+                VolumesAction::Prune => {
+                    for line in smol::block_on(prune_volumes(&state)) {
+                        println!("{line}");
+                    }
+                }
+                VolumesAction::Backup { volume, archive } => {
+                    smol::block_on(backup_volume(&state, &volume, &archive))?;
+                    println!("{volume} -> {}", archive.display());
+                }
+                VolumesAction::Restore { volume, archive } => {
+                    smol::block_on(restore_volume(&state, &volume, &archive))?;
+                    println!("{} -> {volume}", archive.display());
+                }
+            }
+        }
         Mode::Cli => {
             info!("Mode is 'c' which stands for CLI. <Not Implemented>");
         }
@@ -39,4 +203,56 @@ pub fn handle_mod(mode: Mode) {
             info!("Mode is 'd' whic stands for Desktop. <Not Implemented>");
         }
     }
+    Ok(())
+}
+
+/// Validates each given .runer file and prints every diagnostic found.
+///
+/// Returns [RunerError::InvalidFiles] if any of the files has at least one
+/// error, of the parse kind if any of them can't even be parsed.
+fn validate_files(files: &[String]) -> Result<()> {
+    if files.is_empty() {
+        return Err(anyhow!("No .runer file found to validate"));
+    }
+    let mut failure = None;
+    let mut invalid = 0;
+    for file in files {
+        match load_rune(file) {
+            Ok(_) => println!("{file}: ok"),
+            Err(e) => {
+                invalid += 1;
+                if failure != Some(ErrorKind::Parse) {
+                    failure = Some(error_kind(&e));
+                }
+                match e.downcast_ref::<RunerError>() {
+                    Some(RunerError::Parse(diagnostics) | RunerError::Validation(diagnostics)) => {
+                        eprintln!("{diagnostics}\n");
+                        eprintln!("{file}: {} error(s)", diagnostics.0.len());
+                    }
+                    _ => eprintln!("error: {e:#}\n\n{file}: 1 error(s)"),
+                }
+            }
+        }
+    }
+    match failure {
+        Some(kind) => Err(RunerError::InvalidFiles(kind, invalid).into()),
+        None => Ok(()),
+    }
+}
+
+/// Returns the .runer files residing directly in the given directory, in
+/// alphabetical order.
+fn find_rune_files(dir: &str) -> Vec<String> {
+    let mut files: Vec<String> = std::fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| entry.path().is_file())
+                .filter_map(|entry| entry.file_name().into_string().ok())
+                .filter(|name| name.ends_with(".runer"))
+                .collect()
+        })
+        .unwrap_or_default();
+    files.sort();
+    files
 }